@@ -0,0 +1,2459 @@
+// ================== Battle Rules ==================
+// 戦闘ルール本体（ステータス・敵行動・1ターン分の解決処理）。画面や入力には依存しない
+use std::sync::Arc;
+
+use bevy::prelude::*;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+// 敵のブレイク値（0以上）
+#[derive(Component, Clone)]
+pub struct BreakValue {
+    pub current: i32,
+}
+// 敵のブレイク状態（残りターン数）
+#[derive(Component, Clone)]
+pub struct BreakState {
+    pub remaining_turns: u32, // 0なら非ブレイク
+}
+// ブレイク自然回復の現在量（ターンごとに倍増: 1,2,4,...）
+#[derive(Component, Clone)]
+pub struct BreakRegen {
+    pub amount: i32, // 最小1
+}
+#[derive(Component, Clone)]
+pub struct Hp {
+    pub current: i32,
+    pub max: i32,
+}
+#[derive(Component, Clone)]
+pub struct Attack(pub i32);
+
+#[derive(Component, Clone)]
+pub struct Stamina {
+    pub current: i32,
+    pub max: i32,
+}
+
+// 連続コマンドの最大選択数（プレイヤーのステータス）
+#[derive(Component, Clone)]
+pub struct BatchLimit {
+    pub base: usize, // 基本値
+}
+// 選択数の上限（モメンタム段階・オーバードライブ込みでもこれを超えない）
+pub const MAX_BATCH_LIMIT: usize = 6;
+impl BatchLimit {
+    // 現在の最大選択数: 基本値 + モメンタム段階(50ごとに+1) + オーバードライブ(準備完了/発動中 +1)。
+    // バッチの途中では使わず、確定時に ConsecutiveBatch::limit へ固定した値を使う
+    pub fn current(&self, momentum: &Momentum, overdrive: &Overdrive) -> usize {
+        let tier = (momentum.current / 50).max(0) as usize;
+        let od = if overdrive.is_ready() || overdrive.active {
+            1
+        } else {
+            0
+        };
+        (self.base + tier + od).min(MAX_BATCH_LIMIT)
+    }
+}
+
+#[derive(Resource, PartialEq, Eq, Clone)]
+pub enum BattlePhase {
+    AwaitCommand,
+    // 連続コマンドの次コマンドを実行するか確認するフェーズ
+    ConfirmQueued,
+    InBattle,
+    Finished,
+}
+#[derive(Resource, Clone)]
+pub struct Turn(pub u32);
+
+#[derive(Resource, Clone)]
+pub struct CombatLog(pub Vec<String>);
+
+// モメンタム（最大100）
+#[derive(Resource, Default, Clone)]
+pub struct Momentum {
+    pub current: i32,
+}
+
+// 敵ダメージポップアップ用リソース（タイマー制御）
+#[derive(Resource, Default, Clone)]
+pub struct EnemyDamagePopup {
+    pub amount: i32,
+    pub timer: f32, // 秒。0以下で非表示
+}
+
+// オーバードライブゲージ（被ダメージで上昇、ターン経過で徐々に減少）
+// 満タンで次に確定するバッチがオーバードライブ: 選択数+1、そのバッチの最初の1ターンは消費スタミナ0
+#[derive(Resource, Default, Clone)]
+pub struct Overdrive {
+    pub gauge: i32,   // 0..=OVERDRIVE_MAX
+    pub active: bool, // オーバードライブ中か（発動したバッチの最初のターンだけ）
+}
+pub const OVERDRIVE_MAX: i32 = 100;
+impl Overdrive {
+    pub fn is_ready(&self) -> bool {
+        !self.active && self.gauge >= OVERDRIVE_MAX
+    }
+}
+
+// 奥義演出の発動要求（解決処理で立て、バナー生成システムで消費）
+#[derive(Resource, Default, Clone)]
+pub struct FinisherCinematic(pub bool);
+
+// 連続コマンド実行バッチの総件数（選択確定時に設定）
+#[derive(Resource, Default, Clone)]
+pub struct ConsecutiveBatch {
+    pub total: usize,    // このバッチの総選択数
+    pub limit: usize, // このバッチの最大選択数（確定時に固定。途中のモメンタム・オーバードライブでは変わらない）
+    pub executed: usize, // このバッチで既に実行した数
+    pub cursor: usize, // 確認フェーズでの編集カーソル（予約キュー内の位置）
+    pub edited: bool, // 途中で予約を置換/挿入/入れ替えしたか（以降のモメンタム増加は半減）
+}
+impl ConsecutiveBatch {
+    // 予約キューの offset 番目を実行したときのモメンタム増加量（編集済みなら半減）
+    pub fn gain_at(&self, offset: usize) -> i32 {
+        let gain = momentum_gain_for(self.executed + 1 + offset);
+        if self.edited { gain / 2 } else { gain }
+    }
+}
+
+// バッチ内 position 件目（1始まり）を実行したときのモメンタム増加量
+// 1件目: 0 / 2件目: +15 / 3件目: +25 / 以降10ずつ増加
+pub fn momentum_gain_for(position: usize) -> i32 {
+    if position <= 1 {
+        0
+    } else {
+        15 + 10 * (position as i32 - 2)
+    }
+}
+
+// 強化を使ったときの残りターン
+pub const BUFF_TURNS: u32 = 11;
+
+// コマンド強化の残りターン
+#[derive(Resource, Default, Clone)]
+pub struct CommandBuffs {
+    pub attack: u32,
+    pub skill: u32,
+    pub heal: u32,
+    pub defend: u32,
+}
+
+// 次の敵攻撃を無効化する防御フラグ
+#[derive(Resource, Default, Clone)]
+pub struct DefendNextAttack(pub bool);
+
+// 防御後の次プレイヤー行動に対するガードカウンター猶予
+#[derive(Resource, Default, Clone)]
+pub struct GuardCounterReady(pub bool);
+
+// 敵の行動種別（事前決定）
+#[derive(Clone, Copy)]
+pub enum EnemyAction {
+    Attack,
+    Wait,
+    Heal,
+    ChargeStart,
+    ChargeHit,
+}
+
+#[derive(Clone)]
+pub struct ActionProcess {
+    pub action: Arc<Action>,
+    pub next_step_index: usize,
+}
+impl ActionProcess {
+    pub fn from(action: &Arc<Action>) -> Self {
+        ActionProcess {
+            action: Arc::clone(action),
+            next_step_index: 0,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.next_step_index >= self.action.steps.len()
+    }
+
+    pub fn current_step(&self) -> Option<&ActionStep> {
+        if self.is_finished() {
+            None
+        } else {
+            Some(&self.action.steps[self.next_step_index])
+        }
+    }
+
+    pub fn advance(&mut self) -> Option<&ActionStep> {
+        self.next_step_index += 1;
+        if self.is_finished() {
+            None
+        } else {
+            let step = &self.action.steps[self.next_step_index];
+            Some(step)
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Action {
+    pub name: &'static str, // 行動全体の名前（集計・表示用）
+    pub steps: Vec<ActionStep>,
+}
+
+#[derive(Clone, Copy)]
+pub struct ActionStep {
+    pub name: &'static str,
+    pub specification: ActionStepSpecificationEnum,
+}
+
+#[derive(Clone, Copy)]
+pub enum ActionStepSpecificationEnum {
+    Attack(ActionStepSpecificationAttack),
+    Wait(ActionStepSpecificationWait),
+    Heal(ActionStepSpecificationHeal),
+}
+#[derive(Clone, Copy)]
+pub struct ActionStepSpecificationAttack {
+    pub power: f32,
+}
+#[derive(Clone, Copy)]
+pub struct ActionStepSpecificationWait {
+    pub invincible: bool,
+}
+#[derive(Clone, Copy)]
+pub struct ActionStepSpecificationHeal {
+    pub amount: i32,
+}
+
+pub fn create_enemy_attack() -> Action {
+    Action {
+        name: "爪攻撃",
+        steps: vec![ActionStep {
+            name: "爪攻撃",
+            specification: ActionStepSpecificationEnum::Attack(ActionStepSpecificationAttack {
+                power: 1.0,
+            }),
+        }],
+    }
+}
+pub fn create_enemy_claw_strong() -> Action {
+    Action {
+        name: "強力な爪攻撃",
+        steps: vec![
+            ActionStep {
+                name: "強力な爪攻撃",
+                specification: ActionStepSpecificationEnum::Attack(ActionStepSpecificationAttack {
+                    power: 2.0,
+                }),
+            },
+            ActionStep {
+                name: "体勢を立て直す",
+                specification: ActionStepSpecificationEnum::Wait(ActionStepSpecificationWait {
+                    invincible: false,
+                }),
+            },
+        ],
+    }
+}
+pub fn create_enemy_claw_combo() -> Action {
+    Action {
+        name: "爪連撃",
+        steps: vec![
+            ActionStep {
+                name: "爪連撃(1)",
+                specification: ActionStepSpecificationEnum::Attack(ActionStepSpecificationAttack {
+                    power: 0.7,
+                }),
+            },
+            ActionStep {
+                name: "爪連撃(2)",
+                specification: ActionStepSpecificationEnum::Attack(ActionStepSpecificationAttack {
+                    power: 0.7,
+                }),
+            },
+            ActionStep {
+                name: "待機",
+                specification: ActionStepSpecificationEnum::Wait(ActionStepSpecificationWait {
+                    invincible: false,
+                }),
+            },
+        ],
+    }
+}
+pub fn create_enemy_claw_combo_strong() -> Action {
+    Action {
+        name: "強爪連撃",
+        steps: vec![
+            ActionStep {
+                name: "爪連撃(1)",
+                specification: ActionStepSpecificationEnum::Attack(ActionStepSpecificationAttack {
+                    power: 0.8,
+                }),
+            },
+            ActionStep {
+                name: "爪連撃(2)",
+                specification: ActionStepSpecificationEnum::Attack(ActionStepSpecificationAttack {
+                    power: 0.8,
+                }),
+            },
+            ActionStep {
+                name: "噛みつき",
+                specification: ActionStepSpecificationEnum::Attack(ActionStepSpecificationAttack {
+                    power: 2.0,
+                }),
+            },
+            ActionStep {
+                name: "待機",
+                specification: ActionStepSpecificationEnum::Wait(ActionStepSpecificationWait {
+                    invincible: false,
+                }),
+            },
+        ],
+    }
+}
+pub fn create_enemy_stomp() -> Action {
+    Action {
+        name: "踏みつけ",
+        steps: vec![
+            ActionStep {
+                name: "飛び上がり",
+                specification: ActionStepSpecificationEnum::Wait(ActionStepSpecificationWait {
+                    invincible: false,
+                }),
+            },
+            ActionStep {
+                name: "踏みつけ",
+                specification: ActionStepSpecificationEnum::Attack(ActionStepSpecificationAttack {
+                    power: 2.5,
+                }),
+            },
+        ],
+    }
+}
+// ファイアブレス
+pub fn create_enemy_fire_breath() -> Action {
+    Action {
+        name: "ファイアブレス",
+        steps: vec![
+            ActionStep {
+                name: "息を吸い込む",
+                specification: ActionStepSpecificationEnum::Wait(ActionStepSpecificationWait {
+                    invincible: false,
+                }),
+            },
+            ActionStep {
+                name: "炎を吐き始めた",
+                specification: ActionStepSpecificationEnum::Attack(ActionStepSpecificationAttack {
+                    power: 1.0,
+                }),
+            },
+            ActionStep {
+                name: "炎を吐き続ける",
+                specification: ActionStepSpecificationEnum::Attack(ActionStepSpecificationAttack {
+                    power: 2.5,
+                }),
+            },
+            ActionStep {
+                name: "炎を吐き続ける",
+                specification: ActionStepSpecificationEnum::Attack(ActionStepSpecificationAttack {
+                    power: 3.0,
+                }),
+            },
+            ActionStep {
+                name: "炎を吐き続ける",
+                specification: ActionStepSpecificationEnum::Attack(ActionStepSpecificationAttack {
+                    power: 0.5,
+                }),
+            },
+            ActionStep {
+                name: "息切れ",
+                specification: ActionStepSpecificationEnum::Wait(ActionStepSpecificationWait {
+                    invincible: false,
+                }),
+            },
+        ],
+    }
+}
+pub fn create_enemy_wait() -> Action {
+    Action {
+        name: "待機",
+        steps: vec![ActionStep {
+            name: "待機",
+            specification: ActionStepSpecificationEnum::Wait(ActionStepSpecificationWait {
+                invincible: false,
+            }),
+        }],
+    }
+}
+pub fn create_enemy_heal() -> Action {
+    Action {
+        name: "回復",
+        steps: vec![ActionStep {
+            name: "回復",
+            specification: ActionStepSpecificationEnum::Heal(ActionStepSpecificationHeal {
+                amount: 100,
+            }),
+        }],
+    }
+}
+
+// 次ターンに表示される事前決定済み敵行動
+#[derive(Resource, Clone)]
+pub struct EnemyPlannedAction(pub ActionProcess);
+
+// 伏せられた敵ステップの代わりに置く「何もしない」ステップ（予測・探索用）
+pub const HIDDEN_ENEMY_STEP: ActionStep = ActionStep {
+    name: "？？？",
+    specification: ActionStepSpecificationEnum::Wait(ActionStepSpecificationWait {
+        invincible: false,
+    }),
+};
+
+impl EnemyPlannedAction {
+    // 現在の行動の残りステップ
+    pub fn remaining_steps(&self) -> &[ActionStep] {
+        let steps = &self.0.action.steps;
+        &steps[self.0.next_step_index.min(steps.len())..]
+    }
+    // 残りステップのうち難易度で見せてよい分。
+    // 画面・ログ・ガンビット・観測・予測など、敵の予定を読むところはすべてここを通す
+    pub fn visible_steps(&self, difficulty: Difficulty) -> &[ActionStep] {
+        let remaining = self.remaining_steps();
+        &remaining[..remaining.len().min(difficulty.revealed_steps())]
+    }
+    // 画面・ログに出す次の行動（難易度で伏せる場合は「？？？」）
+    pub fn revealed_name(&self, difficulty: Difficulty) -> &'static str {
+        match self.0.current_step() {
+            Some(_) => self
+                .visible_steps(difficulty)
+                .first()
+                .map_or(HIDDEN_ENEMY_STEP.name, |step| step.name),
+            None => "不明",
+        }
+    }
+    // 行動全体の名前（残りのステップを全部見せられる場合だけ）
+    pub fn revealed_action_name(&self, difficulty: Difficulty) -> &'static str {
+        if self.visible_steps(difficulty).len() >= self.remaining_steps().len() {
+            self.0.action.name
+        } else {
+            HIDDEN_ENEMY_STEP.name
+        }
+    }
+    // プレイヤーから見た行動予定。伏せたステップは数だけ残して HIDDEN_ENEMY_STEP に置き換える
+    pub fn revealed(&self, difficulty: Difficulty) -> EnemyPlannedAction {
+        let visible = self.visible_steps(difficulty);
+        let hidden = self.remaining_steps().len() - visible.len();
+        if hidden == 0 {
+            return self.clone();
+        }
+        let mut steps = visible.to_vec();
+        steps.extend(std::iter::repeat_n(HIDDEN_ENEMY_STEP, hidden));
+        EnemyPlannedAction(ActionProcess::from(&Arc::new(Action {
+            name: HIDDEN_ENEMY_STEP.name,
+            steps,
+        })))
+    }
+}
+
+// 中身の分からない敵行動（steps 回何もしない）。予測で「この先の行動選択」の代わりに使う
+pub fn unknown_enemy_action(steps: usize) -> Action {
+    Action {
+        name: HIDDEN_ENEMY_STEP.name,
+        steps: vec![HIDDEN_ENEMY_STEP; steps],
+    }
+}
+
+// 予告パネルの1行（現在の敵行動の残りステップ）
+#[derive(Clone, Copy)]
+pub struct TelegraphStep {
+    pub step: ActionStep,
+    pub damage: Option<i32>, // 攻撃ステップなら今のまま受けた場合のダメージ
+    pub guarded: bool,       // 防御中なのでこのステップは無効になる
+}
+
+// 現在の敵行動の残りステップを、今の防御状態で受けた場合のダメージ付きで並べる。
+// 難易度で見せない分は数だけ返す（(見せるステップ, 伏せたステップ数)）
+pub fn enemy_telegraph(
+    planned: &EnemyPlannedAction,
+    e_attack: &Attack,
+    defend: &DefendNextAttack,
+    difficulty: Difficulty,
+) -> (Vec<TelegraphStep>, usize) {
+    let visible = planned.visible_steps(difficulty);
+    // 防御は次の攻撃1回だけ無効にする
+    let mut defend_left = defend.0;
+    let steps = visible
+        .iter()
+        .map(|&step| match step.specification {
+            ActionStepSpecificationEnum::Attack(spec) => {
+                let guarded = std::mem::take(&mut defend_left);
+                TelegraphStep {
+                    step,
+                    damage: Some(if guarded {
+                        0
+                    } else {
+                        (e_attack.0 as f32 * spec.power) as i32
+                    }),
+                    guarded,
+                }
+            }
+            _ => TelegraphStep {
+                step,
+                damage: None,
+                guarded: false,
+            },
+        })
+        .collect();
+    (steps, planned.remaining_steps().len() - visible.len())
+}
+
+// 敵の行動候補（確率, 生成関数）。確率の合計は1
+pub type EnemyActionTable = &'static [(f32, fn() -> Action)];
+// 戦闘開始時: 攻撃か待機
+pub const ENEMY_ACTIONS_OPENING: EnemyActionTable =
+    &[(0.5, create_enemy_attack), (0.5, create_enemy_wait)];
+// 通常時
+pub const ENEMY_ACTIONS_NORMAL: EnemyActionTable = &[
+    (0.3, create_enemy_wait),
+    (0.3, create_enemy_attack),
+    (0.2, create_enemy_claw_combo),
+    (0.1, create_enemy_claw_strong),
+    (0.1, create_enemy_stomp),
+];
+// 敵HPが半分以下: 回復と大技が選択肢に入る
+pub const ENEMY_ACTIONS_LOW_HP: EnemyActionTable = &[
+    (0.1, create_enemy_wait),
+    (0.1, create_enemy_heal),
+    (0.1, create_enemy_attack),
+    (0.2, create_enemy_claw_combo_strong),
+    (0.2, create_enemy_claw_strong),
+    (0.1, create_enemy_stomp),
+    (0.2, create_enemy_fire_breath),
+];
+// 現在の敵HPで使う行動候補
+pub fn enemy_action_table(e_hp: &Hp) -> EnemyActionTable {
+    if e_hp.current * 2 <= e_hp.max {
+        ENEMY_ACTIONS_LOW_HP
+    } else {
+        ENEMY_ACTIONS_NORMAL
+    }
+}
+// ドラゴンの全行動（2P対戦で名前から行動を引く用）
+pub const ENEMY_MOVES: &[fn() -> Action] = &[
+    create_enemy_attack,
+    create_enemy_claw_strong,
+    create_enemy_claw_combo,
+    create_enemy_claw_combo_strong,
+    create_enemy_stomp,
+    create_enemy_fire_breath,
+    create_enemy_wait,
+    create_enemy_heal,
+];
+pub fn enemy_move_by_name(name: &str) -> Option<Action> {
+    ENEMY_MOVES
+        .iter()
+        .map(|create| create())
+        .find(|action| action.name == name)
+}
+
+// 2P対戦でドラゴン側が予約した次の行動（None なら行動テーブルから乱数で選ぶ）
+#[derive(Resource, Default, Clone)]
+pub struct EnemyMoveChoice(pub Option<Action>);
+
+// このターンの解決後に次の敵行動を決める必要があるか（現在の行動が最後のステップ）
+pub fn enemy_choice_needed(planned: &EnemyPlannedAction) -> bool {
+    planned.0.next_step_index + 1 >= planned.0.action.steps.len()
+}
+
+// 候補の重み（aggression は待機以外の行動に掛かる）
+fn enemy_action_weight(p: f32, action: &Action, aggression: f32) -> f32 {
+    let passive = action
+        .steps
+        .iter()
+        .all(|s| matches!(s.specification, ActionStepSpecificationEnum::Wait(_)));
+    if passive { p } else { p * aggression }
+}
+
+// 各候補が選ばれる確率（pick_enemy_action と同じ重み。探索の確率ノード用）
+pub fn enemy_action_odds(table: EnemyActionTable, aggression: f32) -> Vec<(f32, Action)> {
+    let candidates: Vec<(f32, Action)> = table
+        .iter()
+        .map(|&(p, create)| {
+            let action = create();
+            (enemy_action_weight(p, &action, aggression), action)
+        })
+        .collect();
+    let total: f32 = candidates.iter().map(|(w, _)| w).sum();
+    candidates
+        .into_iter()
+        .map(|(w, action)| (w / total, action))
+        .collect()
+}
+
+// roll (0..1) で候補から1つ選ぶ。aggression は待機以外の行動の重み（1.0で表のまま）
+pub fn pick_enemy_action(table: EnemyActionTable, aggression: f32, roll: f32) -> Action {
+    let candidates: Vec<(f32, Action)> = table
+        .iter()
+        .map(|&(p, create)| {
+            let action = create();
+            (enemy_action_weight(p, &action, aggression), action)
+        })
+        .collect();
+    // 表の確率の合計（=1）に対する比率で伸ばす（aggression=1.0なら roll そのまま）
+    let base: f32 = table.iter().map(|(p, _)| p).sum();
+    let total: f32 = candidates.iter().map(|(w, _)| w).sum();
+    let target = roll * (total / base);
+    let mut acc = 0.0;
+    for (w, action) in candidates {
+        acc += w;
+        if target < acc {
+            return action;
+        }
+    }
+    // 誤差で合計が1に届かなかった場合は最後の候補
+    (table[table.len() - 1].1)()
+}
+
+// 戦闘の乱数（シミュレーターではシード固定で再現できるようにする）
+#[derive(Resource, Clone)]
+pub struct BattleRng(pub StdRng);
+impl BattleRng {
+    pub fn seeded(seed: u64) -> Self {
+        BattleRng(StdRng::seed_from_u64(seed))
+    }
+
+    pub fn from_entropy() -> Self {
+        BattleRng(StdRng::from_rng(&mut rand::rng()))
+    }
+}
+
+// ダメージの出どころ（集計用）
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DamageSource {
+    Attack,
+    ChainAttack,
+    Skill,
+    GuardCounter,
+    Finisher,
+    Combo,
+}
+impl DamageSource {
+    pub fn label(&self) -> &'static str {
+        match self {
+            DamageSource::Attack => "攻撃",
+            DamageSource::ChainAttack => "連撃",
+            DamageSource::Skill => "強攻撃",
+            DamageSource::GuardCounter => "ガードカウンター",
+            DamageSource::Finisher => "奥義",
+            DamageSource::Combo => "コンボ追加",
+        }
+    }
+}
+
+// 1ターンの解決中に起きた出来事（ログ文字列とは別に、集計や外部ツール向け）
+#[derive(Clone, PartialEq, Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum BattleEvent {
+    CommandExecuted { command: CommandKind },
+    StaminaShortage { command: CommandKind },
+    MomentumShortage { command: CommandKind }, // 強化・奥義のモメンタム不足（スタミナは消費済み）
+    AlreadyEnhanced { command: CommandKind },  // 強化中の強化（スタミナは消費済み）
+    DamageDealt { source: DamageSource, amount: i32 },
+    PlayerHealed { amount: i32 },
+    DamageTaken { amount: i32, step: &'static str },
+    EnemyHealed { amount: i32 },
+    ComboFinished { name: &'static str },
+    BreakAdded { amount: i32 },
+    MomentumSpent { amount: i32 },
+    BreakTriggered,
+    BreakRecovered,
+    EnemyActionChosen { name: &'static str },
+}
+
+// 直近1ターン分の BattleEvent（resolve_command の開始時にクリア）
+#[derive(Resource, Default, Clone)]
+pub struct TurnEvents(pub Vec<BattleEvent>);
+
+// 1戦分の集計（結果画面用）
+#[derive(Resource, Default, Clone, Debug)]
+pub struct BattleStats {
+    pub difficulty: Difficulty, // この戦闘の難易度（結果と一緒に記録する）
+    pub turns: u32,
+    pub damage_dealt: i32,
+    pub damage_taken: i32,
+    pub largest_hit: i32,
+    pub turns_broken: u32, // ターン終了時に敵がブレイク中だった回数
+    pub chain_attacks: u32,
+    pub guard_counters: u32,
+    pub combos: u32,
+    pub momentum_earned: i32,
+    pub momentum_spent: i32,
+    pub stamina_failures: u32,
+}
+impl BattleStats {
+    // 1ターン分を加える。momentum_before はバッチ内の加算（advance_batch）より前の値。
+    // 獲得量は前後の差に消費量を足して求める（上限100で切り捨てられた分は含まない）
+    pub fn record_turn(
+        &mut self,
+        events: &[BattleEvent],
+        momentum_before: i32,
+        momentum_after: i32,
+        enemy_broken: bool,
+    ) {
+        self.turns += 1;
+        let mut spent = 0;
+        for event in events {
+            match event {
+                BattleEvent::DamageDealt { source, amount } => {
+                    self.damage_dealt += amount;
+                    self.largest_hit = self.largest_hit.max(*amount);
+                    match source {
+                        DamageSource::ChainAttack => self.chain_attacks += 1,
+                        DamageSource::GuardCounter => self.guard_counters += 1,
+                        _ => {}
+                    }
+                }
+                BattleEvent::DamageTaken { amount, .. } => self.damage_taken += amount,
+                BattleEvent::ComboFinished { .. } => self.combos += 1,
+                BattleEvent::MomentumSpent { amount } => spent += amount,
+                BattleEvent::StaminaShortage { .. } => self.stamina_failures += 1,
+                _ => {}
+            }
+        }
+        self.momentum_spent += spent;
+        self.momentum_earned += momentum_after - momentum_before + spent;
+        if enemy_broken {
+            self.turns_broken += 1;
+        }
+    }
+}
+
+// コマンド種別
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Debug, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandKind {
+    Attack,
+    Skill,
+    Heal,
+    Defend,
+    Wait,
+    EnhanceAttack,
+    EnhanceSkill,
+    EnhanceHeal,
+    EnhanceDefend,
+    // モメンタム100を全て消費する奥義
+    Finisher,
+}
+
+impl CommandKind {
+    // 全コマンド（キー割り当て・外部ツールの番号はこの順）
+    pub const ALL: [CommandKind; 10] = [
+        CommandKind::Attack,
+        CommandKind::Skill,
+        CommandKind::Heal,
+        CommandKind::Defend,
+        CommandKind::Wait,
+        CommandKind::EnhanceAttack,
+        CommandKind::EnhanceSkill,
+        CommandKind::EnhanceHeal,
+        CommandKind::EnhanceDefend,
+        CommandKind::Finisher,
+    ];
+
+    // スクリプトや外部ツールで使う英字名
+    pub fn name(&self) -> &'static str {
+        match self {
+            CommandKind::Attack => "attack",
+            CommandKind::Skill => "skill",
+            CommandKind::Heal => "heal",
+            CommandKind::Defend => "defend",
+            CommandKind::Wait => "wait",
+            CommandKind::EnhanceAttack => "enhance_attack",
+            CommandKind::EnhanceSkill => "enhance_skill",
+            CommandKind::EnhanceHeal => "enhance_heal",
+            CommandKind::EnhanceDefend => "enhance_defend",
+            CommandKind::Finisher => "finisher",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        CommandKind::ALL.into_iter().find(|c| c.name() == name)
+    }
+
+    // 文字で入力する画面（端末版・ネット対戦・シミュレーターのスクリプト）の1文字キー
+    pub fn key(&self) -> char {
+        match self {
+            CommandKind::Attack => 'a',
+            CommandKind::Skill => 's',
+            CommandKind::Heal => 'h',
+            CommandKind::Defend => 'd',
+            CommandKind::Wait => 'w',
+            CommandKind::EnhanceAttack => 'z',
+            CommandKind::EnhanceSkill => 'x',
+            CommandKind::EnhanceHeal => 'c',
+            CommandKind::EnhanceDefend => 'v',
+            CommandKind::Finisher => 'f',
+        }
+    }
+
+    // 大文字小文字は区別しない
+    pub fn from_key(key: char) -> Option<Self> {
+        let key = key.to_ascii_lowercase();
+        CommandKind::ALL.into_iter().find(|c| c.key() == key)
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            CommandKind::Attack => "攻撃",
+            CommandKind::Skill => "強攻撃",
+            CommandKind::Heal => "回復",
+            CommandKind::Defend => "防御",
+            CommandKind::Wait => "待機",
+            CommandKind::EnhanceAttack => "攻撃強化",
+            CommandKind::EnhanceSkill => "強攻撃強化",
+            CommandKind::EnhanceHeal => "回復強化",
+            CommandKind::EnhanceDefend => "防御強化",
+            CommandKind::Finisher => "奥義",
+        }
+    }
+}
+
+// 予約コマンドのキュー
+#[derive(Resource, Default, Clone)]
+pub struct CommandQueue(pub std::collections::VecDeque<CommandKind>);
+
+// プレイヤーが実行に成功したコマンドの履歴（バッチ・ターンをまたいで保持）
+// 末尾が攻撃/強攻撃なら次の攻撃は「連撃」。combo_start以降が現在のコンボ。
+#[derive(Resource, Default, Clone)]
+pub struct PlayerComboState {
+    pub history: Vec<CommandKind>,
+    pub combo_start: usize, // 名前付きコンボ成立後はここから数え直す
+}
+impl PlayerComboState {
+    pub fn last(&self) -> Option<CommandKind> {
+        self.history.last().copied()
+    }
+
+    pub fn current(&self) -> &[CommandKind] {
+        &self.history[self.combo_start.min(self.history.len())..]
+    }
+
+    pub fn push(&mut self, cmd: CommandKind, max_len: usize) {
+        self.history.push(cmd);
+        if self.history.len() > max_len {
+            let overflow = self.history.len() - max_len;
+            self.history.drain(..overflow);
+            self.combo_start = self.combo_start.saturating_sub(overflow);
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.history.clear();
+        self.combo_start = 0;
+    }
+
+    // 現在のコンボ末尾で成立した名前付きコンボを返す
+    pub fn matched(&self) -> Option<&'static ComboPattern> {
+        let current = self.current();
+        COMBO_PATTERNS
+            .iter()
+            .find(|p| current.ends_with(p.sequence))
+    }
+
+    // 成立途中のコンボ（現在のコンボ末尾がパターンの先頭と一致）と残りのコマンドを返す
+    pub fn in_progress(&self) -> Option<(&'static ComboPattern, &'static [CommandKind])> {
+        let current = self.current();
+        COMBO_PATTERNS.iter().find_map(|p| {
+            (1..p.sequence.len())
+                .rev()
+                .find(|&n| current.ends_with(&p.sequence[..n]))
+                .map(|n| (p, &p.sequence[n..]))
+        })
+    }
+}
+
+// コンボのリセット条件
+#[derive(Resource, Clone)]
+pub struct ComboResetRules {
+    pub on_failure: bool,      // スタミナ不足で行動失敗したらリセット
+    pub on_damage_taken: bool, // 敵の攻撃でダメージを受けたらリセット
+    pub on_queue_reset: bool,  // 連続コマンドの予約を破棄したらリセット
+    pub on_wait: bool,         // 待機したらリセット
+    pub max_history: usize,    // 保持する履歴の最大数
+}
+impl Default for ComboResetRules {
+    fn default() -> Self {
+        ComboResetRules {
+            on_failure: true,
+            on_damage_taken: false,
+            on_queue_reset: false,
+            on_wait: false,
+            max_history: 16,
+        }
+    }
+}
+
+// 既定のリセット条件（assets/combo_rules.txt が読めない環境向け）
+pub const DEFAULT_COMBO_RULES: &str = include_str!("../assets/combo_rules.txt");
+
+impl ComboResetRules {
+    // 1行に `項目 = 値`（# 以降はコメント）。書かなかった項目は既定値のまま
+    pub fn parse(src: &str) -> Result<Self, String> {
+        let mut rules = ComboResetRules::default();
+        for (n, raw) in src.lines().enumerate() {
+            let line = raw.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let (name, value) = line
+                .split_once('=')
+                .ok_or_else(|| format!("{}行目: '=' がありません", n + 1))?;
+            let (name, value) = (name.trim(), value.trim());
+            let flag = || {
+                value
+                    .parse::<bool>()
+                    .map_err(|_| format!("{}行目: true か false ではありません '{value}'", n + 1))
+            };
+            match name {
+                "on_failure" => rules.on_failure = flag()?,
+                "on_damage_taken" => rules.on_damage_taken = flag()?,
+                "on_queue_reset" => rules.on_queue_reset = flag()?,
+                "on_wait" => rules.on_wait = flag()?,
+                "max_history" => {
+                    rules.max_history = value.parse().ok().filter(|&m| m >= 1).ok_or_else(|| {
+                        format!("{}行目: 1以上の整数ではありません '{value}'", n + 1)
+                    })?
+                }
+                other => return Err(format!("{}行目: 不明な項目 '{other}'", n + 1)),
+            }
+        }
+        Ok(rules)
+    }
+}
+
+// 名前付きコンボ（sequenceを順に実行し終えた時点でボーナス発動）
+pub struct ComboPattern {
+    pub name: &'static str,
+    pub sequence: &'static [CommandKind],
+    pub bonus: ComboBonus,
+}
+#[derive(Clone, Copy)]
+pub struct ComboBonus {
+    pub damage: i32,
+    pub break_value: i32,
+    pub stamina: i32,
+    pub momentum: i32,
+}
+
+pub const COMBO_PATTERNS: &[ComboPattern] = &[
+    ComboPattern {
+        name: "反撃の型",
+        sequence: &[CommandKind::Defend, CommandKind::Skill, CommandKind::Attack],
+        bonus: ComboBonus {
+            damage: 20,
+            break_value: 20,
+            stamina: 0,
+            momentum: 0,
+        },
+    },
+    ComboPattern {
+        name: "三連撃",
+        sequence: &[
+            CommandKind::Attack,
+            CommandKind::Attack,
+            CommandKind::Attack,
+        ],
+        bonus: ComboBonus {
+            damage: 15,
+            break_value: 15,
+            stamina: 0,
+            momentum: 0,
+        },
+    },
+    ComboPattern {
+        name: "猛攻",
+        sequence: &[CommandKind::Skill, CommandKind::Skill],
+        bonus: ComboBonus {
+            damage: 0,
+            break_value: 20,
+            stamina: 0,
+            momentum: 10,
+        },
+    },
+    ComboPattern {
+        name: "立て直し",
+        sequence: &[CommandKind::Defend, CommandKind::Heal, CommandKind::Wait],
+        bonus: ComboBonus {
+            damage: 0,
+            break_value: 0,
+            stamina: 20,
+            momentum: 20,
+        },
+    },
+];
+
+// 1コマンド分の解決処理に必要な状態一式
+pub struct BattleCtx<'a> {
+    pub phase: &'a mut BattlePhase,
+    pub turn: &'a mut Turn,
+    pub p_attack: &'a Attack,
+    pub p_hp: &'a mut Hp,
+    pub p_sta: &'a mut Stamina,
+    pub e_attack: &'a Attack,
+    pub e_hp: &'a mut Hp,
+    pub e_break: &'a mut BreakValue,
+    pub e_bstate: &'a mut BreakState,
+    pub e_bregen: &'a mut BreakRegen,
+    pub log: &'a mut CombatLog,
+    pub defend: &'a mut DefendNextAttack,
+    pub guard: &'a mut GuardCounterReady,
+    pub combo: &'a mut PlayerComboState,
+    pub combo_rules: &'a ComboResetRules,
+    pub planned: &'a mut EnemyPlannedAction,
+    pub momentum: &'a mut Momentum,
+    pub overdrive: &'a mut Overdrive,
+    pub buffs: &'a mut CommandBuffs,
+    pub enemy_damage_popup: &'a mut EnemyDamagePopup,
+    pub finisher_cinematic: &'a mut FinisherCinematic,
+    pub rng: &'a mut BattleRng,
+    pub events: &'a mut TurnEvents,
+    pub enemy_choice: &'a mut EnemyMoveChoice,
+    pub difficulty: &'a Difficulty,
+}
+
+// チェックサムに含める状態への参照（BattleState と BattleCtx のどちらからでも計算できるように）
+struct ChecksumSource<'a> {
+    turn: &'a Turn,
+    p_hp: &'a Hp,
+    p_sta: &'a Stamina,
+    e_hp: &'a Hp,
+    e_break: &'a BreakValue,
+    e_bstate: &'a BreakState,
+    e_bregen: &'a BreakRegen,
+    momentum: &'a Momentum,
+    overdrive: &'a Overdrive,
+    buffs: &'a CommandBuffs,
+    defend: &'a DefendNextAttack,
+    guard: &'a GuardCounterReady,
+    combo: &'a PlayerComboState,
+    planned: &'a EnemyPlannedAction,
+    rng: &'a BattleRng,
+    difficulty: Difficulty,
+}
+
+impl ChecksumSource<'_> {
+    fn hash(&self) -> u64 {
+        // FNV-1a（実行環境によらず同じ値にするため標準のハッシュは使わない）
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        let mut write = |v: i64| {
+            for b in v.to_le_bytes() {
+                hash ^= b as u64;
+                hash = hash.wrapping_mul(0x0100_0000_01b3);
+            }
+        };
+        for v in [
+            self.turn.0 as i64,
+            self.p_hp.current as i64,
+            self.p_sta.current as i64,
+            self.e_hp.current as i64,
+            self.e_break.current as i64,
+            self.e_bstate.remaining_turns as i64,
+            self.e_bregen.amount as i64,
+            self.momentum.current as i64,
+            self.overdrive.gauge as i64,
+            self.overdrive.active as i64,
+            self.buffs.attack as i64,
+            self.buffs.skill as i64,
+            self.buffs.heal as i64,
+            self.buffs.defend as i64,
+            self.defend.0 as i64,
+            self.guard.0 as i64,
+            self.planned.0.next_step_index as i64,
+            self.difficulty as i64,
+        ] {
+            write(v);
+        }
+        write(self.combo.combo_start as i64);
+        for &cmd in &self.combo.history {
+            write(cmd as i64);
+        }
+        for b in self.planned.0.action.name.bytes() {
+            write(b as i64);
+        }
+        // 乱数の状態（次に出る値）
+        write(self.rng.0.clone().random::<u64>() as i64);
+        hash
+    }
+}
+
+impl BattleCtx<'_> {
+    // BattleState::checksum と同じ値（バッチ実行中のコールバックから使う）
+    pub fn checksum(&self) -> u64 {
+        ChecksumSource {
+            turn: self.turn,
+            p_hp: self.p_hp,
+            p_sta: self.p_sta,
+            e_hp: self.e_hp,
+            e_break: self.e_break,
+            e_bstate: self.e_bstate,
+            e_bregen: self.e_bregen,
+            momentum: self.momentum,
+            overdrive: self.overdrive,
+            buffs: self.buffs,
+            defend: self.defend,
+            guard: self.guard,
+            combo: self.combo,
+            planned: self.planned,
+            rng: self.rng,
+            difficulty: *self.difficulty,
+        }
+        .hash()
+    }
+
+    // 同じ参照一式でバッチ内の複数ターンを続けて解決するための再借用
+    pub fn reborrow(&mut self) -> BattleCtx<'_> {
+        BattleCtx {
+            phase: &mut *self.phase,
+            turn: &mut *self.turn,
+            p_attack: self.p_attack,
+            p_hp: &mut *self.p_hp,
+            p_sta: &mut *self.p_sta,
+            e_attack: self.e_attack,
+            e_hp: &mut *self.e_hp,
+            e_break: &mut *self.e_break,
+            e_bstate: &mut *self.e_bstate,
+            e_bregen: &mut *self.e_bregen,
+            log: &mut *self.log,
+            defend: &mut *self.defend,
+            guard: &mut *self.guard,
+            combo: &mut *self.combo,
+            combo_rules: self.combo_rules,
+            planned: &mut *self.planned,
+            momentum: &mut *self.momentum,
+            overdrive: &mut *self.overdrive,
+            buffs: &mut *self.buffs,
+            enemy_damage_popup: &mut *self.enemy_damage_popup,
+            finisher_cinematic: &mut *self.finisher_cinematic,
+            rng: &mut *self.rng,
+            events: &mut *self.events,
+            enemy_choice: &mut *self.enemy_choice,
+            difficulty: self.difficulty,
+        }
+    }
+}
+
+// 共通のコマンド解決処理（1ターン分: プレイヤー行動→敵行動→ターン終了処理）
+pub fn resolve_command(ctx: BattleCtx<'_>, cmd: CommandKind) {
+    let BattleCtx {
+        phase,
+        turn,
+        p_attack,
+        p_hp,
+        p_sta,
+        e_attack,
+        e_hp,
+        e_break,
+        e_bstate,
+        e_bregen,
+        log,
+        defend,
+        guard,
+        combo,
+        combo_rules,
+        planned,
+        momentum,
+        overdrive,
+        buffs,
+        enemy_damage_popup,
+        finisher_cinematic,
+        rng,
+        events,
+        enemy_choice,
+        difficulty,
+    } = ctx;
+    *phase = BattlePhase::InBattle;
+    events.0.clear();
+    let guard_ready_at_start = guard.0;
+    log.0.push(format!(
+        "ターン {} プレイヤーは{}を選択",
+        turn.0,
+        cmd.label()
+    ));
+    // 連撃判定（直前が攻撃または強攻撃 かつ 今回が攻撃）
+    let is_chain = matches!(combo.last(), Some(CommandKind::Attack | CommandKind::Skill))
+        && matches!(cmd, CommandKind::Attack);
+
+    // コストチェック（実行時にも確認）。不足なら行動失敗。
+    let cost = match cmd {
+        CommandKind::Attack => {
+            if is_chain {
+                5
+            } else {
+                15
+            }
+        }
+        CommandKind::Skill => 25,
+        CommandKind::Heal => {
+            if buffs.heal > 0 {
+                20
+            } else {
+                15
+            }
+        }
+        CommandKind::Defend => {
+            if buffs.defend > 0 {
+                5
+            } else {
+                10
+            }
+        }
+        CommandKind::Wait => 0,
+        CommandKind::EnhanceAttack
+        | CommandKind::EnhanceSkill
+        | CommandKind::EnhanceHeal
+        | CommandKind::EnhanceDefend
+        | CommandKind::Finisher => 0,
+    };
+    // オーバードライブ中は消費なし
+    let cost = if overdrive.active { 0 } else { cost };
+    if p_sta.current < cost {
+        log.0.push("スタミナ不足で行動できませんでした".to_string());
+        events.0.push(BattleEvent::StaminaShortage { command: cmd });
+        // 実行失敗なので連撃を継続させない
+        if combo_rules.on_failure {
+            combo.reset();
+        }
+    } else {
+        p_sta.current -= cost;
+        events.0.push(BattleEvent::CommandExecuted { command: cmd });
+
+        match cmd {
+            CommandKind::EnhanceAttack => {
+                if buffs.attack > 0 {
+                    log.0
+                        .push("攻撃は既に強化中のため強化できません".to_string());
+                    events.0.push(BattleEvent::AlreadyEnhanced { command: cmd });
+                } else if momentum.current < 50 {
+                    log.0
+                        .push("モメンタム不足で強化できませんでした (必要50)".to_string());
+                    events
+                        .0
+                        .push(BattleEvent::MomentumShortage { command: cmd });
+                } else {
+                    momentum.current -= 50;
+                    events.0.push(BattleEvent::MomentumSpent { amount: 50 });
+                    buffs.attack = BUFF_TURNS;
+                    log.0
+                        .push("攻撃を強化した (11ターン持続, モメンタム-50)".to_string());
+                }
+            }
+            CommandKind::EnhanceSkill => {
+                if buffs.skill > 0 {
+                    log.0
+                        .push("強攻撃は既に強化中のため強化できません".to_string());
+                    events.0.push(BattleEvent::AlreadyEnhanced { command: cmd });
+                } else if momentum.current < 50 {
+                    log.0
+                        .push("モメンタム不足で強化できませんでした (必要50)".to_string());
+                    events
+                        .0
+                        .push(BattleEvent::MomentumShortage { command: cmd });
+                } else {
+                    momentum.current -= 50;
+                    events.0.push(BattleEvent::MomentumSpent { amount: 50 });
+                    buffs.skill = BUFF_TURNS;
+                    log.0
+                        .push("強攻撃を強化した (11ターン持続, モメンタム-50)".to_string());
+                }
+            }
+            CommandKind::EnhanceHeal => {
+                if buffs.heal > 0 {
+                    log.0
+                        .push("回復は既に強化中のため強化できません".to_string());
+                    events.0.push(BattleEvent::AlreadyEnhanced { command: cmd });
+                } else if momentum.current < 50 {
+                    log.0
+                        .push("モメンタム不足で強化できませんでした (必要50)".to_string());
+                    events
+                        .0
+                        .push(BattleEvent::MomentumShortage { command: cmd });
+                } else {
+                    momentum.current -= 50;
+                    events.0.push(BattleEvent::MomentumSpent { amount: 50 });
+                    buffs.heal = BUFF_TURNS;
+                    log.0
+                        .push("回復を強化した (11ターン持続, モメンタム-50)".to_string());
+                }
+            }
+            CommandKind::EnhanceDefend => {
+                if buffs.defend > 0 {
+                    log.0
+                        .push("防御は既に強化中のため強化できません".to_string());
+                    events.0.push(BattleEvent::AlreadyEnhanced { command: cmd });
+                } else if momentum.current < 50 {
+                    log.0
+                        .push("モメンタム不足で強化できませんでした (必要50)".to_string());
+                    events
+                        .0
+                        .push(BattleEvent::MomentumShortage { command: cmd });
+                } else {
+                    momentum.current -= 50;
+                    events.0.push(BattleEvent::MomentumSpent { amount: 50 });
+                    buffs.defend = BUFF_TURNS;
+                    log.0
+                        .push("防御を強化した (11ターン持続, モメンタム-50)".to_string());
+                }
+            }
+            CommandKind::Finisher => {
+                if momentum.current < 100 {
+                    log.0
+                        .push("モメンタム不足で奥義を放てませんでした (必要100)".to_string());
+                    events
+                        .0
+                        .push(BattleEvent::MomentumShortage { command: cmd });
+                } else {
+                    events.0.push(BattleEvent::MomentumSpent {
+                        amount: momentum.current,
+                    });
+                    momentum.current = 0;
+                    // 強化中のコマンド1つにつき威力+30 / ブレイク+15
+                    let active_buffs = [buffs.attack, buffs.skill, buffs.heal, buffs.defend]
+                        .iter()
+                        .filter(|&&t| t > 0)
+                        .count() as i32;
+                    let base = 120 + active_buffs * 30;
+                    let mut dmg = base;
+                    let mut break_bonus = 0;
+                    if e_bstate.remaining_turns > 0 {
+                        break_bonus = 30 + base * 2;
+                        dmg = base + break_bonus;
+                    }
+                    e_hp.current = (e_hp.current - dmg).max(0);
+                    enemy_damage_popup.amount = dmg;
+                    enemy_damage_popup.timer = 1.2;
+                    finisher_cinematic.0 = true;
+                    events.0.push(BattleEvent::DamageDealt {
+                        source: DamageSource::Finisher,
+                        amount: dmg,
+                    });
+                    if break_bonus > 0 {
+                        log.0.push(format!(
+                            "奥義! 敵に{}ダメージ (基本{} + ブレイク補正{} = 合計{}, 敵HP {} / {})",
+                            dmg, base, break_bonus, dmg, e_hp.current, e_hp.max
+                        ));
+                    } else {
+                        log.0.push(format!(
+                            "奥義! 敵に{}ダメージ (強化{}種, 敵HP {} / {})",
+                            dmg, active_buffs, e_hp.current, e_hp.max
+                        ));
+                    }
+                    let before_break = e_break.current;
+                    let add_break = 50 + active_buffs * 15;
+                    e_break.current += add_break;
+                    events.0.push(BattleEvent::BreakAdded { amount: add_break });
+                    log.0.push(format!(
+                        "ブレイク値 +{} ({} → {} / {})",
+                        add_break,
+                        before_break,
+                        e_break.current,
+                        difficulty.break_threshold()
+                    ));
+                    e_bregen.amount = difficulty.break_regen_base();
+                }
+            }
+            CommandKind::Heal => {
+                let amount = if buffs.heal > 0 { 60 } else { 50 };
+                let before = p_hp.current;
+                p_hp.current = (p_hp.current + amount).min(p_hp.max);
+                let healed = p_hp.current - before;
+                events.0.push(BattleEvent::PlayerHealed { amount: healed });
+                log.0.push(format!(
+                    "プレイヤーは{}回復 (HP {} / {})",
+                    healed, p_hp.current, p_hp.max
+                ));
+            }
+            CommandKind::Defend => {
+                defend.0 = true;
+                guard.0 = true; // 次プレイヤー行動のガードカウンター猶予
+                log.0
+                    .push("プレイヤーは防御態勢に入った (次の敵攻撃は無効)".to_string());
+                log.0
+                    .push("ガードカウンターの構え! 次の行動で強攻撃が強化".to_string());
+            }
+            CommandKind::Attack => {
+                let base = if buffs.attack > 0 { 25 } else { p_attack.0 };
+                let mut dmg = base;
+                let mut break_bonus = 0;
+                if e_bstate.remaining_turns > 0 {
+                    break_bonus = 30 + base * 2;
+                    dmg = base + break_bonus;
+                }
+                e_hp.current = (e_hp.current - dmg).max(0);
+                // 敵ダメージポップアップ設定
+                enemy_damage_popup.amount = dmg;
+                enemy_damage_popup.timer = 1.2;
+                events.0.push(BattleEvent::DamageDealt {
+                    source: if is_chain {
+                        DamageSource::ChainAttack
+                    } else {
+                        DamageSource::Attack
+                    },
+                    amount: dmg,
+                });
+                if is_chain {
+                    if break_bonus > 0 {
+                        log.0.push(format!(
+                            "連撃! 敵に{}ダメージ (基本{} + ブレイク補正{} = 合計{}, 敵HP {} / {})",
+                            dmg, base, break_bonus, dmg, e_hp.current, e_hp.max
+                        ));
+                    } else {
+                        log.0.push(format!(
+                            "連撃! 敵に{}ダメージ (消費スタミナ半減, 敵HP {} / {})",
+                            dmg, e_hp.current, e_hp.max
+                        ));
+                    }
+                } else {
+                    if break_bonus > 0 {
+                        log.0.push(format!(
+                            "敵に{}ダメージ (基本{} + ブレイク補正{} = 合計{}, 敵HP {} / {})",
+                            dmg, base, break_bonus, dmg, e_hp.current, e_hp.max
+                        ));
+                    } else {
+                        log.0.push(format!(
+                            "敵に{}ダメージ (敵HP {} / {})",
+                            dmg, e_hp.current, e_hp.max
+                        ));
+                    }
+                }
+                // ブレイク値加算（攻撃時の固定増加量: 通常15・強化時25）
+                let before_break = e_break.current;
+                let add_break = if buffs.attack > 0 { 25 } else { 10 };
+                e_break.current += add_break;
+                events.0.push(BattleEvent::BreakAdded { amount: add_break });
+                log.0.push(format!(
+                    "ブレイク値 +{} ({} → {} / {})",
+                    add_break,
+                    before_break,
+                    e_break.current,
+                    difficulty.break_threshold()
+                ));
+                // ダメージを受けたので自然回復量をリセット
+                e_bregen.amount = difficulty.break_regen_base();
+            }
+            CommandKind::Skill => {
+                let mut base = if buffs.skill > 0 { 45 } else { 25 };
+                let is_guard_counter = guard_ready_at_start;
+                if is_guard_counter {
+                    base += 5; // ガードカウンター: 威力+5
+                }
+                let mut dmg = base;
+                let mut break_bonus = 0;
+                if e_bstate.remaining_turns > 0 {
+                    break_bonus = 30 + base * 2;
+                    dmg = base + break_bonus;
+                }
+                e_hp.current = (e_hp.current - dmg).max(0);
+                // 敵ダメージポップアップ設定
+                enemy_damage_popup.amount = dmg;
+                enemy_damage_popup.timer = 1.2;
+                events.0.push(BattleEvent::DamageDealt {
+                    source: if is_guard_counter {
+                        DamageSource::GuardCounter
+                    } else {
+                        DamageSource::Skill
+                    },
+                    amount: dmg,
+                });
+                if is_guard_counter {
+                    if break_bonus > 0 {
+                        log.0.push(format!(
+                            "ガードカウンター! 敵に{}ダメージ (基本{} + ブレイク補正{} = 合計{}, 敵HP {} / {})",
+                            dmg, base, break_bonus, dmg, e_hp.current, e_hp.max
+                        ));
+                    } else {
+                        log.0.push(format!(
+                            "ガードカウンター! 敵に{}ダメージ (敵HP {} / {})",
+                            dmg, e_hp.current, e_hp.max
+                        ));
+                    }
+                } else {
+                    if break_bonus > 0 {
+                        log.0.push(format!(
+                            "敵に{}ダメージ (基本{} + ブレイク補正{} = 合計{}, 敵HP {} / {})",
+                            dmg, base, break_bonus, dmg, e_hp.current, e_hp.max
+                        ));
+                    } else {
+                        log.0.push(format!(
+                            "敵に{}ダメージ (敵HP {} / {})",
+                            dmg, e_hp.current, e_hp.max
+                        ));
+                    }
+                }
+                let before_break = e_break.current;
+                let mut add_break = if buffs.skill > 0 { 40 } else { 25 };
+                if is_guard_counter {
+                    add_break += 20; // ガードカウンター: ブレイク+20
+                }
+                e_break.current += add_break;
+                events.0.push(BattleEvent::BreakAdded { amount: add_break });
+                log.0.push(format!(
+                    "ブレイク値 +{} ({} → {} / {})",
+                    add_break,
+                    before_break,
+                    e_break.current,
+                    difficulty.break_threshold()
+                ));
+                e_bregen.amount = difficulty.break_regen_base();
+            }
+            CommandKind::Wait => {
+                let before = p_sta.current;
+                p_sta.current = (p_sta.current + 60).min(p_sta.max);
+                let recovered = p_sta.current - before;
+                log.0.push(format!(
+                    "プレイヤーは待機してスタミナを{}回復 (Stamina {} / {})",
+                    recovered, p_sta.current, p_sta.max
+                ));
+            }
+        }
+        // 実行成功: 履歴に積む（末尾が攻撃/強攻撃なら次の攻撃が連撃になる）
+        if combo_rules.on_wait && matches!(cmd, CommandKind::Wait) {
+            combo.reset();
+        } else {
+            combo.push(cmd, combo_rules.max_history);
+        }
+        // 名前付きコンボの成立判定。成立したら次のコンボは次のコマンドから数え直す
+        if let Some(pattern) = combo.matched() {
+            let bonus = pattern.bonus;
+            log.0.push(format!("コンボ「{}」成立!", pattern.name));
+            events
+                .0
+                .push(BattleEvent::ComboFinished { name: pattern.name });
+            if bonus.damage > 0 {
+                e_hp.current = (e_hp.current - bonus.damage).max(0);
+                enemy_damage_popup.amount += bonus.damage;
+                enemy_damage_popup.timer = 1.2;
+                e_bregen.amount = difficulty.break_regen_base();
+                events.0.push(BattleEvent::DamageDealt {
+                    source: DamageSource::Combo,
+                    amount: bonus.damage,
+                });
+                log.0.push(format!(
+                    "追加で敵に{}ダメージ (敵HP {} / {})",
+                    bonus.damage, e_hp.current, e_hp.max
+                ));
+            }
+            if bonus.break_value > 0 {
+                let before_break = e_break.current;
+                e_break.current += bonus.break_value;
+                events.0.push(BattleEvent::BreakAdded {
+                    amount: bonus.break_value,
+                });
+                log.0.push(format!(
+                    "ブレイク値 +{} ({} → {} / {})",
+                    bonus.break_value,
+                    before_break,
+                    e_break.current,
+                    difficulty.break_threshold()
+                ));
+            }
+            if bonus.stamina > 0 {
+                p_sta.current = (p_sta.current + bonus.stamina).min(p_sta.max);
+                log.0.push(format!(
+                    "スタミナ+{} (Stamina {} / {})",
+                    bonus.stamina, p_sta.current, p_sta.max
+                ));
+            }
+            if bonus.momentum > 0 {
+                let before = momentum.current;
+                momentum.current = (momentum.current + bonus.momentum).min(100);
+                log.0.push(format!(
+                    "モメンタムが{}増加 ({} → {} / 100)",
+                    momentum.current - before,
+                    before,
+                    momentum.current
+                ));
+            }
+            combo.combo_start = combo.history.len();
+        }
+        // ガードカウンター猶予の消費: 防御以外の行動で消費
+        if !matches!(cmd, CommandKind::Defend) {
+            guard.0 = false;
+        }
+    }
+
+    // プレイヤーの攻撃/強攻撃後にブレイク判定。閾値到達でこのターンの敵行動をキャンセルし、次ターンから4ターンブレイク。
+    let mut enemy_action_canceled_this_turn = false;
+    if e_break.current >= difficulty.break_threshold() && e_bstate.remaining_turns == 0 {
+        enemy_action_canceled_this_turn = true;
+        e_bstate.remaining_turns = 4; // 次ターンから4ターン行動不能
+        events.0.push(BattleEvent::BreakTriggered);
+        log.0.push(
+            "敵がブレイク状態に入る!（次のターンから4ターン行動不能・被ダメ2倍）".to_string(),
+        );
+    }
+
+    if e_hp.current > 0 {
+        // 事前決定済みの敵行動を実行
+        if e_bstate.remaining_turns > 0 {
+            // ブレイク中は行動不能
+            log.0.push("敵はブレイク中のため行動不能".to_string());
+        } else if enemy_action_canceled_this_turn {
+            // このターンの行動はキャンセル
+            log.0.push("敵の行動はブレイクによりキャンセル".to_string());
+        } else {
+            let action = &mut planned.0;
+            let step = action.current_step().unwrap();
+            match step.specification {
+                ActionStepSpecificationEnum::Attack(spec) => {
+                    let mut incoming = (e_attack.0 as f32 * spec.power) as i32;
+                    if defend.0 {
+                        incoming = 0;
+                        defend.0 = false; // 一度きり
+                    }
+                    p_hp.current = (p_hp.current - incoming).max(0);
+                    events.0.push(BattleEvent::DamageTaken {
+                        amount: incoming,
+                        step: step.name,
+                    });
+                    log.0.push(format!(
+                        "敵の行動: {} → {}ダメージ (プレイヤーHP {} / {})",
+                        step.name, incoming, p_hp.current, p_hp.max
+                    ));
+                    // 被ダメージでオーバードライブゲージ上昇（ダメージの3/4）
+                    if incoming > 0 && overdrive.gauge < OVERDRIVE_MAX {
+                        let before = overdrive.gauge;
+                        overdrive.gauge = (overdrive.gauge + incoming * 3 / 4).min(OVERDRIVE_MAX);
+                        if overdrive.gauge >= OVERDRIVE_MAX {
+                            log.0.push(
+                                "オーバードライブ準備完了! 次のバッチは選択数+1・最初のコマンドは消費なし"
+                                    .to_string(),
+                            );
+                        } else {
+                            log.0.push(format!(
+                                "オーバードライブゲージ +{} ({} / {})",
+                                overdrive.gauge - before,
+                                overdrive.gauge,
+                                OVERDRIVE_MAX
+                            ));
+                        }
+                    }
+                    if incoming > 0 && combo_rules.on_damage_taken && !combo.history.is_empty() {
+                        combo.reset();
+                        log.0.push("被弾によりコンボが途切れた".to_string());
+                    }
+                }
+                ActionStepSpecificationEnum::Wait(_) => {
+                    log.0.push(format!("敵の行動: {} (何もしない)", step.name));
+                }
+                ActionStepSpecificationEnum::Heal(spec) => {
+                    // プレイヤーがこのターンに攻撃していた場合、敵の回復量は半減
+                    let base_heal = spec.amount;
+                    let heal_amount = if matches!(
+                        cmd,
+                        CommandKind::Attack | CommandKind::Skill | CommandKind::Finisher
+                    ) {
+                        base_heal / 2
+                    } else {
+                        base_heal
+                    };
+                    let before = e_hp.current;
+                    e_hp.current = (e_hp.current + heal_amount).min(e_hp.max);
+                    let healed = e_hp.current - before;
+                    events.0.push(BattleEvent::EnemyHealed { amount: healed });
+                    log.0.push(format!(
+                        "敵の行動: {} → HPを{}回復 (敵HP {} / {})",
+                        step.name, healed, e_hp.current, e_hp.max
+                    ));
+                }
+            }
+            action.advance();
+        }
+    }
+    // 次ターンの敵行動を事前決定（敵が生きている場合）
+    if e_hp.current > 0 && p_hp.current > 0 {
+        if planned.0.is_finished() {
+            // 現在の行動が完了している場合、新たに行動を決定
+
+            // 2Pの予約があればそれ、なければ乱数（敵HPが半分以下なら、回復と大技を選択肢に含める）
+            let next = match enemy_choice.0.take() {
+                Some(action) => action,
+                None => {
+                    let roll: f32 = rng.0.random();
+                    pick_enemy_action(enemy_action_table(e_hp), difficulty.aggression(), roll)
+                }
+            };
+            events
+                .0
+                .push(BattleEvent::EnemyActionChosen { name: next.name });
+
+            // TODO: 毎回生成してるのやめる
+            planned.0 = ActionProcess::from(&Arc::new(next));
+        }
+        log.0.push(format!(
+            "次ターン敵行動予定: {}",
+            planned.revealed_name(*difficulty)
+        ));
+    }
+    // ターン終了時、ブレイク残りターンのデクリメント（ブレイク中のみ）。解除時にブレイク値リセット。
+    if e_bstate.remaining_turns > 0 {
+        e_bstate.remaining_turns = e_bstate.remaining_turns.saturating_sub(1);
+        if e_bstate.remaining_turns == 0 {
+            e_break.current = 0;
+            events.0.push(BattleEvent::BreakRecovered);
+            log.0
+                .push("敵のブレイク状態が解除。ブレイク値を0にリセット".to_string());
+            // 0になったので自然回復量もリセット
+            e_bregen.amount = difficulty.break_regen_base();
+        }
+    }
+    // ターン終了時、攻撃/強攻撃が無ければ自然回復: 1,2,4,...と倍増。0到達またはダメージ受けで1へリセット。
+    if !matches!(
+        cmd,
+        CommandKind::Attack | CommandKind::Skill | CommandKind::Finisher
+    ) {
+        let before = e_break.current;
+        e_break.current = (e_break.current - e_bregen.amount).max(0);
+        if e_break.current != before {
+            log.0.push(format!(
+                "敵のブレイク値が自然回復: {} → {} (回復量 {})",
+                before, e_break.current, e_bregen.amount
+            ));
+        }
+        if e_break.current == 0 {
+            e_bregen.amount = difficulty.break_regen_base();
+        } else {
+            e_bregen.amount = (e_bregen.amount * difficulty.break_regen_growth()).max(1);
+        }
+    }
+    // ターン終了時、強化の残りターンをデクリメント
+    let prev = (buffs.attack, buffs.skill, buffs.heal, buffs.defend);
+    if buffs.attack > 0 {
+        buffs.attack -= 1;
+        if buffs.attack == 0 && prev.0 > 0 {
+            log.0.push("攻撃の強化が解除された".to_string());
+        }
+    }
+    if buffs.skill > 0 {
+        buffs.skill -= 1;
+        if buffs.skill == 0 && prev.1 > 0 {
+            log.0.push("強攻撃の強化が解除された".to_string());
+        }
+    }
+    if buffs.heal > 0 {
+        buffs.heal -= 1;
+        if buffs.heal == 0 && prev.2 > 0 {
+            log.0.push("回復の強化が解除された".to_string());
+        }
+    }
+    if buffs.defend > 0 {
+        buffs.defend -= 1;
+        if buffs.defend == 0 && prev.3 > 0 {
+            log.0.push("防御の強化が解除された".to_string());
+        }
+    }
+    // オーバードライブは発動した1ターンだけ
+    end_overdrive(overdrive, log);
+    // ターン終了時、満タンでないオーバードライブゲージは少しずつ減少
+    if overdrive.gauge > 0 && overdrive.gauge < OVERDRIVE_MAX {
+        overdrive.gauge = (overdrive.gauge - 2).max(0);
+    }
+    turn.0 += 1;
+    *phase = BattlePhase::AwaitCommand;
+}
+
+// ================== Batch ==================
+// 選択確定時: バッチ総数と最大選択数を記録し、オーバードライブ準備完了ならこのバッチで発動
+pub fn start_batch(
+    batch: &mut ConsecutiveBatch,
+    overdrive: &mut Overdrive,
+    log: &mut CombatLog,
+    total: usize,
+    limit: usize,
+) {
+    batch.total = total;
+    batch.limit = limit;
+    batch.executed = 1; // 先頭はすぐに実行する
+    batch.cursor = 0;
+    batch.edited = false;
+    if overdrive.is_ready() {
+        overdrive.gauge = 0;
+        overdrive.active = true;
+        log.0
+            .push("オーバードライブ発動! このターンはスタミナ消費なし".to_string());
+    }
+}
+
+// 予約の先頭を実行する直前: バッチ内の位置で加算判定（2件目:+15, 3件目:+25, ...）
+pub fn advance_batch(batch: &mut ConsecutiveBatch, momentum: &mut Momentum, log: &mut CombatLog) {
+    let gain = batch.gain_at(0);
+    batch.executed += 1;
+    batch.cursor = 0;
+    let before = momentum.current;
+    momentum.current = (momentum.current + gain).min(100);
+    let gained = momentum.current - before;
+    if gained > 0 {
+        log.0.push(format!(
+            "モメンタムが{}増加 ({} → {} / 100)",
+            gained, before, momentum.current
+        ));
+    }
+}
+
+// 確認フェーズでの予約編集（ゲーム本体・端末版で共用。キーの割り当ては各フロントエンド）
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum QueueEdit {
+    CursorUp,
+    CursorDown,
+    SwapUp,
+    SwapDown,
+    Remove,
+    Replace(CommandKind),
+    Insert(CommandKind),
+}
+
+// 予約キューに edit を適用する。limit は確定時に固定した最大選択数（途中でオーバードライブが終わっても減らない）。
+// モメンタム増加はバッチ内の位置で決まる。置換・挿入・入れ替えをすると以降の増加は半減、削除のみなら変化なし。
+// 全て削除したらバッチ終了（呼び出し側でフェーズを戻す）
+pub fn apply_queue_edit(
+    queue: &mut std::collections::VecDeque<CommandKind>,
+    batch: &mut ConsecutiveBatch,
+    limit: usize,
+    log: &mut CombatLog,
+    edit: QueueEdit,
+) {
+    let len = queue.len();
+    if len == 0 {
+        return;
+    }
+    batch.cursor = batch.cursor.min(len - 1);
+    let cursor = batch.cursor;
+    match edit {
+        QueueEdit::CursorUp | QueueEdit::SwapUp if cursor > 0 => {
+            if edit == QueueEdit::SwapUp {
+                queue.swap(cursor, cursor - 1);
+                log.0.push(format!(
+                    "予約を入れ替え: {}件目と{}件目",
+                    batch.executed + cursor + 1,
+                    batch.executed + cursor
+                ));
+                mark_queue_edited(batch, log);
+            }
+            batch.cursor -= 1;
+        }
+        QueueEdit::CursorDown | QueueEdit::SwapDown if cursor + 1 < len => {
+            if edit == QueueEdit::SwapDown {
+                queue.swap(cursor, cursor + 1);
+                log.0.push(format!(
+                    "予約を入れ替え: {}件目と{}件目",
+                    batch.executed + cursor + 1,
+                    batch.executed + cursor + 2
+                ));
+                mark_queue_edited(batch, log);
+            }
+            batch.cursor += 1;
+        }
+        QueueEdit::Remove => {
+            if let Some(removed) = queue.remove(cursor) {
+                batch.total -= 1;
+                log.0.push(format!("予約を削除: {}", removed.label()));
+            }
+            batch.cursor = batch.cursor.min(queue.len().saturating_sub(1));
+            if queue.is_empty() {
+                batch.total = 0;
+                batch.executed = 0;
+            }
+        }
+        QueueEdit::Insert(cmd) => {
+            if batch.total >= limit {
+                log.0
+                    .push(format!("これ以上予約を追加できません (最大{limit}件)"));
+                return;
+            }
+            queue.insert(cursor, cmd);
+            batch.total += 1;
+            log.0.push(format!(
+                "予約を挿入: {}件目に{}",
+                batch.executed + cursor + 1,
+                cmd.label()
+            ));
+            mark_queue_edited(batch, log);
+        }
+        QueueEdit::Replace(cmd) => {
+            let old = std::mem::replace(&mut queue[cursor], cmd);
+            if old == cmd {
+                return;
+            }
+            log.0.push(format!(
+                "予約を置換: {}件目 {} → {}",
+                batch.executed + cursor + 1,
+                old.label(),
+                cmd.label()
+            ));
+            mark_queue_edited(batch, log);
+        }
+        _ => {}
+    }
+}
+
+fn mark_queue_edited(batch: &mut ConsecutiveBatch, log: &mut CombatLog) {
+    if !batch.edited {
+        batch.edited = true;
+        log.0
+            .push("計画変更: このバッチの以降のモメンタム増加は半減".to_string());
+    }
+}
+
+// オーバードライブ終了（発動したターンの終わり）
+fn end_overdrive(overdrive: &mut Overdrive, log: &mut CombatLog) {
+    if overdrive.active {
+        overdrive.active = false;
+        log.0.push("オーバードライブが終了した".to_string());
+    }
+}
+
+// 1バッチを確認なしで最後まで実行する（BattleState・ヘッドレスモード・予測で共用）。
+// limit は確定時の最大選択数（commands は呼び出し側で切り詰めておく）。
+// 1件解決するたびに after_command(ctx, 解決したターン, 解決前のモメンタム) を呼ぶ。
+// どちらかのHPが0になったら Finished にして残りを捨てる
+pub fn execute_batch(
+    mut ctx: BattleCtx<'_>,
+    batch: &mut ConsecutiveBatch,
+    commands: &[CommandKind],
+    limit: usize,
+    mut after_command: impl FnMut(&mut BattleCtx<'_>, u32, i32),
+) {
+    for i in 0..commands.len() {
+        let turn = ctx.turn.0;
+        let momentum_before = ctx.momentum.current;
+        let go_on = execute_batch_step(ctx.reborrow(), batch, commands, limit, i);
+        after_command(&mut ctx, turn, momentum_before);
+        if !go_on {
+            break;
+        }
+    }
+}
+
+// バッチの index 件目だけを解決する（先頭なら start_batch、2件目以降は advance_batch してから）。
+// どちらかのHPが0になったら Finished にして false を返す
+pub fn execute_batch_step(
+    mut ctx: BattleCtx<'_>,
+    batch: &mut ConsecutiveBatch,
+    commands: &[CommandKind],
+    limit: usize,
+    index: usize,
+) -> bool {
+    if index == 0 {
+        start_batch(batch, ctx.overdrive, ctx.log, commands.len(), limit);
+    } else {
+        advance_batch(batch, ctx.momentum, ctx.log);
+    }
+    resolve_command(ctx.reborrow(), commands[index]);
+    if ctx.p_hp.current <= 0 || ctx.e_hp.current <= 0 {
+        *ctx.phase = BattlePhase::Finished;
+        return false;
+    }
+    true
+}
+
+// ================== Difficulty ==================
+// 難易度プリセット（戦闘前に選び、シミュレーター・ヘッドレス出力・ネット対戦にも記録する）
+#[derive(Resource, Clone, Copy, PartialEq, Eq, Hash, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Difficulty {
+    Easy,
+    #[default]
+    Normal,
+    Hard,
+    Nightmare,
+}
+
+impl Difficulty {
+    pub const ALL: [Difficulty; 4] = [
+        Difficulty::Easy,
+        Difficulty::Normal,
+        Difficulty::Hard,
+        Difficulty::Nightmare,
+    ];
+
+    // コマンドライン・JSON用の名前
+    pub fn name(self) -> &'static str {
+        match self {
+            Difficulty::Easy => "easy",
+            Difficulty::Normal => "normal",
+            Difficulty::Hard => "hard",
+            Difficulty::Nightmare => "nightmare",
+        }
+    }
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|d| d.name() == name)
+    }
+    pub fn label(self) -> &'static str {
+        match self {
+            Difficulty::Easy => "イージー",
+            Difficulty::Normal => "ノーマル",
+            Difficulty::Hard => "ハード",
+            Difficulty::Nightmare => "ナイトメア",
+        }
+    }
+
+    // 敵の最大HP
+    pub fn enemy_hp(self) -> i32 {
+        match self {
+            Difficulty::Easy => 1000,
+            Difficulty::Normal => 1500,
+            Difficulty::Hard => 2000,
+            Difficulty::Nightmare => 2500,
+        }
+    }
+    // 敵の攻撃力
+    pub fn enemy_attack(self) -> i32 {
+        match self {
+            Difficulty::Easy => 30,
+            Difficulty::Normal => 40,
+            Difficulty::Hard => 50,
+            Difficulty::Nightmare => 60,
+        }
+    }
+    // ブレイクに必要なブレイク値
+    pub fn break_threshold(self) -> i32 {
+        match self {
+            Difficulty::Easy => 80,
+            Difficulty::Normal => 100,
+            Difficulty::Hard => 120,
+            Difficulty::Nightmare => 150,
+        }
+    }
+    // ブレイク値の自然回復: 初期量と毎ターンの倍率
+    pub fn break_regen_base(self) -> i32 {
+        match self {
+            Difficulty::Easy | Difficulty::Normal => 1,
+            Difficulty::Hard => 2,
+            Difficulty::Nightmare => 3,
+        }
+    }
+    pub fn break_regen_growth(self) -> i32 {
+        match self {
+            Difficulty::Easy => 1, // 倍増しない
+            Difficulty::Normal | Difficulty::Hard => 2,
+            Difficulty::Nightmare => 3,
+        }
+    }
+    // 敵AIの積極性（待機以外の行動の重み）
+    pub fn aggression(self) -> f32 {
+        match self {
+            Difficulty::Easy => 0.6,
+            Difficulty::Normal => 1.0,
+            Difficulty::Hard => 1.5,
+            Difficulty::Nightmare => 2.5,
+        }
+    }
+    // 敵の行動予定を何ステップ先まで見せるか（0なら次の行動も伏せる）
+    pub fn revealed_steps(self) -> usize {
+        match self {
+            Difficulty::Easy | Difficulty::Normal => usize::MAX,
+            Difficulty::Hard => 1,
+            Difficulty::Nightmare => 0,
+        }
+    }
+}
+
+// ================== Initial State ==================
+// プレイヤーの初期ステータス
+pub fn create_player() -> (Hp, Attack, Stamina, BatchLimit) {
+    (
+        Hp {
+            current: 100,
+            max: 100,
+        },
+        Attack(10),
+        Stamina {
+            current: 100,
+            max: 100,
+        },
+        BatchLimit { base: 3 },
+    )
+}
+
+// 敵（ドラゴン）の初期ステータス
+pub fn create_enemy(difficulty: Difficulty) -> (Hp, Attack, BreakValue, BreakState, BreakRegen) {
+    (
+        Hp {
+            current: difficulty.enemy_hp(),
+            max: difficulty.enemy_hp(),
+        },
+        Attack(difficulty.enemy_attack()),
+        BreakValue { current: 0 },
+        BreakState { remaining_turns: 0 },
+        BreakRegen {
+            amount: difficulty.break_regen_base(),
+        },
+    )
+}
+
+// 戦闘開始時の敵行動
+pub fn plan_opening_action(rng: &mut BattleRng, difficulty: Difficulty) -> EnemyPlannedAction {
+    let roll: f32 = rng.0.random();
+    let action = pick_enemy_action(ENEMY_ACTIONS_OPENING, difficulty.aggression(), roll);
+    EnemyPlannedAction(ActionProcess::from(&Arc::new(action)))
+}
+
+// ================== Headless Battle ==================
+// ECSを使わずに1戦分の状態をまとめて持つ（シミュレーターなど画面なしで回す用）
+#[derive(Clone)]
+pub struct BattleState {
+    pub phase: BattlePhase,
+    pub turn: Turn,
+    pub p_attack: Attack,
+    pub p_hp: Hp,
+    pub p_sta: Stamina,
+    pub p_limit: BatchLimit,
+    pub e_attack: Attack,
+    pub e_hp: Hp,
+    pub e_break: BreakValue,
+    pub e_bstate: BreakState,
+    pub e_bregen: BreakRegen,
+    pub log: CombatLog, // 直近のバッチ分のみ
+    pub defend: DefendNextAttack,
+    pub guard: GuardCounterReady,
+    pub combo: PlayerComboState,
+    pub combo_rules: ComboResetRules,
+    pub planned: EnemyPlannedAction,
+    pub momentum: Momentum,
+    pub overdrive: Overdrive,
+    pub buffs: CommandBuffs,
+    pub batch: ConsecutiveBatch,
+    pub enemy_damage_popup: EnemyDamagePopup,
+    pub finisher_cinematic: FinisherCinematic,
+    pub rng: BattleRng,
+    pub events: TurnEvents,
+    pub enemy_choice: EnemyMoveChoice,
+    pub difficulty: Difficulty,
+    pub stats: BattleStats,
+}
+
+// バッチの予測プレビュー1件分（BattleState::preview_batch）
+#[derive(Clone, Debug)]
+pub struct PreviewStep {
+    pub command: CommandKind,
+    pub warning: Option<&'static str>, // 失敗・不発になる場合の理由
+    pub shortage: bool,                // スタミナ・モメンタム不足で失敗・不発になる
+    pub stamina_after: i32,
+    pub damage: i32,
+    pub break_gain: i32,
+    pub break_triggered: bool,
+    pub enemy_defeated: bool,
+}
+
+impl BattleState {
+    pub fn new(seed: u64) -> Self {
+        Self::with_difficulty(seed, Difficulty::Normal)
+    }
+
+    pub fn with_difficulty(seed: u64, difficulty: Difficulty) -> Self {
+        let mut rng = BattleRng::seeded(seed);
+        let planned = plan_opening_action(&mut rng, difficulty);
+        let (p_hp, p_attack, p_sta, p_limit) = create_player();
+        let (e_hp, e_attack, e_break, e_bstate, e_bregen) = create_enemy(difficulty);
+        BattleState {
+            phase: BattlePhase::AwaitCommand,
+            turn: Turn(1),
+            p_attack,
+            p_hp,
+            p_sta,
+            p_limit,
+            e_attack,
+            e_hp,
+            e_break,
+            e_bstate,
+            e_bregen,
+            log: CombatLog(Vec::new()),
+            defend: DefendNextAttack::default(),
+            guard: GuardCounterReady::default(),
+            combo: PlayerComboState::default(),
+            combo_rules: ComboResetRules::default(),
+            planned,
+            momentum: Momentum::default(),
+            overdrive: Overdrive::default(),
+            buffs: CommandBuffs::default(),
+            batch: ConsecutiveBatch::default(),
+            enemy_damage_popup: EnemyDamagePopup::default(),
+            finisher_cinematic: FinisherCinematic::default(),
+            rng,
+            events: TurnEvents::default(),
+            enemy_choice: EnemyMoveChoice::default(),
+            difficulty,
+            stats: BattleStats {
+                difficulty,
+                ..BattleStats::default()
+            },
+        }
+    }
+
+    pub fn ctx(&mut self) -> BattleCtx<'_> {
+        BattleCtx {
+            phase: &mut self.phase,
+            turn: &mut self.turn,
+            p_attack: &self.p_attack,
+            p_hp: &mut self.p_hp,
+            p_sta: &mut self.p_sta,
+            e_attack: &self.e_attack,
+            e_hp: &mut self.e_hp,
+            e_break: &mut self.e_break,
+            e_bstate: &mut self.e_bstate,
+            e_bregen: &mut self.e_bregen,
+            log: &mut self.log,
+            defend: &mut self.defend,
+            guard: &mut self.guard,
+            combo: &mut self.combo,
+            combo_rules: &self.combo_rules,
+            planned: &mut self.planned,
+            momentum: &mut self.momentum,
+            overdrive: &mut self.overdrive,
+            buffs: &mut self.buffs,
+            enemy_damage_popup: &mut self.enemy_damage_popup,
+            finisher_cinematic: &mut self.finisher_cinematic,
+            rng: &mut self.rng,
+            events: &mut self.events,
+            enemy_choice: &mut self.enemy_choice,
+            difficulty: &self.difficulty,
+        }
+    }
+
+    // 現在の最大選択数
+    pub fn max_select(&self) -> usize {
+        self.p_limit.current(&self.momentum, &self.overdrive)
+    }
+
+    pub fn is_over(&self) -> bool {
+        self.p_hp.current <= 0 || self.e_hp.current <= 0
+    }
+
+    pub fn player_won(&self) -> bool {
+        self.e_hp.current <= 0 && self.p_hp.current > 0
+    }
+
+    // 対戦の同期確認用。同じシード・同じ入力なら両端で同じ値になる
+    pub fn checksum(&self) -> u64 {
+        ChecksumSource {
+            turn: &self.turn,
+            p_hp: &self.p_hp,
+            p_sta: &self.p_sta,
+            e_hp: &self.e_hp,
+            e_break: &self.e_break,
+            e_bstate: &self.e_bstate,
+            e_bregen: &self.e_bregen,
+            momentum: &self.momentum,
+            overdrive: &self.overdrive,
+            buffs: &self.buffs,
+            defend: &self.defend,
+            guard: &self.guard,
+            combo: &self.combo,
+            planned: &self.planned,
+            rng: &self.rng,
+            difficulty: self.difficulty,
+        }
+        .hash()
+    }
+
+    // 1バッチを確認なしで最後まで実行し、起きた BattleEvent を返す（最大選択数を超えた分は捨てる）
+    pub fn run_batch(&mut self, commands: &[CommandKind]) -> Vec<BattleEvent> {
+        self.run_batch_with(commands, |_| {})
+    }
+
+    // run_batch と同じだが、1件解決するたびに on_command(ctx) を呼ぶ（探索で敵の行動選択を差し込む用）
+    pub fn run_batch_with(
+        &mut self,
+        commands: &[CommandKind],
+        mut on_command: impl FnMut(&mut BattleCtx<'_>),
+    ) -> Vec<BattleEvent> {
+        let mut out = Vec::new();
+        let limit = self.max_select();
+        let commands = &commands[..commands.len().min(limit)];
+        if commands.is_empty() || self.is_over() {
+            return out;
+        }
+        self.log.0.clear();
+        // ctx() が self 全体を借りるので、バッチ状態と集計は一旦取り出す
+        let mut batch = std::mem::take(&mut self.batch);
+        let mut stats = std::mem::take(&mut self.stats);
+        execute_batch(
+            self.ctx(),
+            &mut batch,
+            commands,
+            limit,
+            |ctx, _, momentum_before| {
+                stats.record_turn(
+                    &ctx.events.0,
+                    momentum_before,
+                    ctx.momentum.current,
+                    ctx.e_bstate.remaining_turns > 0,
+                );
+                on_command(ctx);
+                out.append(&mut ctx.events.0);
+            },
+        );
+        self.batch = batch;
+        self.stats = stats;
+        out
+    }
+
+    // バッチの index 件目だけを実行する（探索がバッチの途中の状態から分岐する用。集計はしない）。
+    // index 0 から順に呼ぶこと。まだ続けられるなら true
+    pub fn run_batch_step(&mut self, commands: &[CommandKind], index: usize) -> bool {
+        let limit = if index == 0 {
+            self.max_select()
+        } else {
+            self.batch.limit
+        };
+        if index >= commands.len().min(limit) || self.is_over() {
+            return false;
+        }
+        if index == 0 {
+            self.log.0.clear();
+        }
+        let mut batch = std::mem::take(&mut self.batch);
+        let go_on = execute_batch_step(self.ctx(), &mut batch, commands, limit, index);
+        self.batch = batch;
+        go_on
+    }
+
+    // 保留中のバッチを確定した場合の見込み（状態は変えない）。
+    // 敵は見えている行動予定どおりに動くものとし、伏せられたステップとその先の行動は「何もしない」とみなす
+    pub fn preview_batch(&self, commands: &[CommandKind]) -> Vec<PreviewStep> {
+        let mut sim = self.clone();
+        let limit = sim.max_select();
+        let commands = &commands[..commands.len().min(limit)];
+        let mut out = Vec::new();
+        if sim.is_over() {
+            return out;
+        }
+        sim.planned = self.planned.revealed(self.difficulty);
+        sim.enemy_choice = EnemyMoveChoice(Some(unknown_enemy_action(commands.len())));
+        let mut batch = std::mem::take(&mut sim.batch);
+        execute_batch(sim.ctx(), &mut batch, commands, limit, |ctx, _, _| {
+            let mut step = PreviewStep {
+                command: commands[out.len()],
+                warning: None,
+                shortage: false,
+                stamina_after: ctx.p_sta.current,
+                damage: 0,
+                break_gain: 0,
+                break_triggered: false,
+                enemy_defeated: ctx.e_hp.current <= 0,
+            };
+            for event in ctx.events.0.drain(..) {
+                match event {
+                    BattleEvent::StaminaShortage { .. } => {
+                        step.warning = Some("スタミナ不足で失敗します");
+                        step.shortage = true;
+                    }
+                    BattleEvent::MomentumShortage { .. } => {
+                        step.warning = Some("モメンタム不足で不発になります");
+                        step.shortage = true;
+                    }
+                    BattleEvent::AlreadyEnhanced { .. } => {
+                        step.warning = Some("強化中のため無駄になります")
+                    }
+                    BattleEvent::DamageDealt { amount, .. } => step.damage += amount,
+                    BattleEvent::BreakAdded { amount } => step.break_gain += amount,
+                    BattleEvent::BreakTriggered => step.break_triggered = true,
+                    _ => {}
+                }
+            }
+            out.push(step);
+        });
+        out
+    }
+
+    // 選択中の pending に続けて cmd を選べるか（選択数に収まり、スタミナ・モメンタムが足りる）
+    pub fn can_afford_next(&self, pending: &[CommandKind], cmd: CommandKind) -> bool {
+        let mut commands = pending.to_vec();
+        commands.push(cmd);
+        let steps = self.preview_batch(&commands);
+        steps.len() == commands.len() && steps.last().is_some_and(|s| !s.shortage)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BATCHES: &[&[CommandKind]] = &[
+        &[CommandKind::Defend, CommandKind::Skill, CommandKind::Attack],
+        &[CommandKind::Attack, CommandKind::Attack],
+        &[CommandKind::Wait],
+        &[CommandKind::EnhanceAttack, CommandKind::Attack],
+        &[CommandKind::Heal, CommandKind::Skill, CommandKind::Attack],
+    ];
+
+    // 同じシード・同じ入力で BATCHES を繰り返したときのバッチごとのチェックサム
+    fn checksums(seed: u64, difficulty: Difficulty) -> Vec<u64> {
+        let mut state = BattleState::with_difficulty(seed, difficulty);
+        let mut out = vec![state.checksum()];
+        for batch in BATCHES.iter().cycle().take(40) {
+            if state.is_over() {
+                break;
+            }
+            state.run_batch(batch);
+            out.push(state.checksum());
+        }
+        out
+    }
+
+    #[test]
+    fn batch_steps_match_run_batch() {
+        let mut whole = BattleState::new(11);
+        let mut stepped = whole.clone();
+        for batch in BATCHES.iter().cycle().take(20) {
+            if whole.is_over() {
+                break;
+            }
+            whole.run_batch(batch);
+            let mut i = 0;
+            while stepped.run_batch_step(batch, i) {
+                i += 1;
+            }
+            assert_eq!(whole.checksum(), stepped.checksum());
+        }
+    }
+
+    #[test]
+    fn checksum_is_deterministic() {
+        for difficulty in Difficulty::ALL {
+            for seed in [0, 1, 42] {
+                assert_eq!(checksums(seed, difficulty), checksums(seed, difficulty));
+            }
+        }
+    }
+
+    #[test]
+    fn checksum_tracks_state_changes() {
+        let mut attacked = BattleState::new(7);
+        let mut waited = attacked.clone();
+        assert_eq!(attacked.checksum(), waited.checksum());
+        attacked.run_batch(&[CommandKind::Attack]);
+        waited.run_batch(&[CommandKind::Wait]);
+        assert_ne!(attacked.checksum(), waited.checksum());
+    }
+
+    #[test]
+    fn ctx_checksum_matches_state() {
+        let mut state = BattleState::new(5);
+        let mut last = 0;
+        state.run_batch_with(&[CommandKind::Attack, CommandKind::Skill], |ctx| {
+            last = ctx.checksum();
+        });
+        assert_eq!(last, state.checksum());
+    }
+
+    #[test]
+    fn preview_does_not_change_state() {
+        let mut state = BattleState::new(3);
+        state.run_batch(&[CommandKind::Attack, CommandKind::Attack]);
+        let before = state.checksum();
+        state.preview_batch(&[CommandKind::Skill, CommandKind::Attack, CommandKind::Heal]);
+        assert_eq!(state.checksum(), before);
+    }
+}
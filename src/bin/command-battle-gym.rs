@@ -0,0 +1,120 @@
+// ================== Gym JSON-lines Server ==================
+// 標準入力から1行1リクエストのJSONを読み、1行1レスポンスのJSONを標準出力に返す（学習スクリプト用）
+//
+//   {"type":"spec"}
+//   {"type":"reset","seed":42,"difficulty":"hard"}   difficulty は省略で normal
+//   {"type":"step","action":["defend","skill",0]}   コマンド名か CommandKind::ALL の番号
+//
+// 応答: reset/step は {"observation":{...},"features":[...],"reward":0.0,"done":false}
+//       不正なリクエストは {"error":"..."}
+use std::io::{BufRead, Write};
+
+use command_battle::battle::{CommandKind, Difficulty, MAX_BATCH_LIMIT};
+use command_battle::gym::{BattleEnv, CommandRef, FEATURE_LEN, Observation, parse_batch};
+use serde::{Deserialize, Serialize};
+
+const DEFAULT_MAX_TURNS: u32 = 500;
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Request {
+    Spec,
+    Reset {
+        #[serde(default)]
+        seed: u64,
+        #[serde(default)]
+        difficulty: Option<String>,
+    },
+    Step {
+        action: Vec<CommandRef>,
+    },
+}
+
+#[derive(Serialize)]
+struct Spec {
+    commands: Vec<&'static str>,
+    difficulties: Vec<&'static str>,
+    max_batch: usize,
+    feature_len: usize,
+    max_turns: u32,
+}
+
+#[derive(Serialize)]
+struct StepResponse {
+    observation: Observation,
+    features: Vec<f32>,
+    reward: f32,
+    done: bool,
+}
+
+#[derive(Serialize)]
+struct ErrorResponse {
+    error: String,
+}
+
+fn handle(env: &mut BattleEnv, line: &str) -> Result<String, String> {
+    let request: Request = serde_json::from_str(line).map_err(|e| e.to_string())?;
+    let json = match request {
+        Request::Spec => serde_json::to_string(&Spec {
+            commands: CommandKind::ALL.iter().map(|c| c.name()).collect(),
+            difficulties: Difficulty::ALL.iter().map(|d| d.name()).collect(),
+            max_batch: MAX_BATCH_LIMIT,
+            feature_len: FEATURE_LEN,
+            max_turns: env.max_turns,
+        }),
+        Request::Reset { seed, difficulty } => {
+            let difficulty = match difficulty {
+                Some(name) => {
+                    Difficulty::from_name(&name).ok_or_else(|| format!("不明な難易度「{name}」"))?
+                }
+                None => Difficulty::Normal,
+            };
+            let observation = env.reset(seed, difficulty);
+            serde_json::to_string(&StepResponse {
+                features: observation.to_features(),
+                observation,
+                reward: 0.0,
+                done: false,
+            })
+        }
+        Request::Step { action } => {
+            let action = parse_batch(&action)?;
+            let (observation, reward, done) = env.step(&action);
+            serde_json::to_string(&StepResponse {
+                features: observation.to_features(),
+                observation,
+                reward,
+                done,
+            })
+        }
+    };
+    json.map_err(|e| e.to_string())
+}
+
+fn main() {
+    let max_turns = std::env::args()
+        .skip_while(|a| a != "--max-turns")
+        .nth(1)
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_MAX_TURNS);
+    let mut env = BattleEnv::new(max_turns);
+    let stdin = std::io::stdin();
+    let mut stdout = std::io::stdout().lock();
+    for line in stdin.lock().lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let out = handle(&mut env, &line).unwrap_or_else(|error| {
+            serde_json::to_string(&ErrorResponse { error }).unwrap_or_default()
+        });
+        if writeln!(stdout, "{out}")
+            .and_then(|_| stdout.flush())
+            .is_err()
+        {
+            break;
+        }
+    }
+}
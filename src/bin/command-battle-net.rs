@@ -0,0 +1,540 @@
+// ================== Network PvP (Lockstep) ==================
+// TCPで2人対戦。片方が勇者、もう片方がドラゴンを操作する
+//
+//   ホスト: command-battle-net host 0.0.0.0:7878 [--role hero|dragon] [--seed N] [--difficulty hard]
+//   参加:   command-battle-net join 192.168.0.2:7878
+//
+// 送るのはコマンドの選択だけ（1行1メッセージのJSON）。戦闘ルールは両端で BattleState を同じシードから動かす
+//   1ラウンド = 勇者の1バッチ + ドラゴンが予約する行動（空ならシード乱数で選ぶ）
+//   ラウンドごとに各ターン後のチェックサムを交換し、食い違ったら同期ずれとして終了する
+//   切断されたら再接続を待ち、双方が合意済みのラウンドまで入力履歴を再生して再開する
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+
+use command_battle::battle::*;
+use serde::{Deserialize, Serialize};
+
+const PROTOCOL_VERSION: u32 = 1;
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Role {
+    Hero,
+    Dragon,
+}
+
+impl Role {
+    fn other(self) -> Role {
+        match self {
+            Role::Hero => Role::Dragon,
+            Role::Dragon => Role::Hero,
+        }
+    }
+    fn label(self) -> &'static str {
+        match self {
+            Role::Hero => "勇者",
+            Role::Dragon => "ドラゴン",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum NetMessage {
+    // 接続ごとの最初のメッセージ。agreed は合意済みのラウンド数
+    // （初回の参加側はシードも役割も難易度もまだ知らないので None）
+    Hello {
+        version: u32,
+        seed: Option<u64>,
+        role: Option<Role>,
+        difficulty: Option<Difficulty>,
+        agreed: usize,
+    },
+    // 自分の担当分の選択（勇者はコマンド名、ドラゴンは行動名）
+    Input {
+        round: usize,
+        choices: Vec<String>,
+    },
+    // ラウンド内の各ターン後のチェックサム
+    Check {
+        round: usize,
+        checksums: Vec<u64>,
+    },
+}
+
+// 1ラウンド分の入力（両端で同じものを持つ）
+#[derive(Clone, Debug, Default)]
+struct RoundInput {
+    hero: Vec<CommandKind>,
+    dragon: Vec<String>,
+}
+
+enum NetError {
+    Disconnected(io::Error), // 再接続して再開する
+    Fatal(String),           // 同期ずれ・設定の不一致など
+}
+
+impl From<io::Error> for NetError {
+    fn from(e: io::Error) -> Self {
+        NetError::Disconnected(e)
+    }
+}
+
+enum Mode {
+    Host(TcpListener),
+    Join(String),
+}
+
+struct Session {
+    mode: Mode,
+    role: Role,
+    seed: Option<u64>, // 参加側は最初の接続でホストから受け取る
+    difficulty: Difficulty,
+    history: Vec<RoundInput>,
+    // 送信前後に切断されたときに入れ直さなくて済むよう、自分の入力を取っておく
+    pending: Option<(usize, Vec<String>)>,
+}
+
+struct Peer {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+}
+
+impl Peer {
+    fn new(stream: TcpStream) -> io::Result<Self> {
+        stream.set_nodelay(true)?;
+        Ok(Peer {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+        })
+    }
+
+    fn send(&mut self, msg: &NetMessage) -> Result<(), NetError> {
+        let json = serde_json::to_string(msg).map_err(|e| NetError::Fatal(e.to_string()))?;
+        writeln!(self.writer, "{json}")?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn recv(&mut self) -> Result<NetMessage, NetError> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        serde_json::from_str(&line).map_err(|e| NetError::Fatal(format!("不正なメッセージ: {e}")))
+    }
+}
+
+// 1ラウンドを実行して各ターン後のチェックサムを返す（両端で同じ結果になる）
+fn apply_round(state: &mut BattleState, input: &RoundInput) -> Vec<u64> {
+    let mut checksums = Vec::new();
+    let mut moves: VecDeque<Action> = input
+        .dragon
+        .iter()
+        .filter_map(|name| enemy_move_by_name(name))
+        .collect();
+    // 予約を1つずつ渡す（敵が次の行動を選ぶたびに次の予約を置く）
+    if state.enemy_choice.0.is_none() {
+        state.enemy_choice.0 = moves.pop_front();
+    }
+    state.run_batch_with(&input.hero, |ctx| {
+        checksums.push(ctx.checksum());
+        if ctx.enemy_choice.0.is_none() {
+            ctx.enemy_choice.0 = moves.pop_front();
+        }
+    });
+    // 使われなかった予約は次のラウンドに持ち越さない
+    state.enemy_choice = EnemyMoveChoice::default();
+    checksums
+}
+
+fn connect(mode: &Mode) -> io::Result<TcpStream> {
+    match mode {
+        Mode::Host(listener) => {
+            println!("接続を待っています ({})...", listener.local_addr()?);
+            let (stream, addr) = listener.accept()?;
+            println!("{addr} が接続しました");
+            Ok(stream)
+        }
+        Mode::Join(addr) => loop {
+            match TcpStream::connect(addr) {
+                Ok(stream) => {
+                    println!("{addr} に接続しました");
+                    return Ok(stream);
+                }
+                Err(e) => {
+                    println!("{addr} に接続できません ({e})。再試行します...");
+                    std::thread::sleep(RECONNECT_INTERVAL);
+                }
+            }
+        },
+    }
+}
+
+// 接続直後のやりとり。合意済みラウンド数を決めて、そこまで履歴を戻す
+fn handshake(session: &mut Session, peer: &mut Peer) -> Result<(), NetError> {
+    let is_host = matches!(session.mode, Mode::Host(_));
+    peer.send(&NetMessage::Hello {
+        version: PROTOCOL_VERSION,
+        seed: session.seed,
+        role: session.seed.map(|_| session.role),
+        difficulty: session.seed.map(|_| session.difficulty),
+        agreed: session.history.len(),
+    })?;
+    let NetMessage::Hello {
+        version,
+        seed,
+        role,
+        difficulty,
+        agreed,
+    } = peer.recv()?
+    else {
+        return Err(NetError::Fatal(
+            "最初のメッセージが Hello ではありません".into(),
+        ));
+    };
+    if version != PROTOCOL_VERSION {
+        return Err(NetError::Fatal(format!(
+            "プロトコルのバージョンが違います (自分 {PROTOCOL_VERSION} / 相手 {version})"
+        )));
+    }
+    if !is_host && session.seed.is_none() {
+        // 初回の参加: シードと役割はホストに合わせる
+        let (Some(seed), Some(role), Some(difficulty)) = (seed, role, difficulty) else {
+            return Err(NetError::Fatal("ホストからシードが届きません".into()));
+        };
+        session.seed = Some(seed);
+        session.role = role.other();
+        session.difficulty = difficulty;
+        println!(
+            "あなたは{}です (難易度 {})",
+            session.role.label(),
+            difficulty.label()
+        );
+    } else if seed.is_some_and(|s| session.seed != Some(s)) {
+        return Err(NetError::Fatal(
+            "別の対戦に接続しようとしています（シード不一致）".into(),
+        ));
+    } else if difficulty.is_some_and(|d| d != session.difficulty) {
+        return Err(NetError::Fatal("相手と難易度が違います".into()));
+    } else if role.is_some_and(|r| r != session.role.other()) {
+        return Err(NetError::Fatal("相手と役割が重なっています".into()));
+    }
+    // 片方だけが最後のチェックを受け取って切れた場合があるので、少ない方に合わせる
+    let agreed = if seed.is_none() {
+        0 // 相手が最初から参加し直した
+    } else {
+        agreed.min(session.history.len())
+    };
+    if agreed < session.history.len() {
+        println!("ラウンド{}から再開します", agreed + 1);
+    }
+    session.history.truncate(agreed);
+    Ok(())
+}
+
+fn print_state(state: &BattleState, role: Role) {
+    println!();
+    println!(
+        "---- ターン {} ----------------------------------------",
+        state.turn.0
+    );
+    for text in &state.log.0 {
+        println!("  {text}");
+    }
+    let broken = if state.e_bstate.remaining_turns > 0 {
+        format!(" ブレイク中(残り{})", state.e_bstate.remaining_turns)
+    } else {
+        String::new()
+    };
+    // ドラゴン側には自分の行動予定を全部見せる
+    let shown = match role {
+        Role::Hero => state.difficulty,
+        Role::Dragon => Difficulty::Easy,
+    };
+    println!(
+        "ドラゴン  HP {}/{}  BREAK {}/{}{}  次の行動: {} ({})",
+        state.e_hp.current,
+        state.e_hp.max,
+        state.e_break.current,
+        state.difficulty.break_threshold(),
+        broken,
+        state.planned.revealed_name(shown),
+        state.planned.revealed_action_name(shown)
+    );
+    println!(
+        "勇者      HP {}/{}  スタミナ {}/{}  モメンタム {}/100  オーバードライブ {}/{}",
+        state.p_hp.current,
+        state.p_hp.max,
+        state.p_sta.current,
+        state.p_sta.max,
+        state.momentum.current,
+        state.overdrive.gauge,
+        OVERDRIVE_MAX
+    );
+    if role == Role::Dragon
+        && let Some(choice) = &state.enemy_choice.0
+    {
+        println!("予約済みの次の行動: {}", choice.name);
+    }
+}
+
+fn prompt(text: &str) -> io::Result<String> {
+    print!("{text}");
+    io::stdout().flush()?;
+    let mut line = String::new();
+    if io::stdin().read_line(&mut line)? == 0 {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof));
+    }
+    Ok(line.trim().to_string())
+}
+
+// 自分の担当分の入力を読み、送信用の名前の列にする
+fn read_local_input(state: &BattleState, role: Role) -> Result<Vec<String>, NetError> {
+    // 標準入力が閉じたら対戦をやめる
+    let read = |text: &str| prompt(text).map_err(|_| NetError::Fatal("入力が終了しました".into()));
+    match role {
+        Role::Hero => {
+            let max_select = state.max_select();
+            loop {
+                let line = read(&format!(
+                    "コマンド (最大{max_select}件 例: dsa) A=攻撃 S=強攻撃 H=回復 D=防御 W=待機 Z/X/C/V=強化 F=奥義 > "
+                ))?;
+                let commands: Option<Vec<CommandKind>> = line
+                    .chars()
+                    .filter(|c| !c.is_whitespace())
+                    .map(CommandKind::from_key)
+                    .collect();
+                match commands {
+                    Some(c) if !c.is_empty() && c.len() <= max_select => {
+                        return Ok(c.iter().map(|c| c.name().to_string()).collect());
+                    }
+                    Some(c) if c.len() > max_select => {
+                        println!("これ以上選択を追加できません (最大{max_select}件)");
+                    }
+                    _ => println!("コマンドを入力してください"),
+                }
+            }
+        }
+        Role::Dragon => {
+            let table = enemy_action_table(&state.e_hp);
+            let menu = table
+                .iter()
+                .enumerate()
+                .map(|(i, (_, create))| format!("{}={}", i + 1, create().name))
+                .collect::<Vec<_>>()
+                .join(" ");
+            loop {
+                let line = read(&format!(
+                    "次に使う行動を順に予約 (例: 2 1、空なら運まかせ) {menu} > "
+                ))?;
+                let moves: Option<Vec<String>> = line
+                    .split_whitespace()
+                    .map(|n| {
+                        let i = n.parse::<usize>().ok()?.checked_sub(1)?;
+                        table.get(i).map(|(_, create)| create().name.to_string())
+                    })
+                    .collect();
+                match moves {
+                    Some(m) => return Ok(m),
+                    None => println!("1〜{} の番号で入力してください", table.len()),
+                }
+            }
+        }
+    }
+}
+
+// 1接続分の対戦。決着すれば Ok
+fn play(session: &mut Session, peer: &mut Peer) -> Result<(), NetError> {
+    handshake(session, peer)?;
+    let seed = session.seed.unwrap_or(0);
+    let mut state = BattleState::with_difficulty(seed, session.difficulty);
+    for input in &session.history {
+        apply_round(&mut state, input);
+    }
+    if session.history.is_empty() {
+        let first = state.planned.revealed_name(state.difficulty);
+        state
+            .log
+            .0
+            .push(format!("難易度: {}", state.difficulty.label()));
+        state.log.0.push(format!("初期敵行動: {first}"));
+    }
+
+    while !state.is_over() {
+        let round = session.history.len();
+        print_state(&state, session.role);
+        let mine = match session.pending.take() {
+            Some((r, choices)) if r == round => choices,
+            _ => read_local_input(&state, session.role)?,
+        };
+        session.pending = Some((round, mine.clone()));
+        peer.send(&NetMessage::Input {
+            round,
+            choices: mine.clone(),
+        })?;
+        println!("相手の選択を待っています...");
+        let theirs = match peer.recv()? {
+            NetMessage::Input { round: r, choices } if r == round => choices,
+            other => {
+                return Err(NetError::Fatal(format!(
+                    "ラウンド{round}の入力を待っていましたが {other:?} を受信しました"
+                )));
+            }
+        };
+        let (hero, dragon) = match session.role {
+            Role::Hero => (mine, theirs),
+            Role::Dragon => (theirs, mine),
+        };
+        let input = RoundInput {
+            hero: hero
+                .iter()
+                .map(|n| {
+                    CommandKind::from_name(n)
+                        .ok_or_else(|| NetError::Fatal(format!("不明なコマンド「{n}」")))
+                })
+                .collect::<Result<_, _>>()?,
+            dragon,
+        };
+
+        let checksums = apply_round(&mut state, &input);
+        peer.send(&NetMessage::Check {
+            round,
+            checksums: checksums.clone(),
+        })?;
+        match peer.recv()? {
+            NetMessage::Check {
+                round: r,
+                checksums: theirs,
+            } if r == round => {
+                if let Some(turn) = checksums.iter().zip(&theirs).position(|(a, b)| a != b) {
+                    return Err(NetError::Fatal(format!(
+                        "同期ずれ: ラウンド{} の{}ターン目で状態が一致しません",
+                        round + 1,
+                        turn + 1
+                    )));
+                }
+                if checksums.len() != theirs.len() {
+                    return Err(NetError::Fatal(format!(
+                        "同期ずれ: ラウンド{} のターン数が一致しません",
+                        round + 1
+                    )));
+                }
+            }
+            other => {
+                return Err(NetError::Fatal(format!(
+                    "ラウンド{round}のチェックサムを待っていましたが {other:?} を受信しました"
+                )));
+            }
+        }
+        session.history.push(input);
+        session.pending = None;
+    }
+
+    print_state(&state, session.role);
+    let won = match session.role {
+        Role::Hero => state.player_won(),
+        Role::Dragon => !state.player_won(),
+    };
+    println!();
+    println!(
+        "{} ({}ラウンド)",
+        if won {
+            "あなたの勝利!"
+        } else {
+            "あなたの敗北..."
+        },
+        session.history.len()
+    );
+    Ok(())
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: command-battle-net host <addr> [--role hero|dragon] [--seed N] [--difficulty easy|normal|hard|nightmare]"
+    );
+    eprintln!("       command-battle-net join <addr>");
+    std::process::exit(2);
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let (Some(command), Some(addr)) = (args.first(), args.get(1)) else {
+        usage();
+    };
+    let option = |name: &str| {
+        args.iter()
+            .skip_while(|a| *a != name)
+            .nth(1)
+            .map(String::as_str)
+    };
+    let mut session = match command.as_str() {
+        "host" => {
+            let role = match option("--role") {
+                None | Some("hero") => Role::Hero,
+                Some("dragon") => Role::Dragon,
+                Some(other) => {
+                    eprintln!("不明な役割「{other}」");
+                    usage();
+                }
+            };
+            let seed = match option("--seed") {
+                Some(v) => v.parse().unwrap_or_else(|_| usage()),
+                None => rand::random(),
+            };
+            let difficulty = match option("--difficulty") {
+                Some(v) => Difficulty::from_name(v).unwrap_or_else(|| usage()),
+                None => Difficulty::Normal,
+            };
+            let listener = TcpListener::bind(addr).unwrap_or_else(|e| {
+                eprintln!("{addr} で待ち受けできません: {e}");
+                std::process::exit(1);
+            });
+            println!(
+                "あなたは{}です (シード {seed} / 難易度 {})",
+                role.label(),
+                difficulty.label()
+            );
+            Session {
+                mode: Mode::Host(listener),
+                role,
+                seed: Some(seed),
+                difficulty,
+                history: Vec::new(),
+                pending: None,
+            }
+        }
+        "join" => Session {
+            mode: Mode::Join(addr.clone()),
+            role: Role::Dragon, // ホストの役割を受け取ったら決め直す
+            seed: None,
+            difficulty: Difficulty::Normal,
+            history: Vec::new(),
+            pending: None,
+        },
+        _ => usage(),
+    };
+
+    loop {
+        let result = connect(&session.mode)
+            .and_then(Peer::new)
+            .map_err(NetError::Disconnected)
+            .and_then(|mut peer| play(&mut session, &mut peer));
+        match result {
+            Ok(()) => break,
+            Err(NetError::Disconnected(e)) => {
+                println!("接続が切れました ({e})。再接続して合意済みのラウンドから再開します");
+                if matches!(session.mode, Mode::Join(_)) {
+                    std::thread::sleep(RECONNECT_INTERVAL);
+                }
+            }
+            Err(NetError::Fatal(message)) => {
+                eprintln!("{message}");
+                std::process::exit(1);
+            }
+        }
+    }
+}
@@ -0,0 +1,353 @@
+// ================== Batch Balance Simulator ==================
+// 画面なしでドラゴン戦を大量に回し、勝率・撃破ターン数・ダメージ内訳・ブレイク回数・敵行動の頻度を集計する
+//
+//   cargo run --release --bin command-battle-sim -- --policy greedy --runs 5000 --seed 1
+//
+// ポリシー:
+//   random                 毎バッチ、件数もコマンドもランダム
+//   greedy                 スタミナの範囲で最大火力（モメンタム100なら奥義）を選択数いっぱいに詰める
+//   scripted[:DSA/AAA/W]   キー文字(A S H D W Z X C V F)のバッチを / 区切りで順に繰り返す
+//   gambit:<file>          ガンビットファイルのルールでバッチを選ぶ（ゲーム内のオートバトルと同じ）
+//   search[:深さ[:長さ]]    実際のルールで先読みする探索プレイヤー（敵の行動選択は候補表の確率で分岐する expectimax）。
+//                          長さは1段目で試すバッチの最大長（既定3）。遅いので --release 推奨
+use std::collections::HashMap;
+use std::process::exit;
+
+use command_battle::battle::*;
+use command_battle::gambit::{GambitScript, GambitView};
+use command_battle::search::SearchPlayer;
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+
+const USAGE: &str = "使い方: command-battle-sim [--policy random|greedy|scripted[:DSA/AAA/W]|gambit:<file>|search[:depth[:len]]] [--runs N] [--seed S] [--max-turns T] [--difficulty easy|normal|hard|nightmare]";
+const DEFAULT_SCRIPT: &str = "DSA/AAA/W";
+
+enum Policy {
+    Random,
+    Greedy,
+    Scripted(Vec<Vec<CommandKind>>),
+    Gambit(GambitScript),
+    Search(SearchPlayer),
+}
+
+impl Policy {
+    fn parse(s: &str) -> Result<Self, String> {
+        let (name, arg) = match s.split_once(':') {
+            Some((n, a)) => (n, Some(a)),
+            None => (s, None),
+        };
+        match (name, arg) {
+            ("random", None) => Ok(Policy::Random),
+            ("greedy", None) => Ok(Policy::Greedy),
+            ("scripted", script) => parse_script(script.unwrap_or(DEFAULT_SCRIPT)),
+            ("gambit", Some(path)) => {
+                let src = std::fs::read_to_string(path)
+                    .map_err(|e| format!("ガンビットを読めません ({path}): {e}"))?;
+                Ok(Policy::Gambit(GambitScript::parse(&src)?))
+            }
+            ("search", arg) => {
+                let mut player = SearchPlayer::default();
+                let mut parts = arg.into_iter().flat_map(|a| a.split(':'));
+                if let Some(d) = parts.next() {
+                    player.depth = d
+                        .parse()
+                        .ok()
+                        .filter(|&d| d >= 1)
+                        .ok_or_else(|| format!("探索の深さが不正です「{d}」"))?;
+                }
+                if let Some(l) = parts.next() {
+                    player.max_len = l
+                        .parse()
+                        .ok()
+                        .filter(|&l| l >= 1)
+                        .ok_or_else(|| format!("探索のバッチ長が不正です「{l}」"))?;
+                }
+                Ok(Policy::Search(player))
+            }
+            _ => Err(format!("不明なポリシー「{s}」")),
+        }
+    }
+
+    // 次に確定するバッチ（step はこの戦闘で何バッチ目か）
+    fn choose(&self, state: &BattleState, rng: &mut StdRng, step: usize) -> Vec<CommandKind> {
+        let limit = state.max_select();
+        match self {
+            Policy::Random => {
+                let len = rng.random_range(1..=limit);
+                (0..len)
+                    .map(|_| CommandKind::ALL[rng.random_range(0..CommandKind::ALL.len())])
+                    .collect()
+            }
+            Policy::Greedy => greedy_batch(state, limit),
+            Policy::Scripted(batches) => batches[step % batches.len()].clone(),
+            Policy::Gambit(script) => {
+                let view = GambitView::from_state(state);
+                let mut batch = script
+                    .choose(&view)
+                    .map_or_else(|| vec![CommandKind::Wait], |(_, g)| g.commands.clone());
+                batch.truncate(limit);
+                batch
+            }
+            Policy::Search(player) => player.plan(state),
+        }
+    }
+}
+
+fn parse_script(src: &str) -> Result<Policy, String> {
+    let mut batches = Vec::new();
+    for part in src.split('/') {
+        let batch = part
+            .chars()
+            .map(|c| {
+                CommandKind::from_key(c).ok_or_else(|| format!("スクリプトに不明なキー「{c}」"))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if batch.is_empty() {
+            return Err("スクリプトに空のバッチがあります".to_string());
+        }
+        batches.push(batch);
+    }
+    Ok(Policy::Scripted(batches))
+}
+
+// スタミナとモメンタムを手元で見積もりながら、その時点で一番ダメージの大きいコマンドを詰める
+fn greedy_batch(state: &BattleState, limit: usize) -> Vec<CommandKind> {
+    // オーバードライブは最初の1件だけ消費なし
+    let mut free = state.overdrive.active || state.overdrive.is_ready();
+    let mut stamina = state.p_sta.current;
+    let mut momentum = state.momentum.current;
+    let mut last = state.combo.last();
+    let mut batch = Vec::new();
+    for pos in 1..=limit {
+        momentum = (momentum + momentum_gain_for(pos)).min(100);
+        let chain = matches!(last, Some(CommandKind::Attack | CommandKind::Skill));
+        let attack_cost = if chain { 5 } else { 15 };
+        let (cmd, cost) = if momentum >= 100 {
+            momentum = 0;
+            (CommandKind::Finisher, 0)
+        } else if free || stamina >= 25 {
+            (CommandKind::Skill, 25)
+        } else if stamina >= attack_cost {
+            (CommandKind::Attack, attack_cost)
+        } else {
+            stamina = (stamina + 60).min(state.p_sta.max);
+            (CommandKind::Wait, 0)
+        };
+        if !free {
+            stamina -= cost;
+        }
+        free = false;
+        last = Some(cmd);
+        batch.push(cmd);
+    }
+    batch
+}
+
+#[derive(Default)]
+struct Report {
+    runs: u32,
+    wins: u32,
+    losses: u32,
+    timeouts: u32,
+    win_turns: Vec<u32>,
+    damage: HashMap<DamageSource, (i64, u32)>, // (合計, 回数)
+    breaks: u32,
+    stamina_shortages: u32,
+    commands: HashMap<CommandKind, u32>,
+    enemy_actions: HashMap<&'static str, u32>,
+}
+
+impl Report {
+    fn record(&mut self, events: &[BattleEvent]) {
+        for ev in events {
+            match *ev {
+                BattleEvent::DamageDealt { source, amount } => {
+                    let e = self.damage.entry(source).or_default();
+                    e.0 += amount as i64;
+                    e.1 += 1;
+                }
+                BattleEvent::BreakTriggered => self.breaks += 1,
+                BattleEvent::StaminaShortage { .. } => self.stamina_shortages += 1,
+                BattleEvent::CommandExecuted { command } => {
+                    *self.commands.entry(command).or_default() += 1
+                }
+                BattleEvent::EnemyActionChosen { name } => {
+                    *self.enemy_actions.entry(name).or_default() += 1
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn print(&self, policy: &str, difficulty: Difficulty, seed: u64, max_turns: u32) {
+        let runs = self.runs.max(1) as f64;
+        println!(
+            "ポリシー: {policy} / 難易度 {} / 試行 {} 回 / シード {seed}〜 / 最大{max_turns}ターン",
+            difficulty.label(),
+            self.runs
+        );
+        println!(
+            "勝率: {:.1}% (勝ち {} / 負け {} / 時間切れ {})",
+            self.wins as f64 * 100.0 / runs,
+            self.wins,
+            self.losses,
+            self.timeouts
+        );
+
+        println!("\n[撃破ターン数]");
+        if self.win_turns.is_empty() {
+            println!("  勝利なし");
+        } else {
+            let mut turns = self.win_turns.clone();
+            turns.sort_unstable();
+            let at = |q: f64| turns[((turns.len() - 1) as f64 * q).round() as usize];
+            let avg = turns.iter().map(|&t| t as f64).sum::<f64>() / turns.len() as f64;
+            println!(
+                "  最短 {} / 25% {} / 中央値 {} / 75% {} / 最長 {} / 平均 {:.1}",
+                at(0.0),
+                at(0.25),
+                at(0.5),
+                at(0.75),
+                at(1.0),
+                avg
+            );
+            // 10ターン刻みのヒストグラム
+            const BUCKET: u32 = 10;
+            let mut buckets: Vec<(u32, u32)> = Vec::new();
+            for &t in &turns {
+                let b = t / BUCKET * BUCKET;
+                match buckets.last_mut() {
+                    Some((start, n)) if *start == b => *n += 1,
+                    _ => buckets.push((b, 1)),
+                }
+            }
+            let peak = buckets.iter().map(|&(_, n)| n).max().unwrap_or(1);
+            for (start, n) in buckets {
+                let bar = "#".repeat(((n * 40).div_ceil(peak)) as usize);
+                println!("  {:>4}-{:<4} {:>6} {}", start, start + BUCKET - 1, n, bar);
+            }
+        }
+
+        println!("\n[与ダメージ内訳]");
+        let total: i64 = self.damage.values().map(|&(d, _)| d).sum();
+        let mut damage: Vec<_> = self.damage.iter().collect();
+        damage.sort_by_key(|&(_, &(sum, _))| std::cmp::Reverse(sum));
+        for (source, &(sum, hits)) in damage {
+            println!(
+                "  {:>5.1}% 1戦平均 {:>7.1} {:>7}回  {}",
+                sum as f64 * 100.0 / total.max(1) as f64,
+                sum as f64 / runs,
+                hits,
+                source.label()
+            );
+        }
+
+        println!("\n[ブレイク]");
+        println!(
+            "  発生 {} 回 (1戦平均 {:.2}) / スタミナ不足 {} 回",
+            self.breaks,
+            self.breaks as f64 / runs,
+            self.stamina_shortages
+        );
+
+        // 待機/防御ばかりのような偏った戦い方を見つける用
+        println!("\n[コマンド実行回数]");
+        let executed: u32 = self.commands.values().sum();
+        let mut commands: Vec<_> = self.commands.iter().collect();
+        commands.sort_by(|a, b| b.1.cmp(a.1).then(a.0.label().cmp(b.0.label())));
+        for (cmd, &n) in commands {
+            println!(
+                "  {:>5.1}% {:>7}回  {}",
+                n as f64 * 100.0 / executed.max(1) as f64,
+                n,
+                cmd.label()
+            );
+        }
+
+        println!("\n[敵の行動選択]");
+        let chosen: u32 = self.enemy_actions.values().sum();
+        let mut actions: Vec<_> = self.enemy_actions.iter().collect();
+        actions.sort_by(|a, b| b.1.cmp(a.1).then(a.0.cmp(b.0)));
+        for (name, &n) in actions {
+            println!(
+                "  {:>5.1}% {:>7}回  {}",
+                n as f64 * 100.0 / chosen.max(1) as f64,
+                n,
+                name
+            );
+        }
+    }
+}
+
+fn main() {
+    let mut policy_name = "greedy".to_string();
+    let mut runs: u32 = 1000;
+    let mut seed: u64 = 0;
+    let mut max_turns: u32 = 500;
+    let mut difficulty = Difficulty::Normal;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == "-h" || arg == "--help" {
+            println!("{USAGE}");
+            return;
+        }
+        let Some(value) = args.next() else {
+            eprintln!("{arg} の値がありません\n{USAGE}");
+            exit(2);
+        };
+        let parsed = match arg.as_str() {
+            "--policy" => {
+                policy_name = value.clone();
+                Ok(())
+            }
+            "--runs" => value.parse().map(|v| runs = v).map_err(|_| ()),
+            "--seed" => value.parse().map(|v| seed = v).map_err(|_| ()),
+            "--max-turns" => value.parse().map(|v| max_turns = v).map_err(|_| ()),
+            "--difficulty" => Difficulty::from_name(&value)
+                .map(|v| difficulty = v)
+                .ok_or(()),
+            _ => {
+                eprintln!("不明なオプション「{arg}」\n{USAGE}");
+                exit(2);
+            }
+        };
+        if parsed.is_err() {
+            eprintln!("{arg} の値が不正です「{value}」");
+            exit(2);
+        }
+    }
+    let policy = Policy::parse(&policy_name).unwrap_or_else(|e| {
+        eprintln!("{e}\n{USAGE}");
+        exit(2);
+    });
+
+    let mut report = Report::default();
+    for i in 0..runs {
+        // 1戦ごとにシードをずらす（敵の乱数とポリシーの乱数は別系列）
+        let run_seed = seed.wrapping_add(i as u64);
+        let mut state = BattleState::with_difficulty(run_seed, difficulty);
+        let mut policy_rng = StdRng::seed_from_u64(run_seed ^ 0x5EED_5EED);
+        *report
+            .enemy_actions
+            .entry(state.planned.0.action.name)
+            .or_default() += 1;
+        let mut step = 0;
+        while !state.is_over() && state.turn.0 <= max_turns {
+            let batch = policy.choose(&state, &mut policy_rng, step);
+            let events = state.run_batch(&batch);
+            report.record(&events);
+            step += 1;
+        }
+        report.runs += 1;
+        if state.player_won() {
+            report.wins += 1;
+            report.win_turns.push(state.turn.0 - 1);
+        } else if state.is_over() {
+            report.losses += 1;
+        } else {
+            report.timeouts += 1;
+        }
+    }
+    report.print(&policy_name, difficulty, seed, max_turns);
+}
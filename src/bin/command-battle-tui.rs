@@ -0,0 +1,663 @@
+// ================== Terminal Frontend ==================
+// ウィンドウを開けない環境（SSHなど）向けの端末版。ルールは BattleState、操作はゲーム本体と同じ
+//   コマンドの1文字キーは CommandKind::key()、それ以外のキーは KEYS の表（画面の操作説明も KEYS から作る）
+//   戦闘前に 1〜4 で難易度を選ぶ / Q=終了 / 決着後 R=もう一度（難易度選択に戻る）
+#[cfg(target_arch = "wasm32")]
+fn main() {}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn main() -> std::io::Result<()> {
+    tui::run()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+mod tui {
+    use std::collections::VecDeque;
+    use std::io::{Write, stdout};
+
+    use command_battle::battle::*;
+    use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+    use crossterm::style::{Color, Print, ResetColor, SetForegroundColor};
+    use crossterm::terminal::{self, Clear, ClearType};
+    use crossterm::{cursor, execute, queue};
+
+    const LOG_LINES: usize = 10;
+    const GAUGE_WIDTH: usize = 30;
+
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    enum TuiAction {
+        Select,
+        Undo,
+        Clear,
+        Decide,
+        Execute,
+        Reselect,
+        Edit(EditKey),
+    }
+
+    // 確認フェーズの編集（コマンドを伴うものはキーから決まる）
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    enum EditKey {
+        Cursor,
+        Swap,
+        Remove,
+        Replace,
+        Insert,
+    }
+
+    enum Keys {
+        Codes(&'static [KeyCode]),
+        // CommandKind::key() の文字（大文字はShift扱い）
+        Command,
+    }
+
+    struct KeyBinding {
+        phase: BattlePhase,
+        keys: Keys,
+        shift: Option<bool>, // None=Shiftの有無を問わない
+        action: TuiAction,
+        label: &'static str,
+        help: &'static str,
+    }
+
+    // 戦闘中のキー割り当て（上から順に判定する）
+    const KEYS: &[KeyBinding] = &[
+        KeyBinding {
+            phase: BattlePhase::AwaitCommand,
+            keys: Keys::Command,
+            shift: None,
+            action: TuiAction::Select,
+            label: "コマンドキー",
+            help: "選択",
+        },
+        KeyBinding {
+            phase: BattlePhase::AwaitCommand,
+            keys: Keys::Codes(&[KeyCode::Backspace]),
+            shift: None,
+            action: TuiAction::Undo,
+            label: "Backspace",
+            help: "取消",
+        },
+        KeyBinding {
+            phase: BattlePhase::AwaitCommand,
+            keys: Keys::Codes(&[KeyCode::Esc]),
+            shift: None,
+            action: TuiAction::Clear,
+            label: "Esc",
+            help: "全クリア",
+        },
+        KeyBinding {
+            phase: BattlePhase::AwaitCommand,
+            keys: Keys::Codes(&[KeyCode::Enter]),
+            shift: None,
+            action: TuiAction::Decide,
+            label: "Enter",
+            help: "決定",
+        },
+        KeyBinding {
+            phase: BattlePhase::ConfirmQueued,
+            keys: Keys::Codes(&[KeyCode::Char('y'), KeyCode::Char('Y'), KeyCode::Enter]),
+            shift: None,
+            action: TuiAction::Execute,
+            label: "Y/Enter",
+            help: "実行",
+        },
+        KeyBinding {
+            phase: BattlePhase::ConfirmQueued,
+            keys: Keys::Codes(&[KeyCode::Char('n'), KeyCode::Char('N'), KeyCode::Esc]),
+            shift: None,
+            action: TuiAction::Reselect,
+            label: "N/Esc",
+            help: "選び直し",
+        },
+        KeyBinding {
+            phase: BattlePhase::ConfirmQueued,
+            keys: Keys::Codes(&[KeyCode::Up, KeyCode::Down]),
+            shift: Some(false),
+            action: TuiAction::Edit(EditKey::Cursor),
+            label: "↑↓",
+            help: "カーソル",
+        },
+        KeyBinding {
+            phase: BattlePhase::ConfirmQueued,
+            keys: Keys::Codes(&[KeyCode::Up, KeyCode::Down]),
+            shift: Some(true),
+            action: TuiAction::Edit(EditKey::Swap),
+            label: "Shift+↑↓",
+            help: "入れ替え",
+        },
+        KeyBinding {
+            phase: BattlePhase::ConfirmQueued,
+            keys: Keys::Command,
+            shift: Some(false),
+            action: TuiAction::Edit(EditKey::Replace),
+            label: "コマンドキー",
+            help: "置換",
+        },
+        KeyBinding {
+            phase: BattlePhase::ConfirmQueued,
+            keys: Keys::Command,
+            shift: Some(true),
+            action: TuiAction::Edit(EditKey::Insert),
+            label: "Shift+コマンドキー",
+            help: "挿入",
+        },
+        KeyBinding {
+            phase: BattlePhase::ConfirmQueued,
+            keys: Keys::Codes(&[KeyCode::Backspace, KeyCode::Delete]),
+            shift: None,
+            action: TuiAction::Edit(EditKey::Remove),
+            label: "Backspace/Delete",
+            help: "削除",
+        },
+    ];
+
+    // 押されたキーに対応する操作（コマンドを伴うならそのコマンドも）
+    fn lookup(phase: &BattlePhase, key: &KeyEvent) -> Option<(TuiAction, Option<CommandKind>)> {
+        let shift = key.modifiers.contains(KeyModifiers::SHIFT)
+            || matches!(key.code, KeyCode::Char(ch) if ch.is_ascii_uppercase());
+        let command = match key.code {
+            KeyCode::Char(ch) => CommandKind::from_key(ch),
+            _ => None,
+        };
+        KEYS.iter()
+            .filter(|b| b.phase == *phase && b.shift.is_none_or(|s| s == shift))
+            .find_map(|b| match b.keys {
+                Keys::Codes(codes) if codes.contains(&key.code) => Some((b.action, None)),
+                Keys::Command if command.is_some() => Some((b.action, command)),
+                _ => None,
+            })
+    }
+
+    // 操作説明の1行（KEYS から作る）
+    fn key_help(phase: &BattlePhase) -> String {
+        KEYS.iter()
+            .filter(|b| b.phase == *phase)
+            .map(|b| format!("{}={}", b.label, b.help))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    struct Tui {
+        state: BattleState,
+        pending: Vec<CommandKind>,
+        queue: VecDeque<CommandKind>,
+    }
+
+    pub fn run() -> std::io::Result<()> {
+        terminal::enable_raw_mode()?;
+        let mut out = stdout();
+        execute!(out, terminal::EnterAlternateScreen, cursor::Hide)?;
+        let result = main_loop(&mut out);
+        execute!(out, cursor::Show, terminal::LeaveAlternateScreen)?;
+        terminal::disable_raw_mode()?;
+        result
+    }
+
+    fn new_battle(difficulty: Difficulty) -> Tui {
+        let mut state = BattleState::with_difficulty(rand::random(), difficulty);
+        let first = state.planned.revealed_name(difficulty);
+        state.log.0.push(format!("難易度: {}", difficulty.label()));
+        state.log.0.push(format!("初期敵行動: {first}"));
+        state.log.0.push("コマンドを選択してください".to_string());
+        Tui {
+            state,
+            pending: Vec::new(),
+            queue: VecDeque::new(),
+        }
+    }
+
+    fn main_loop(out: &mut impl Write) -> std::io::Result<()> {
+        // None の間は難易度選択画面
+        let mut battle: Option<Tui> = None;
+        loop {
+            match &battle {
+                Some(tui) => draw(out, tui)?,
+                None => draw_difficulty_select(out)?,
+            }
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            if key.code == KeyCode::Char('q')
+                || (key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL))
+            {
+                return Ok(());
+            }
+            let Some(tui) = &mut battle else {
+                if let KeyCode::Char(ch) = key.code
+                    && let Some(&difficulty) = ch
+                        .to_digit(10)
+                        .and_then(|n| Difficulty::ALL.get((n as usize).checked_sub(1)?))
+                {
+                    battle = Some(new_battle(difficulty));
+                }
+                continue;
+            };
+            if tui.state.phase == BattlePhase::Finished {
+                if key.code == KeyCode::Char('r') {
+                    battle = None;
+                }
+                continue;
+            }
+            if let Some((action, command)) = lookup(&tui.state.phase, &key) {
+                if tui.state.phase == BattlePhase::ConfirmQueued {
+                    confirm_input(tui, action, command, key.code == KeyCode::Up);
+                } else {
+                    select_input(tui, action, command);
+                }
+            }
+        }
+    }
+
+    // コマンド選択（ゲーム本体の AwaitCommand と同じ）
+    fn select_input(tui: &mut Tui, action: TuiAction, command: Option<CommandKind>) {
+        let max_select = tui.state.max_select();
+        match action {
+            TuiAction::Clear => tui.pending.clear(),
+            TuiAction::Undo => {
+                tui.pending.pop();
+            }
+            TuiAction::Decide if !tui.pending.is_empty() => {
+                let selected = std::mem::take(&mut tui.pending);
+                let names = selected
+                    .iter()
+                    .map(|c| c.label())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let s = &mut tui.state;
+                s.log.0.push(format!("選択確定: {names}"));
+                start_batch(
+                    &mut s.batch,
+                    &mut s.overdrive,
+                    &mut s.log,
+                    selected.len(),
+                    max_select,
+                );
+                tui.queue.extend(selected.iter().skip(1));
+                if selected.len() > 1 {
+                    s.log
+                        .0
+                        .push(format!("{}件のコマンドを予約", selected.len() - 1));
+                }
+                execute(tui, selected[0]);
+            }
+            TuiAction::Select => {
+                if let Some(cmd) = command {
+                    if tui.pending.len() < max_select {
+                        tui.pending.push(cmd);
+                    } else {
+                        tui.state
+                            .log
+                            .0
+                            .push(format!("これ以上選択を追加できません (最大{max_select}件)"));
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // 連続コマンド確認（実行 / 選び直し / 予約の編集。編集の上限は確定時に固定した最大選択数）
+    // up は↑で押されたか（カーソル移動・入れ替えの向き）
+    fn confirm_input(tui: &mut Tui, action: TuiAction, command: Option<CommandKind>, up: bool) {
+        match action {
+            TuiAction::Execute => {
+                if let Some(next) = tui.queue.pop_front() {
+                    let s = &mut tui.state;
+                    advance_batch(&mut s.batch, &mut s.momentum, &mut s.log);
+                    execute(tui, next);
+                }
+            }
+            TuiAction::Reselect => {
+                tui.queue.clear();
+                let s = &mut tui.state;
+                s.batch.total = 0;
+                s.batch.executed = 0;
+                s.batch.cursor = 0;
+                s.batch.edited = false;
+                if s.combo_rules.on_queue_reset {
+                    s.combo.reset();
+                }
+                s.log.0.push(
+                    "連続コマンドの予約をリセットしました。コマンドを選び直してください"
+                        .to_string(),
+                );
+                s.phase = BattlePhase::AwaitCommand;
+            }
+            TuiAction::Edit(key) => {
+                let s = &mut tui.state;
+                let edit = match (key, command) {
+                    (EditKey::Cursor, _) if up => QueueEdit::CursorUp,
+                    (EditKey::Cursor, _) => QueueEdit::CursorDown,
+                    (EditKey::Swap, _) if up => QueueEdit::SwapUp,
+                    (EditKey::Swap, _) => QueueEdit::SwapDown,
+                    (EditKey::Remove, _) => QueueEdit::Remove,
+                    (EditKey::Replace, Some(cmd)) => QueueEdit::Replace(cmd),
+                    (EditKey::Insert, Some(cmd)) => QueueEdit::Insert(cmd),
+                    _ => return,
+                };
+                let limit = s.batch.limit;
+                apply_queue_edit(&mut tui.queue, &mut s.batch, limit, &mut s.log, edit);
+                if tui.queue.is_empty() {
+                    s.phase = BattlePhase::AwaitCommand;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // 1ターン解決して、決着・予約の有無でフェーズを決める
+    fn execute(tui: &mut Tui, cmd: CommandKind) {
+        let s = &mut tui.state;
+        resolve_command(s.ctx(), cmd);
+        if s.e_hp.current <= 0 {
+            s.phase = BattlePhase::Finished;
+            s.log.0.push("勝利! 敵を倒しました".to_string());
+        } else if s.p_hp.current <= 0 {
+            s.phase = BattlePhase::Finished;
+            s.log.0.push("敗北... プレイヤーのHPが0です".to_string());
+        } else if !tui.queue.is_empty() {
+            s.phase = BattlePhase::ConfirmQueued;
+        }
+        if s.phase == BattlePhase::Finished {
+            tui.queue.clear();
+        }
+    }
+
+    fn gauge(current: i32, max: i32) -> String {
+        let filled = if max > 0 {
+            (current.clamp(0, max) as usize * GAUGE_WIDTH) / max as usize
+        } else {
+            0
+        };
+        format!(
+            "[{}{}]",
+            "#".repeat(filled),
+            "-".repeat(GAUGE_WIDTH - filled)
+        )
+    }
+
+    fn line(out: &mut impl Write, row: &mut u16, color: Color, text: &str) -> std::io::Result<()> {
+        queue!(
+            out,
+            cursor::MoveTo(0, *row),
+            SetForegroundColor(color),
+            Print(text),
+            ResetColor
+        )?;
+        *row += 1;
+        Ok(())
+    }
+
+    fn draw_difficulty_select(out: &mut impl Write) -> std::io::Result<()> {
+        let mut row = 0;
+        queue!(out, Clear(ClearType::All))?;
+        line(out, &mut row, Color::Red, "ドラゴン討伐")?;
+        row += 1;
+        line(out, &mut row, Color::White, "難易度を選んでください")?;
+        for (i, d) in Difficulty::ALL.iter().enumerate() {
+            line(
+                out,
+                &mut row,
+                Color::White,
+                &format!(
+                    "  {}={:<8} 敵HP {}  攻撃力 {}  ブレイク {}",
+                    i + 1,
+                    d.label(),
+                    d.enemy_hp(),
+                    d.enemy_attack(),
+                    d.break_threshold()
+                ),
+            )?;
+        }
+        row += 1;
+        line(out, &mut row, Color::Green, "1〜4=決定 Q=終了")?;
+        out.flush()
+    }
+
+    fn draw(out: &mut impl Write, tui: &Tui) -> std::io::Result<()> {
+        let s = &tui.state;
+        let mut row = 0;
+        queue!(out, Clear(ClearType::All))?;
+
+        // 敵
+        line(
+            out,
+            &mut row,
+            Color::Red,
+            &format!("ドラゴン [{}]", s.difficulty.label()),
+        )?;
+        line(
+            out,
+            &mut row,
+            Color::White,
+            &format!(
+                "  HP    {} {} / {}",
+                gauge(s.e_hp.current, s.e_hp.max),
+                s.e_hp.current,
+                s.e_hp.max
+            ),
+        )?;
+        let broken = if s.e_bstate.remaining_turns > 0 {
+            format!("  ブレイク中 (残り{}ターン)", s.e_bstate.remaining_turns)
+        } else {
+            String::new()
+        };
+        line(
+            out,
+            &mut row,
+            Color::Yellow,
+            &format!(
+                "  BREAK {} {} / {}{}",
+                gauge(s.e_break.current, s.difficulty.break_threshold()),
+                s.e_break.current,
+                s.difficulty.break_threshold(),
+                broken
+            ),
+        )?;
+        let next = if s.phase == BattlePhase::Finished {
+            "-"
+        } else {
+            s.planned.revealed_name(s.difficulty)
+        };
+        line(
+            out,
+            &mut row,
+            Color::White,
+            &format!(
+                "  次の行動: {next} ({})",
+                s.planned.revealed_action_name(s.difficulty)
+            ),
+        )?;
+        row += 1;
+
+        // プレイヤー
+        line(out, &mut row, Color::Cyan, "プレイヤー")?;
+        line(
+            out,
+            &mut row,
+            Color::White,
+            &format!(
+                "  HP {} / {}   スタミナ {} / {}   モメンタム {} / 100   オーバードライブ {} / {}{}",
+                s.p_hp.current,
+                s.p_hp.max,
+                s.p_sta.current,
+                s.p_sta.max,
+                s.momentum.current,
+                s.overdrive.gauge,
+                OVERDRIVE_MAX,
+                if s.overdrive.active {
+                    " (発動中)"
+                } else {
+                    ""
+                }
+            ),
+        )?;
+        let buffs = [
+            ("攻撃", s.buffs.attack),
+            ("強攻撃", s.buffs.skill),
+            ("回復", s.buffs.heal),
+            ("防御", s.buffs.defend),
+        ]
+        .iter()
+        .filter(|(_, t)| *t > 0)
+        .map(|(n, t)| format!("{n}強化{t}"))
+        .collect::<Vec<_>>();
+        let mut status = Vec::new();
+        if !buffs.is_empty() {
+            status.push(buffs.join(" "));
+        }
+        if s.defend.0 {
+            status.push("防御中".to_string());
+        }
+        if s.guard.0 {
+            status.push("ガードカウンター".to_string());
+        }
+        if let Some((pattern, rest)) = s.combo.in_progress() {
+            let rest = rest.iter().map(|c| c.label()).collect::<Vec<_>>().join("→");
+            status.push(format!("コンボ「{}」あと {rest}", pattern.name));
+        }
+        line(
+            out,
+            &mut row,
+            Color::White,
+            &format!("  {}", status.join(" / ")),
+        )?;
+        row += 1;
+
+        // コマンド
+        line(
+            out,
+            &mut row,
+            Color::Green,
+            &CommandKind::ALL
+                .iter()
+                .map(|c| format!("{}={}", c.key().to_ascii_uppercase(), c.label()))
+                .collect::<Vec<_>>()
+                .join(" "),
+        )?;
+        if s.phase != BattlePhase::Finished {
+            line(
+                out,
+                &mut row,
+                Color::Green,
+                &format!("{} / Q=終了", key_help(&s.phase)),
+            )?;
+        }
+        match s.phase {
+            BattlePhase::Finished => {
+                let result = if s.player_won() {
+                    "勝利!"
+                } else {
+                    "敗北..."
+                };
+                line(
+                    out,
+                    &mut row,
+                    Color::Yellow,
+                    &format!("{result}  R=もう一度 Q=終了"),
+                )?;
+            }
+            BattlePhase::ConfirmQueued => {
+                line(
+                    out,
+                    &mut row,
+                    Color::Yellow,
+                    &format!(
+                        "[連続コマンド確認] {}/{} (上限{}件)",
+                        s.batch.executed + 1,
+                        s.batch.total,
+                        s.batch.limit
+                    ),
+                )?;
+                // 予約の一覧（> が編集カーソル）
+                for (i, cmd) in tui.queue.iter().enumerate() {
+                    let mark = if i == s.batch.cursor { ">" } else { " " };
+                    let next = if i == 0 { "  ← 次に実行" } else { "" };
+                    line(
+                        out,
+                        &mut row,
+                        Color::White,
+                        &format!(
+                            " {mark} {}. {}  (モメンタム+{}){next}",
+                            s.batch.executed + i + 1,
+                            cmd.label(),
+                            s.batch.gain_at(i)
+                        ),
+                    )?;
+                }
+                if s.batch.edited {
+                    line(
+                        out,
+                        &mut row,
+                        Color::Grey,
+                        "  計画変更済み: 以降のモメンタム増加は半減",
+                    )?;
+                }
+            }
+            _ => {
+                let selected = tui
+                    .pending
+                    .iter()
+                    .map(|c| c.label())
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let od = if s.overdrive.is_ready() {
+                    "  オーバードライブ準備完了"
+                } else {
+                    ""
+                };
+                line(
+                    out,
+                    &mut row,
+                    Color::White,
+                    &format!(
+                        "選択中 ({}/{}): {selected}{od}",
+                        tui.pending.len(),
+                        s.max_select()
+                    ),
+                )?;
+                // 選択中バッチの予測（実際のルールで試し打ち）
+                let preview = s.preview_batch(&tui.pending);
+                for (i, step) in preview.iter().enumerate() {
+                    let mut text = format!(
+                        "  {}. {}  スタミナ→{}  ダメージ{}  ブレイク+{}",
+                        i + 1,
+                        step.command.label(),
+                        step.stamina_after,
+                        step.damage,
+                        step.break_gain
+                    );
+                    if step.break_triggered {
+                        text.push_str("  ブレイク発生!");
+                    }
+                    if step.enemy_defeated {
+                        text.push_str("  撃破!");
+                    }
+                    line(out, &mut row, Color::Grey, &text)?;
+                }
+                for (i, step) in preview.iter().enumerate() {
+                    if let Some(warning) = step.warning {
+                        line(
+                            out,
+                            &mut row,
+                            Color::Red,
+                            &format!("  {}つ目は{warning}", i + 1),
+                        )?;
+                    }
+                }
+            }
+        }
+        row += 1;
+
+        // ログ（最新 LOG_LINES 行）
+        let start = s.log.0.len().saturating_sub(LOG_LINES);
+        for text in &s.log.0[start..] {
+            line(out, &mut row, Color::Grey, text)?;
+        }
+        out.flush()
+    }
+}
@@ -0,0 +1,380 @@
+// ================== Gambit (条件付きコマンドスクリプト) ==================
+// 1行1ルール: 「条件 & 条件 => コマンド, コマンド, ...」。上から順に評価し、最初に全条件を満たしたルールの
+// コマンド列を1バッチとして確定する（最大選択数を超えた分は捨てる）。
+//
+//   # コメント
+//   enemy_attack & stamina>=10 => defend
+//   enemy_broken => skill, attack, attack
+//   always => attack
+//
+// 条件（! で否定）:
+//   always / enemy_attack / enemy_wait / enemy_heal / enemy_broken / guard_counter / overdrive_ready
+//   buff_attack / buff_skill / buff_heal / buff_defend
+//   hp / hp% / stamina / momentum / break / overdrive と比較演算子(>= <= > < ==)と整数
+// コマンド:
+//   attack skill heal defend wait enhance_attack enhance_skill enhance_heal enhance_defend finisher
+
+use crate::battle::{
+    ActionStepSpecificationEnum, BattleState, CommandKind, Difficulty, EnemyPlannedAction,
+};
+
+// ガンビット評価に使う戦況（プレイヤー自身の状態と、見えている敵の次の行動）
+pub struct GambitView {
+    pub hp: i32,
+    pub hp_max: i32,
+    pub stamina: i32,
+    pub momentum: i32,
+    pub overdrive: i32,
+    pub overdrive_ready: bool,
+    pub enemy_break: i32,
+    pub enemy_broken: bool,
+    pub enemy_next: EnemyNextKind,
+    pub guard_counter: bool,
+    pub buffs: [bool; 4], // 攻撃/強攻撃/回復/防御の強化中フラグ
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EnemyNextKind {
+    Attack,
+    Wait,
+    Heal,
+    Unknown,
+}
+impl EnemyNextKind {
+    // 事前決定済みの敵行動の次ステップの種類（難易度で伏せられていれば Unknown）
+    pub fn of(planned: &EnemyPlannedAction, difficulty: Difficulty) -> Self {
+        match planned
+            .visible_steps(difficulty)
+            .first()
+            .map(|s| s.specification)
+        {
+            Some(ActionStepSpecificationEnum::Attack(_)) => EnemyNextKind::Attack,
+            Some(ActionStepSpecificationEnum::Wait(_)) => EnemyNextKind::Wait,
+            Some(ActionStepSpecificationEnum::Heal(_)) => EnemyNextKind::Heal,
+            None => EnemyNextKind::Unknown,
+        }
+    }
+}
+
+impl GambitView {
+    // 画面なしの戦闘状態から作る（シミュレーター用）
+    pub fn from_state(state: &BattleState) -> Self {
+        GambitView {
+            hp: state.p_hp.current,
+            hp_max: state.p_hp.max,
+            stamina: state.p_sta.current,
+            momentum: state.momentum.current,
+            overdrive: state.overdrive.gauge,
+            overdrive_ready: state.overdrive.is_ready(),
+            enemy_break: state.e_break.current,
+            enemy_broken: state.e_bstate.remaining_turns > 0,
+            enemy_next: EnemyNextKind::of(&state.planned, state.difficulty),
+            guard_counter: state.guard.0,
+            buffs: [
+                state.buffs.attack > 0,
+                state.buffs.skill > 0,
+                state.buffs.heal > 0,
+                state.buffs.defend > 0,
+            ],
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Flag {
+    Always,
+    EnemyAttack,
+    EnemyWait,
+    EnemyHeal,
+    EnemyBroken,
+    GuardCounter,
+    OverdriveReady,
+    Buff(usize),
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Stat {
+    Hp,
+    HpPercent,
+    Stamina,
+    Momentum,
+    Break,
+    Overdrive,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum CmpOp {
+    Ge,
+    Le,
+    Gt,
+    Lt,
+    Eq,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Condition {
+    Flag { flag: Flag, negate: bool },
+    Compare { stat: Stat, op: CmpOp, value: i32 },
+}
+
+#[derive(Clone, Debug)]
+pub struct Gambit {
+    conditions: Vec<Condition>,
+    pub commands: Vec<CommandKind>, // 1バッチ分（1件以上）
+    pub source: String,             // 表示用の元の行
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct GambitScript(pub Vec<Gambit>);
+
+// 既定のガンビット（assets/gambits.txt が読めない環境向け）
+pub const DEFAULT_GAMBITS: &str = include_str!("../assets/gambits.txt");
+
+impl GambitScript {
+    pub fn parse(src: &str) -> Result<Self, String> {
+        let mut rules = Vec::new();
+        for (no, raw) in src.lines().enumerate() {
+            let line = raw.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let rule = parse_rule(line).map_err(|e| format!("{}行目: {} ({})", no + 1, e, line))?;
+            rules.push(rule);
+        }
+        Ok(GambitScript(rules))
+    }
+
+    // 条件を満たした最初のルールの (番号, ルール) を返す
+    pub fn choose(&self, view: &GambitView) -> Option<(usize, &Gambit)> {
+        self.0
+            .iter()
+            .enumerate()
+            .find(|(_, g)| g.conditions.iter().all(|c| c.eval(view)))
+    }
+}
+
+fn parse_rule(line: &str) -> Result<Gambit, String> {
+    let (cond_str, cmd_str) = line
+        .split_once("=>")
+        .ok_or_else(|| "「=>」がありません".to_string())?;
+    let commands = cmd_str
+        .split(',')
+        .map(|s| match s.trim() {
+            "" => Err("空のコマンドがあります".to_string()),
+            s => parse_command(s),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut conditions = Vec::new();
+    for token in cond_str.split('&') {
+        let token = token.trim();
+        if token.is_empty() {
+            return Err("空の条件があります".to_string());
+        }
+        conditions.push(parse_condition(token)?);
+    }
+    Ok(Gambit {
+        conditions,
+        commands,
+        source: line.to_string(),
+    })
+}
+
+fn parse_command(s: &str) -> Result<CommandKind, String> {
+    CommandKind::from_name(s).ok_or_else(|| format!("不明なコマンド「{s}」"))
+}
+
+fn parse_condition(token: &str) -> Result<Condition, String> {
+    // 比較（長い演算子から順に探す）
+    for (sym, op) in [
+        (">=", CmpOp::Ge),
+        ("<=", CmpOp::Le),
+        ("==", CmpOp::Eq),
+        (">", CmpOp::Gt),
+        ("<", CmpOp::Lt),
+    ] {
+        if let Some((lhs, rhs)) = token.split_once(sym) {
+            let stat = match lhs.trim() {
+                "hp" => Stat::Hp,
+                "hp%" => Stat::HpPercent,
+                "stamina" => Stat::Stamina,
+                "momentum" => Stat::Momentum,
+                "break" => Stat::Break,
+                "overdrive" => Stat::Overdrive,
+                other => return Err(format!("不明な値「{other}」")),
+            };
+            let value = rhs
+                .trim()
+                .parse::<i32>()
+                .map_err(|_| format!("数値ではありません「{}」", rhs.trim()))?;
+            return Ok(Condition::Compare { stat, op, value });
+        }
+    }
+    let (negate, name) = match token.strip_prefix('!') {
+        Some(rest) => (true, rest.trim()),
+        None => (false, token),
+    };
+    let flag = match name {
+        "always" => Flag::Always,
+        "enemy_attack" => Flag::EnemyAttack,
+        "enemy_wait" => Flag::EnemyWait,
+        "enemy_heal" => Flag::EnemyHeal,
+        "enemy_broken" => Flag::EnemyBroken,
+        "guard_counter" => Flag::GuardCounter,
+        "overdrive_ready" => Flag::OverdriveReady,
+        "buff_attack" => Flag::Buff(0),
+        "buff_skill" => Flag::Buff(1),
+        "buff_heal" => Flag::Buff(2),
+        "buff_defend" => Flag::Buff(3),
+        other => return Err(format!("不明な条件「{other}」")),
+    };
+    Ok(Condition::Flag { flag, negate })
+}
+
+impl Condition {
+    fn eval(&self, v: &GambitView) -> bool {
+        match *self {
+            Condition::Flag { flag, negate } => {
+                let b = match flag {
+                    Flag::Always => true,
+                    Flag::EnemyAttack => v.enemy_next == EnemyNextKind::Attack,
+                    Flag::EnemyWait => v.enemy_next == EnemyNextKind::Wait,
+                    Flag::EnemyHeal => v.enemy_next == EnemyNextKind::Heal,
+                    Flag::EnemyBroken => v.enemy_broken,
+                    Flag::GuardCounter => v.guard_counter,
+                    Flag::OverdriveReady => v.overdrive_ready,
+                    Flag::Buff(i) => v.buffs[i],
+                };
+                b != negate
+            }
+            Condition::Compare { stat, op, value } => {
+                let lhs = match stat {
+                    Stat::Hp => v.hp,
+                    Stat::HpPercent => {
+                        if v.hp_max > 0 {
+                            v.hp * 100 / v.hp_max
+                        } else {
+                            0
+                        }
+                    }
+                    Stat::Stamina => v.stamina,
+                    Stat::Momentum => v.momentum,
+                    Stat::Break => v.enemy_break,
+                    Stat::Overdrive => v.overdrive,
+                };
+                match op {
+                    CmpOp::Ge => lhs >= value,
+                    CmpOp::Le => lhs <= value,
+                    CmpOp::Gt => lhs > value,
+                    CmpOp::Lt => lhs < value,
+                    CmpOp::Eq => lhs == value,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn view() -> GambitView {
+        GambitView {
+            hp: 100,
+            hp_max: 200,
+            stamina: 30,
+            momentum: 0,
+            overdrive: 0,
+            overdrive_ready: false,
+            enemy_break: 0,
+            enemy_broken: false,
+            enemy_next: EnemyNextKind::Wait,
+            guard_counter: false,
+            buffs: [false; 4],
+        }
+    }
+
+    fn chosen(script: &GambitScript, v: &GambitView) -> Option<CommandKind> {
+        script.choose(v).map(|(_, g)| g.commands[0])
+    }
+
+    #[test]
+    fn default_gambits_parse() {
+        let script = GambitScript::parse(DEFAULT_GAMBITS).unwrap();
+        assert_eq!(script.0.len(), 8);
+        assert_eq!(
+            script.0[0].source,
+            "momentum>=100 & enemy_broken => finisher"
+        );
+    }
+
+    #[test]
+    fn comments_and_blank_lines_are_skipped() {
+        let script =
+            GambitScript::parse("# コメント\n\n  always => wait  # 末尾のコメント\n").unwrap();
+        assert_eq!(script.0.len(), 1);
+        assert_eq!(script.0[0].commands, vec![CommandKind::Wait]);
+        assert_eq!(script.0[0].source, "always => wait");
+    }
+
+    #[test]
+    fn errors_report_line_number() {
+        let err = GambitScript::parse("always => wait\nenemy_attack defend").unwrap_err();
+        assert!(err.starts_with("2行目: 「=>」がありません"), "{err}");
+        for (src, expected) in [
+            ("always => jump", "不明なコマンド「jump」"),
+            ("sleepy => wait", "不明な条件「sleepy」"),
+            ("mp>=10 => wait", "不明な値「mp」"),
+            ("hp>=ten => wait", "数値ではありません「ten」"),
+            ("always & => wait", "空の条件があります"),
+            ("always => attack,", "空のコマンドがあります"),
+            ("always => attack, jump", "不明なコマンド「jump」"),
+        ] {
+            let err = GambitScript::parse(src).unwrap_err();
+            assert!(err.contains(expected), "{src}: {err}");
+        }
+    }
+
+    #[test]
+    fn rule_can_list_a_batch() {
+        let script = GambitScript::parse("always => skill, attack ,attack").unwrap();
+        assert_eq!(
+            script.choose(&view()).map(|(_, g)| g.commands.clone()),
+            Some(vec![
+                CommandKind::Skill,
+                CommandKind::Attack,
+                CommandKind::Attack
+            ])
+        );
+    }
+
+    #[test]
+    fn first_matching_rule_wins() {
+        let script =
+            GambitScript::parse("enemy_attack => defend\nstamina>=25 => skill\nalways => wait")
+                .unwrap();
+        let mut v = view();
+        assert_eq!(chosen(&script, &v), Some(CommandKind::Skill));
+        v.enemy_next = EnemyNextKind::Attack;
+        assert_eq!(chosen(&script, &v), Some(CommandKind::Defend));
+        v.enemy_next = EnemyNextKind::Unknown;
+        v.stamina = 24;
+        assert_eq!(chosen(&script, &v), Some(CommandKind::Wait));
+    }
+
+    #[test]
+    fn comparisons_and_negation() {
+        let script = GambitScript::parse(
+            "hp%<=50 & !buff_heal => heal\nmomentum==100 => finisher\nstamina>30 => attack",
+        )
+        .unwrap();
+        let mut v = view();
+        assert_eq!(chosen(&script, &v), Some(CommandKind::Heal));
+        v.buffs[2] = true;
+        assert_eq!(chosen(&script, &v), None);
+        v.momentum = 100;
+        assert_eq!(chosen(&script, &v), Some(CommandKind::Finisher));
+        v.momentum = 99;
+        v.stamina = 31;
+        assert_eq!(chosen(&script, &v), Some(CommandKind::Attack));
+    }
+}
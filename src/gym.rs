@@ -0,0 +1,381 @@
+// ================== Gym Environment ==================
+// 強化学習用の reset / step インターフェース。中身は BattleState（実際の戦闘ルール）
+//
+// 行動: 1バッチ分のコマンド列（1〜MAX_BATCH_LIMIT件、番号は CommandKind::ALL の順）
+//       その時点の最大選択数を超えた分は捨てる。空なら待機
+// 報酬: 与ダメージ/敵最大HP - 被ダメージ/プレイヤー最大HP、勝利で+1・敗北で-1
+// 終了: どちらかのHPが0、または max_turns を超えた
+use serde::{Deserialize, Serialize};
+
+use crate::battle::{
+    ActionStepSpecificationEnum, BattleCtx, BattleState, BreakState, BreakValue, CommandBuffs,
+    CommandKind, DefendNextAttack, Difficulty, EnemyPlannedAction, GuardCounterReady, Hp,
+    MAX_BATCH_LIMIT, Momentum, Overdrive, Stamina, Turn,
+};
+
+// 観測（JSONではこのまま、学習用には to_features() の数値ベクトル）
+#[derive(Clone, Debug, Serialize)]
+pub struct Observation {
+    pub difficulty: Difficulty,
+    pub turn: u32,
+    pub hp: i32,
+    pub hp_max: i32,
+    pub stamina: i32,
+    pub stamina_max: i32,
+    pub momentum: i32,
+    pub overdrive: i32,
+    pub overdrive_active: bool,
+    pub buffs: [u32; 4], // 攻撃/強攻撃/回復/防御の強化残りターン
+    pub defend: bool,
+    pub guard_counter: bool,
+    pub max_select: usize,
+    pub enemy_hp: i32,
+    pub enemy_hp_max: i32,
+    pub enemy_break: i32,
+    pub enemy_break_max: i32,   // ブレイクに必要な値（難易度で変わる）
+    pub enemy_break_turns: u32, // ブレイク残りターン（0なら非ブレイク）
+    pub enemy_next: EnemyNextStep,
+}
+
+// 事前決定済みの敵の次ステップ
+#[derive(Clone, Debug, Serialize)]
+pub struct EnemyNextStep {
+    pub action: &'static str,
+    pub step: &'static str,
+    pub kind: &'static str, // attack / wait / heal / none / hidden（難易度で伏せられている）
+    pub power: f32,         // 攻撃なら威力倍率、回復なら回復量、それ以外は0
+    pub remaining_steps: usize,
+}
+
+// 観測に使う状態への参照（BattleState と BattleCtx のどちらからでも作れるように）
+struct ObservationSource<'a> {
+    difficulty: Difficulty,
+    turn: &'a Turn,
+    p_hp: &'a Hp,
+    p_sta: &'a Stamina,
+    momentum: &'a Momentum,
+    overdrive: &'a Overdrive,
+    buffs: &'a CommandBuffs,
+    defend: &'a DefendNextAttack,
+    guard: &'a GuardCounterReady,
+    e_hp: &'a Hp,
+    e_break: &'a BreakValue,
+    e_bstate: &'a BreakState,
+    planned: &'a EnemyPlannedAction,
+}
+
+impl Observation {
+    pub fn from_state(state: &BattleState) -> Self {
+        Self::from_source(
+            ObservationSource {
+                difficulty: state.difficulty,
+                turn: &state.turn,
+                p_hp: &state.p_hp,
+                p_sta: &state.p_sta,
+                momentum: &state.momentum,
+                overdrive: &state.overdrive,
+                buffs: &state.buffs,
+                defend: &state.defend,
+                guard: &state.guard,
+                e_hp: &state.e_hp,
+                e_break: &state.e_break,
+                e_bstate: &state.e_bstate,
+                planned: &state.planned,
+            },
+            state.max_select(),
+        )
+    }
+
+    // ECS側（ヘッドレスモード）でも同じ観測を作れるように BattleCtx から作る
+    pub fn from_ctx(ctx: &BattleCtx<'_>, max_select: usize) -> Self {
+        Self::from_source(
+            ObservationSource {
+                difficulty: *ctx.difficulty,
+                turn: ctx.turn,
+                p_hp: ctx.p_hp,
+                p_sta: ctx.p_sta,
+                momentum: ctx.momentum,
+                overdrive: ctx.overdrive,
+                buffs: ctx.buffs,
+                defend: ctx.defend,
+                guard: ctx.guard,
+                e_hp: ctx.e_hp,
+                e_break: ctx.e_break,
+                e_bstate: ctx.e_bstate,
+                planned: ctx.planned,
+            },
+            max_select,
+        )
+    }
+
+    fn from_source(state: ObservationSource<'_>, max_select: usize) -> Self {
+        let planned = state.planned;
+        let difficulty = state.difficulty;
+        let visible = planned.visible_steps(difficulty);
+        let (kind, power) = match visible.first().map(|s| s.specification) {
+            Some(ActionStepSpecificationEnum::Attack(spec)) => ("attack", spec.power),
+            Some(ActionStepSpecificationEnum::Wait(_)) => ("wait", 0.0),
+            Some(ActionStepSpecificationEnum::Heal(spec)) => ("heal", spec.amount as f32),
+            None if planned.0.current_step().is_some() => ("hidden", 0.0),
+            None => ("none", 0.0),
+        };
+        Observation {
+            difficulty,
+            turn: state.turn.0,
+            hp: state.p_hp.current,
+            hp_max: state.p_hp.max,
+            stamina: state.p_sta.current,
+            stamina_max: state.p_sta.max,
+            momentum: state.momentum.current,
+            overdrive: state.overdrive.gauge,
+            overdrive_active: state.overdrive.active,
+            buffs: [
+                state.buffs.attack,
+                state.buffs.skill,
+                state.buffs.heal,
+                state.buffs.defend,
+            ],
+            defend: state.defend.0,
+            guard_counter: state.guard.0,
+            max_select,
+            enemy_hp: state.e_hp.current,
+            enemy_hp_max: state.e_hp.max,
+            enemy_break: state.e_break.current,
+            enemy_break_max: difficulty.break_threshold(),
+            enemy_break_turns: state.e_bstate.remaining_turns,
+            enemy_next: EnemyNextStep {
+                action: planned.revealed_action_name(difficulty),
+                step: planned
+                    .0
+                    .current_step()
+                    .map_or("", |_| planned.revealed_name(difficulty)),
+                kind,
+                power,
+                // 見せてよいステップ数まで
+                remaining_steps: visible.len(),
+            },
+        }
+    }
+
+    // 0〜1程度に正規化した数値ベクトル（長さは FEATURE_LEN）
+    pub fn to_features(&self) -> Vec<f32> {
+        let ratio = |a: i32, b: i32| if b > 0 { a as f32 / b as f32 } else { 0.0 };
+        let next = &self.enemy_next;
+        vec![
+            ratio(self.hp, self.hp_max),
+            ratio(self.stamina, self.stamina_max),
+            self.momentum as f32 / 100.0,
+            self.overdrive as f32 / 100.0,
+            self.overdrive_active as u8 as f32,
+            self.buffs[0] as f32 / 11.0,
+            self.buffs[1] as f32 / 11.0,
+            self.buffs[2] as f32 / 11.0,
+            self.buffs[3] as f32 / 11.0,
+            self.defend as u8 as f32,
+            self.guard_counter as u8 as f32,
+            self.max_select as f32 / MAX_BATCH_LIMIT as f32,
+            ratio(self.enemy_hp, self.enemy_hp_max),
+            ratio(self.enemy_break, self.enemy_break_max),
+            self.enemy_break_turns as f32 / 4.0,
+            (next.kind == "attack") as u8 as f32,
+            (next.kind == "wait") as u8 as f32,
+            (next.kind == "heal") as u8 as f32,
+            if next.kind == "attack" {
+                next.power / 3.0
+            } else {
+                0.0
+            },
+            next.remaining_steps as f32 / 6.0,
+        ]
+    }
+}
+
+pub const FEATURE_LEN: usize = 20;
+
+pub struct BattleEnv {
+    state: BattleState,
+    pub max_turns: u32,
+}
+
+impl BattleEnv {
+    pub fn new(max_turns: u32) -> Self {
+        BattleEnv {
+            state: BattleState::new(0),
+            max_turns,
+        }
+    }
+
+    pub fn state(&self) -> &BattleState {
+        &self.state
+    }
+
+    pub fn reset(&mut self, seed: u64, difficulty: Difficulty) -> Observation {
+        self.state = BattleState::with_difficulty(seed, difficulty);
+        Observation::from_state(&self.state)
+    }
+
+    pub fn step(&mut self, action: &[CommandKind]) -> (Observation, f32, bool) {
+        if self.is_done() {
+            return (Observation::from_state(&self.state), 0.0, true);
+        }
+        let before_e = self.state.e_hp.current;
+        let before_p = self.state.p_hp.current;
+        let batch = if action.is_empty() {
+            &[CommandKind::Wait][..]
+        } else {
+            action
+        };
+        self.state.run_batch(batch);
+
+        let dealt = (before_e - self.state.e_hp.current) as f32 / self.state.e_hp.max as f32;
+        let taken = (before_p - self.state.p_hp.current) as f32 / self.state.p_hp.max as f32;
+        let mut reward = dealt - taken;
+        if self.state.player_won() {
+            reward += 1.0;
+        } else if self.state.is_over() {
+            reward -= 1.0;
+        }
+        (Observation::from_state(&self.state), reward, self.is_done())
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.state.is_over() || self.state.turn.0 > self.max_turns
+    }
+}
+
+// JSONで受け取るコマンド指定（英字名か CommandKind::ALL の番号）
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum CommandRef {
+    Index(usize),
+    Name(String),
+}
+
+// JSONのコマンド列を1バッチ分のコマンドに変換
+pub fn parse_batch(items: &[CommandRef]) -> Result<Vec<CommandKind>, String> {
+    if items.len() > MAX_BATCH_LIMIT {
+        return Err(format!("1バッチは最大{MAX_BATCH_LIMIT}件です"));
+    }
+    items
+        .iter()
+        .map(|item| match item {
+            CommandRef::Index(i) => CommandKind::ALL
+                .get(*i)
+                .copied()
+                .ok_or_else(|| format!("不明なコマンド番号 {i}")),
+            CommandRef::Name(name) => {
+                CommandKind::from_name(name).ok_or_else(|| format!("不明なコマンド「{name}」"))
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn env(max_turns: u32) -> BattleEnv {
+        let mut env = BattleEnv::new(max_turns);
+        env.reset(5, Difficulty::Normal);
+        env
+    }
+
+    #[test]
+    fn step_truncates_to_max_select() {
+        let mut env = env(100);
+        let max_select = env.state().max_select();
+        let turn = env.state().turn.0;
+        let action = [CommandKind::Wait; MAX_BATCH_LIMIT + 2];
+        let (obs, _, done) = env.step(&action);
+        assert!(!done);
+        assert_eq!(obs.turn, turn + max_select as u32);
+    }
+
+    #[test]
+    fn empty_action_waits_one_turn() {
+        let mut env = env(100);
+        let turn = env.state().turn.0;
+        let (obs, _, _) = env.step(&[]);
+        assert_eq!(obs.turn, turn + 1);
+    }
+
+    #[test]
+    fn win_ends_with_bonus() {
+        let mut env = env(100);
+        env.state.e_hp.current = 1;
+        let (_, reward, done) = env.step(&[CommandKind::Attack]);
+        assert!(done);
+        assert!(env.state().player_won());
+        assert!(reward > 1.0, "{reward}");
+        // 終了後の step は何もしない
+        assert_eq!(env.step(&[CommandKind::Attack]).1, 0.0);
+    }
+
+    #[test]
+    fn loss_ends_with_penalty() {
+        let mut env = env(100);
+        env.state.p_hp.current = 1;
+        let mut last = (0.0, false);
+        for _ in 0..50 {
+            let (_, reward, done) = env.step(&[CommandKind::Wait]);
+            last = (reward, done);
+            if done {
+                break;
+            }
+        }
+        assert!(last.1);
+        assert!(!env.state().player_won());
+        assert!(last.0 <= -1.0, "{}", last.0);
+    }
+
+    #[test]
+    fn max_turns_ends_without_bonus() {
+        let mut env = env(3);
+        let mut steps = 0;
+        loop {
+            let (_, reward, done) = env.step(&[CommandKind::Defend]);
+            steps += 1;
+            assert!(reward.abs() < 1.0, "{reward}");
+            if done {
+                break;
+            }
+        }
+        assert_eq!(steps, 3);
+        assert!(!env.state().is_over());
+        assert!(env.state().turn.0 > 3);
+    }
+
+    #[test]
+    fn parse_batch_accepts_names_and_indices() {
+        let items: Vec<CommandRef> =
+            serde_json::from_str(r#"["attack", 1, "enhance_heal"]"#).unwrap();
+        assert_eq!(
+            parse_batch(&items).unwrap(),
+            vec![
+                CommandKind::Attack,
+                CommandKind::Skill,
+                CommandKind::EnhanceHeal
+            ]
+        );
+        for (json, expected) in [
+            (r#"[10]"#, "不明なコマンド番号 10"),
+            (r#"["jump"]"#, "不明なコマンド「jump」"),
+        ] {
+            let items: Vec<CommandRef> = serde_json::from_str(json).unwrap();
+            assert!(parse_batch(&items).unwrap_err().contains(expected));
+        }
+        let too_long = vec![CommandRef::Index(0); MAX_BATCH_LIMIT + 1];
+        assert!(parse_batch(&too_long).is_err());
+    }
+
+    #[test]
+    fn features_have_fixed_length_and_range() {
+        let env = env(100);
+        let features = Observation::from_state(env.state()).to_features();
+        assert_eq!(features.len(), FEATURE_LEN);
+        assert!(
+            features.iter().all(|f| (0.0..=1.0).contains(f)),
+            "{features:?}"
+        );
+    }
+}
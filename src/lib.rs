@@ -0,0 +1,4 @@
+pub mod battle;
+pub mod gambit;
+pub mod gym;
+pub mod search;
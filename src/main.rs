@@ -1,387 +1,290 @@
-use std::sync::Arc;
+// Bevy のシステムは引数が多く、Query の型も長くなるので許可する
+#![allow(clippy::too_many_arguments, clippy::type_complexity)]
 
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use bevy::app::ScheduleRunnerPlugin;
 use bevy::prelude::*;
-use rand::Rng;
+use command_battle::battle::*;
+use command_battle::gambit::{DEFAULT_GAMBITS, EnemyNextKind, GambitScript, GambitView};
+use command_battle::gym::{CommandRef, Observation, parse_batch};
+use serde::{Deserialize, Serialize};
 
 // 画面レイアウト切替用定数（false: 既存レイアウト / true: 新レイアウト）
 const USE_DQ_LIKE_LAYOUT: bool = true;
 
 fn main() {
+    // --difficulty easy|normal|hard|nightmare: 戦闘前に難易度を選ぶ
+    let difficulty = std::env::args()
+        .skip_while(|a| a != "--difficulty")
+        .nth(1)
+        .and_then(|v| Difficulty::from_name(&v))
+        .unwrap_or_default();
+    // --seed N: 敵の乱数を固定する（省略時はランダム）
+    let seed = BattleSeed(
+        std::env::args()
+            .skip_while(|a| a != "--seed")
+            .nth(1)
+            .and_then(|v| v.parse().ok()),
+    );
+    // --headless: 画面なしで標準入出力の JSON Lines でバッチを受け付ける
+    if std::env::args().any(|a| a == "--headless") {
+        App::new()
+            // 入力待ちで空回りしないように間隔を空けて回す
+            .add_plugins(
+                MinimalPlugins.set(ScheduleRunnerPlugin::run_loop(Duration::from_millis(5))),
+            )
+            .insert_resource(difficulty)
+            .insert_resource(seed)
+            .insert_resource(spawn_stdin_reader())
+            .add_systems(Startup, setup_battle)
+            .add_systems(Update, headless_system)
+            .run();
+        return;
+    }
+    let hotseat = std::env::args().any(|a| a == "--hotseat");
+    let practice = std::env::args().any(|a| a == "--practice");
+    let training = std::env::args().any(|a| a == "--training");
+    // キー設定（読み込みに失敗したら既定のキーを使う）
+    let input_map = load_input_map().unwrap_or_else(|e| {
+        eprintln!("キー設定の読み込みに失敗: {e} / 既定のキーを使用");
+        InputMap::default()
+    });
     App::new()
-        .add_plugins(DefaultPlugins)
-        .add_systems(Startup, setup)
-        .add_systems(Update, player_input_system)
-        .add_systems(Update, battle_end_check_system)
-        .add_systems(Update, ui_update_system)
-        .add_systems(Update, ui_update_enemy_system)
-        .add_systems(Update, ui_update_enemy_damage_popup_system)
-        .add_systems(Update, ui_update_player_status_system)
-        .add_systems(Update, ui_update_command_system)
-        .add_systems(Update, ui_update_message_system)
-        .add_systems(Update, ui_update_skill_effect_system)
-        .add_systems(Update, boss_slain_banner_system)
+        // wasm ではページいっぱいに広げる（スマートフォンでも遊べるように）
+        .add_plugins(DefaultPlugins.set(WindowPlugin {
+            primary_window: Some(Window {
+                fit_canvas_to_parent: true,
+                ..default()
+            }),
+            ..default()
+        }))
+        .init_state::<AppState>()
+        .init_state::<BattleSession>()
+        .insert_resource(difficulty)
+        .insert_resource(seed)
+        .insert_resource(Hotseat(hotseat))
+        .insert_resource(Practice {
+            enabled: practice,
+            history: Vec::new(),
+        })
+        .insert_resource(Training {
+            enabled: training,
+            ..default()
+        })
+        .init_resource::<BatchPreview>()
+        .insert_resource(input_map)
+        .init_resource::<SettingsCursor>()
+        .init_resource::<KeybindCursor>()
+        .init_resource::<PadInput>()
+        .init_resource::<CommandCursor>()
+        .init_resource::<PointerInput>()
+        .init_resource::<CommandAvailability>()
+        .configure_sets(Update, BattleSystems.run_if(in_state(AppState::Battle)))
+        .add_systems(Startup, setup_camera)
+        .add_systems(
+            PreUpdate,
+            gamepad_input_system.after(bevy::input::InputSystems),
+        )
+        // 戦闘はタイトル・結果画面から入るたびに作り直す（ポーズからの復帰では作らない）
+        .add_systems(
+            OnEnter(BattleSession::Open),
+            (setup_battle, setup, training_setup).chain(),
+        )
+        .add_systems(OnEnter(AppState::Title), setup_title)
+        .add_systems(OnEnter(AppState::Settings), setup_settings)
+        .add_systems(OnEnter(AppState::Keybinds), setup_keybinds)
+        .add_systems(OnEnter(AppState::Results), setup_results)
+        .add_systems(OnEnter(AppState::Paused), (setup_pause, pause_time))
+        .add_systems(OnEnter(AppState::Battle), resume_time)
+        .add_systems(Update, (menu_button_system, ui_scale_system))
+        .add_systems(Update, title_input_system.run_if(in_state(AppState::Title)))
+        .add_systems(
+            Update,
+            (settings_input_system, ui_update_settings_system)
+                .chain()
+                .run_if(in_state(AppState::Settings)),
+        )
+        .add_systems(
+            Update,
+            (keybinds_input_system, ui_update_keybinds_system)
+                .chain()
+                .run_if(in_state(AppState::Keybinds)),
+        )
+        .add_systems(
+            Update,
+            results_input_system.run_if(in_state(AppState::Results)),
+        )
+        .add_systems(
+            Update,
+            pause_input_system.run_if(in_state(AppState::Battle).or(in_state(AppState::Paused))),
+        )
+        .add_systems(
+            Update,
+            (
+                auto_battle_system
+                    .run_if(training_closed)
+                    .before(player_input_system),
+                dragon_input_system
+                    .run_if(training_closed)
+                    .before(player_input_system),
+                (
+                    practice_rewind_system.run_if(training_closed),
+                    practice_snapshot_system,
+                )
+                    .chain()
+                    .before(auto_battle_system)
+                    .before(dragon_input_system),
+                command_menu_input_system
+                    .before(auto_battle_system)
+                    .before(player_input_system),
+                player_input_system.run_if(training_closed),
+                // 設定画面を閉じた Enter がコマンド決定にならないよう入力処理の後
+                (training_system, ui_update_training_system)
+                    .chain()
+                    .after(player_input_system),
+                (battle_end_check_system, battle_finish_system).chain(),
+            )
+                .in_set(BattleSystems),
+        )
+        .add_systems(
+            Update,
+            (
+                ui_update_system,
+                ui_update_enemy_system.after(battle_end_check_system),
+                ui_update_enemy_damage_popup_system,
+                ui_update_player_status_system,
+                ui_update_combo_system,
+                ui_update_overdrive_system,
+                ui_update_command_system,
+                (
+                    batch_forecast_system.run_if(forecast_inputs_changed),
+                    (
+                        ui_update_batch_preview_system,
+                        ui_update_command_menu_system,
+                    ),
+                )
+                    .chain()
+                    .after(player_input_system),
+                ui_update_message_system,
+                ui_update_skill_effect_system,
+                boss_slain_banner_system,
+                finisher_banner_system,
+            )
+                .in_set(BattleSystems),
+        )
         .run();
 }
 
+// ================== App State ==================
+// 画面の遷移: タイトル → 戦闘 ⇄ ポーズ → 結果 → 戦闘（再戦）/タイトル。設定はタイトルとポーズから、キー設定は設定から
+#[derive(States, Default, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+enum AppState {
+    #[default]
+    Title,
+    Battle,
+    Results,
+    Paused,
+    Settings,
+    Keybinds,
+}
+
+// 戦闘が続いているか（ポーズやポーズから開いた設定画面の間も Open）。
+// 戦闘のエンティティとUIは Open に入ると作られ、Closed に戻ると消える
+#[derive(States, Default, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+enum BattleSession {
+    #[default]
+    Closed,
+    Open,
+}
+
+// 戦闘中（ポーズ以外）だけ動かすシステム
+#[derive(SystemSet, Clone, PartialEq, Eq, Hash, Debug)]
+struct BattleSystems;
+
 // ================== Components & Resources ==================
 #[derive(Component)]
 struct Player;
 #[derive(Component)]
 struct Enemy;
-// 敵のブレイク値（0以上）
-#[derive(Component)]
-struct BreakValue {
-    current: i32,
-}
-// 敵のブレイク状態（残りターン数）
-#[derive(Component)]
-struct BreakState {
-    remaining_turns: u32, // 0なら非ブレイク
-}
-// ブレイク自然回復の現在量（ターンごとに倍増: 1,2,4,...）
-#[derive(Component)]
-struct BreakRegen {
-    amount: i32, // 最小1
-}
-#[derive(Component)]
-struct Hp {
-    current: i32,
-    max: i32,
-}
-#[derive(Component)]
-struct Attack(i32);
-
-#[derive(Component)]
-struct Stamina {
-    current: i32,
-    max: i32,
-}
-
-#[derive(Resource, PartialEq, Eq)]
-enum BattlePhase {
-    AwaitCommand,
-    // 連続コマンドの次コマンドを実行するか確認するフェーズ
-    ConfirmQueued,
-    InBattle,
-    Finished,
-}
-#[derive(Resource)]
-struct Turn(u32);
 
+// オートバトル（ガンビットで毎ターンのバッチを自動選択）
 #[derive(Resource)]
-struct CombatLog(Vec<String>);
-
-// モメンタム（最大100）
-#[derive(Resource, Default)]
-struct Momentum {
-    current: i32,
+struct AutoBattle {
+    enabled: bool,
+    script: GambitScript,
+    timer: f32, // 次の自動入力までの秒数
+    fire: bool, // このフレームで自動的に決定/実行する
 }
 
-// 敵ダメージポップアップ用リソース（タイマー制御）
+// コマンド一覧のカーソル（CommandKind::ALL の番号。十字キー・スティック・矢印キー・マウスで動かす）
+// 一覧は2列: 左列が通常コマンド、右列が強化と奥義
 #[derive(Resource, Default)]
-struct EnemyDamagePopup {
-    amount: i32,
-    timer: f32, // 秒。0以下で非表示
-}
+struct CommandCursor(usize);
+const COMMAND_MENU_ROWS: usize = 5;
 
-// 連続コマンド実行バッチの総件数（選択確定時に設定）
+// コマンド一覧の灰色表示用（CommandKind::ALL の順。今の選択に続けて選べるか）
 #[derive(Resource, Default)]
-struct ConsecutiveBatch {
-    total: usize,    // このバッチの総選択数
-    executed: usize, // このバッチで既に実行した数
-}
+struct CommandAvailability(Vec<bool>);
 
-// コマンド強化の残りターン
+// 未確定の複数選択バッファ（Enterで確定）
 #[derive(Resource, Default)]
-struct CommandBuffs {
-    attack: u32,
-    skill: u32,
-    heal: u32,
-    defend: u32,
-}
+struct PendingSelections(Vec<CommandKind>);
 
-// 次の敵攻撃を無効化する防御フラグ
-#[derive(Resource, Default)]
-struct DefendNextAttack(bool);
+// 敵の乱数のシード（--seed。None なら起動ごとにランダム）
+#[derive(Resource, Clone, Copy)]
+struct BattleSeed(Option<u64>);
 
-// 防御後の次プレイヤー行動に対するガードカウンター猶予
+// 練習モード（--practice）: ターンごとに状態を残し、U で1ターンずつ巻き戻せる
 #[derive(Resource, Default)]
-struct GuardCounterReady(bool);
-
-// 敵の行動種別（事前決定）
-#[derive(Clone, Copy)]
-enum EnemyAction {
-    Attack,
-    Wait,
-    Heal,
-    ChargeStart,
-    ChargeHit,
-}
-
-#[derive(Clone)]
-struct ActionProcess {
-    action: Arc<Action>,
-    next_step_index: usize,
-}
-impl ActionProcess {
-    fn from(action: &Arc<Action>) -> Self {
-        ActionProcess {
-            action: Arc::clone(action),
-            next_step_index: 0,
-        }
-    }
-
-    fn is_finished(&self) -> bool {
-        self.next_step_index >= self.action.steps.len()
-    }
-
-    fn current_step(&self) -> Option<&ActionStep> {
-        if self.is_finished() {
-            None
-        } else {
-            Some(&self.action.steps[self.next_step_index])
-        }
-    }
-
-    fn next(&mut self) -> Option<&ActionStep> {
-        self.next_step_index += 1;
-        if self.is_finished() {
-            None
-        } else {
-            let step = &self.action.steps[self.next_step_index];
-            Some(step)
-        }
-    }
+struct Practice {
+    enabled: bool,
+    history: Vec<TurnSnapshot>, // ターンの解決前の状態（ターン順）
 }
-
-#[derive(Clone)]
-struct Action {
-    steps: Vec<ActionStep>,
-}
-
-#[derive(Clone, Copy)]
-struct ActionStep {
-    name: &'static str,
-    specification: ActionStepSpecificationEnum,
-}
-
-#[derive(Clone, Copy)]
-enum ActionStepSpecificationEnum {
-    Attack(ActionStepSpecificationAttack),
-    Wait(ActionStepSpecificationWait),
-    Heal(ActionStepSpecificationHeal),
-}
-#[derive(Clone, Copy)]
-struct ActionStepSpecificationAttack {
-    power: f32,
-}
-#[derive(Clone, Copy)]
-struct ActionStepSpecificationWait {
-    invincible: bool,
-}
-#[derive(Clone, Copy)]
-struct ActionStepSpecificationHeal {
-    amount: i32,
-}
-
-fn create_enemy_attack() -> Action {
-    Action {
-        steps: vec![ActionStep {
-            name: "爪攻撃",
-            specification: ActionStepSpecificationEnum::Attack(ActionStepSpecificationAttack {
-                power: 1.0,
-            }),
-        }],
-    }
-}
-fn create_enemy_claw_strong() -> Action {
-    Action {
-        steps: vec![
-            ActionStep {
-                name: "強力な爪攻撃",
-                specification: ActionStepSpecificationEnum::Attack(ActionStepSpecificationAttack {
-                    power: 2.0,
-                }),
-            },
-            ActionStep {
-                name: "体勢を立て直す",
-                specification: ActionStepSpecificationEnum::Wait(ActionStepSpecificationWait {
-                    invincible: false,
-                }),
-            },
-        ],
-    }
-}
-fn create_enemy_claw_combo() -> Action {
-    Action {
-        steps: vec![
-            ActionStep {
-                name: "爪連撃(1)",
-                specification: ActionStepSpecificationEnum::Attack(ActionStepSpecificationAttack {
-                    power: 0.7,
-                }),
-            },
-            ActionStep {
-                name: "爪連撃(2)",
-                specification: ActionStepSpecificationEnum::Attack(ActionStepSpecificationAttack {
-                    power: 0.7,
-                }),
-            },
-            ActionStep {
-                name: "待機",
-                specification: ActionStepSpecificationEnum::Wait(ActionStepSpecificationWait {
-                    invincible: false,
-                }),
-            },
-        ],
-    }
-}
-fn create_enemy_claw_combo_strong() -> Action {
-    Action {
-        steps: vec![
-            ActionStep {
-                name: "爪連撃(1)",
-                specification: ActionStepSpecificationEnum::Attack(ActionStepSpecificationAttack {
-                    power: 0.8,
-                }),
-            },
-            ActionStep {
-                name: "爪連撃(2)",
-                specification: ActionStepSpecificationEnum::Attack(ActionStepSpecificationAttack {
-                    power: 0.8,
-                }),
-            },
-            ActionStep {
-                name: "噛みつき",
-                specification: ActionStepSpecificationEnum::Attack(ActionStepSpecificationAttack {
-                    power: 2.0,
-                }),
-            },
-            ActionStep {
-                name: "待機",
-                specification: ActionStepSpecificationEnum::Wait(ActionStepSpecificationWait {
-                    invincible: false,
-                }),
-            },
-        ],
-    }
-}
-fn create_enemy_stomp() -> Action {
-    Action {
-        steps: vec![
-            ActionStep {
-                name: "飛び上がり",
-                specification: ActionStepSpecificationEnum::Wait(ActionStepSpecificationWait {
-                    invincible: false,
-                }),
-            },
-            ActionStep {
-                name: "踏みつけ",
-                specification: ActionStepSpecificationEnum::Attack(ActionStepSpecificationAttack {
-                    power: 2.5,
-                }),
-            },
-        ],
-    }
-}
-// ファイアブレス
-fn create_enemy_fire_breath() -> Action {
-    Action {
-        steps: vec![
-            ActionStep {
-                name: "息を吸い込む",
-                specification: ActionStepSpecificationEnum::Wait(ActionStepSpecificationWait {
-                    invincible: false,
-                }),
-            },
-            ActionStep {
-                name: "炎を吐き始めた",
-                specification: ActionStepSpecificationEnum::Attack(ActionStepSpecificationAttack {
-                    power: 1.0,
-                }),
-            },
-            ActionStep {
-                name: "炎を吐き続ける",
-                specification: ActionStepSpecificationEnum::Attack(ActionStepSpecificationAttack {
-                    power: 2.5,
-                }),
-            },
-            ActionStep {
-                name: "炎を吐き続ける",
-                specification: ActionStepSpecificationEnum::Attack(ActionStepSpecificationAttack {
-                    power: 3.0,
-                }),
-            },
-            ActionStep {
-                name: "炎を吐き続ける",
-                specification: ActionStepSpecificationEnum::Attack(ActionStepSpecificationAttack {
-                    power: 0.5,
-                }),
-            },
-            ActionStep {
-                name: "息切れ",
-                specification: ActionStepSpecificationEnum::Wait(ActionStepSpecificationWait {
-                    invincible: false,
-                }),
-            },
-        ],
-    }
-}
-fn create_enemy_wait() -> Action {
-    Action {
-        steps: vec![ActionStep {
-            name: "待機",
-            specification: ActionStepSpecificationEnum::Wait(ActionStepSpecificationWait {
-                invincible: false,
-            }),
-        }],
-    }
+struct TurnSnapshot {
+    state: BattleState,
+    queue: CommandQueue,
+    log: Vec<String>,
 }
-fn create_enemy_heal() -> Action {
-    Action {
-        steps: vec![ActionStep {
-            name: "回復",
-            specification: ActionStepSpecificationEnum::Heal(ActionStepSpecificationHeal {
-                amount: 100,
-            }),
-        }],
-    }
-}
-
-// 次ターンに表示される事前決定済み敵行動
-#[derive(Resource)]
-struct EnemyPlannedAction(ActionProcess);
-
-// コマンド種別
-#[derive(Clone, Copy)]
-enum CommandKind {
-    Attack,
-    Skill,
-    Heal,
-    Defend,
-    Wait,
-    EnhanceAttack,
-    EnhanceSkill,
-    EnhanceHeal,
-    EnhanceDefend,
-}
-
-// 予約コマンドのキュー
-#[derive(Resource, Default)]
-struct CommandQueue(std::collections::VecDeque<CommandKind>);
 
-// 直前のプレイヤー実行コマンドが攻撃だったかを保持（攻撃後の攻撃=連撃）
+// 訓練モード（--training）: 設定画面で状況を作り、敵の行動を固定して計算式を確かめる
 #[derive(Resource, Default)]
-struct PlayerChainState {
-    last_was_attack: bool,
+struct Training {
+    enabled: bool,
+    open: bool,    // 設定画面を表示中（T で開閉）
+    cursor: usize, // 設定画面の選択行
+    settings: TrainingSettings,
+    refilled_turn: u32, // 無限リソースで補充済みのターン
+}
+#[derive(Clone, Default)]
+struct TrainingSettings {
+    player_hp: i32,
+    player_stamina: i32,
+    momentum: i32,
+    enemy_hp: i32,
+    enemy_break: i32,
+    buffs: CommandBuffs,
+    enemy_lock: usize,  // 0=固定しない / 1以降=ENEMY_MOVES の番号+1
+    infinite: bool,     // 勇者のHP・スタミナ・モメンタムが設定値を下回ったら毎ターン戻す
+    enemy_refill: bool, // ドラゴンのHPが設定値を下回ったら毎ターン戻す
 }
 
-// 未確定の複数選択バッファ（Enterで確定）
+// 2P対戦（--hotseat）: ドラゴンの次の行動を2人目が数字キーで選ぶ
 #[derive(Resource, Default)]
-struct PendingSelections(Vec<CommandKind>);
-
-#[derive(Component)]
-struct UiRoot;
+struct Hotseat(bool);
+const DRAGON_KEYS: [KeyCode; 9] = [
+    KeyCode::Digit1,
+    KeyCode::Digit2,
+    KeyCode::Digit3,
+    KeyCode::Digit4,
+    KeyCode::Digit5,
+    KeyCode::Digit6,
+    KeyCode::Digit7,
+    KeyCode::Digit8,
+    KeyCode::Digit9,
+];
 
 #[derive(Component)]
 struct UiStatus;
@@ -402,10 +305,6 @@ struct UiEffHeal;
 #[derive(Component)]
 struct UiEffDefend;
 
-//
-#[derive(Component)]
-struct UiBackground;
-
 #[derive(Component)]
 struct UiPlayerStatus;
 #[derive(Component)]
@@ -420,11 +319,15 @@ struct UiStaGaugeFill;
 struct UiMomentumText;
 #[derive(Component)]
 struct UiBuffsText;
+#[derive(Component)]
+struct UiComboText;
+#[derive(Component)]
+struct UiOverdriveText;
+#[derive(Component)]
+struct UiOverdriveGaugeFill;
 
 #[derive(Component)]
 struct UiEnemy;
-#[derive(Component)]
-struct UiEnemyStatus;
 
 // UiEnemy 内部の更新ターゲット
 #[derive(Component)]
@@ -435,6 +338,8 @@ struct UiEnemyBreakGaugeFill;
 struct UiEnemyBreakLabel; // 「ブレイク中」表示用
 #[derive(Component)]
 struct UiEnemyNextActionText; // 「次の行動: ...」
+#[derive(Component)]
+struct UiEnemyTelegraphText; // 現在の敵行動の残りステップ一覧
 
 // 敵ダメージ表示テキスト（HPゲージの横に一時表示）
 #[derive(Component)]
@@ -446,146 +351,760 @@ struct UiMessage;
 struct UiCommand;
 #[derive(Component)]
 struct UiCommandHelp;
-
-// ================== Boss Slain Banner ==================
-#[derive(Component)]
-struct BossSlainText; // ボス撃破表示用
-
 #[derive(Component)]
-struct BossSlainBanner {
-    elapsed: f32,
-    phase: BannerPhase,
-}
-
-// バナー背面の黒帯（グラデーション）
+struct UiCommandItem(CommandKind); // コマンド一覧の1項目（マウス・タッチで選べる）
 #[derive(Component)]
-struct BossSlainBackdrop;
+struct UiInputButton(InputAction); // コマンド入力パネルの操作ボタン（決定・取り消しなど）
 #[derive(Component)]
-struct BossSlainBackdropCenter; // 中央の帯（不透明）
+struct UiBatchPreview; // 選択中バッチの予測（コマンド入力パネルの右）
 #[derive(Component)]
-struct BossSlainBackdropRow(u8); // グラデーション行（0=最上段）
+struct UiTrainingPanel; // 訓練モードの設定画面
 
-enum BannerPhase {
-    FadeIn,
-    Hold,
-    FadeOut,
+// 直前の戦闘の勝敗（結果画面用）
+#[derive(Resource)]
+struct BattleOutcome {
+    won: bool,
 }
 
-// ================== Setup ==================
-fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
-    commands.spawn(Camera2d);
-    commands.spawn((
-        Player,
-        Hp {
-            current: 100,
-            max: 100,
-        },
-        Attack(10),
-        Stamina {
-            current: 100,
-            max: 100,
-        },
-    ));
-    commands.spawn((
-        Enemy,
-        Hp {
-            current: 1500,
-            max: 1500,
-        },
-        Attack(40),
-        BreakValue { current: 0 },
-        BreakState { remaining_turns: 0 },
-        BreakRegen { amount: 1 },
-    ));
-    commands.insert_resource(BattlePhase::AwaitCommand);
-    commands.insert_resource(Turn(1));
-    // 初期ログと敵行動決定
-    let mut rng = rand::rng();
-    let attack = Arc::new(create_enemy_attack());
-    let wait = Arc::new(create_enemy_wait());
-    let first_action = if rng.random_bool(0.5) {
-        ActionProcess::from(&attack)
-    } else {
-        ActionProcess::from(&wait)
-    };
-    commands.insert_resource(CombatLog(vec![
-        format!("初期敵行動: {}", first_action.current_step().unwrap().name),
-        "コマンドを選択してください (A=攻撃 S=強攻撃 H=回復 D=防御 W=待機 / Backspace=直前取り消し / Esc=全クリア / Enter=決定)".to_string(),
-    ]));
-    commands.insert_resource(DefendNextAttack::default());
-    commands.insert_resource(GuardCounterReady::default());
-    commands.insert_resource(CommandQueue::default());
-    commands.insert_resource(PlayerChainState::default());
-    commands.insert_resource(PendingSelections::default());
-    commands.insert_resource(EnemyPlannedAction(first_action));
-    commands.insert_resource(Momentum { current: 0 });
-    commands.insert_resource(ConsecutiveBatch::default());
-    commands.insert_resource(CommandBuffs::default());
-    commands.insert_resource(EnemyDamagePopup::default());
+// 選択中バッチを確定した場合の予測（コマンド入力待ちで選択があるときだけ）
+#[derive(Resource, Default)]
+struct BatchPreview(Vec<PreviewStep>);
 
-    const MARGIN: Val = Val::Px(12.);
-    let font = asset_server.load("fonts/x12y16pxMaruMonica.ttf");
+// ================== Input Map ==================
+// 戦闘中のキー・ゲームパッドの割り当て。assets/keybinds.txt（配布する既定）から読み、
+// キー設定画面で変えたものは config/keybinds.txt（git管理外）に保存して次回からそちらを優先する
+const KEYBINDS_PATH: &str = "assets/keybinds.txt";
+const USER_KEYBINDS_PATH: &str = "config/keybinds.txt";
 
-    // 画面下のログメッセージ（白枠、最大10行）
-    commands
-        .spawn((
-            Node {
-                width: Val::Px(750.0),
-                height: Val::Auto,
-                position_type: PositionType::Absolute,
-                right: Val::Px(12.0),
-                bottom: Val::Px(12.0),
-                border: UiRect::all(Val::Px(1.0)),
-                padding: UiRect::all(Val::Px(8.0)),
-                ..default()
-            },
-            BackgroundColor(Color::from(LinearRgba {
-                red: 0.0,
-                green: 0.0,
-                blue: 0.0,
-                alpha: 0.6,
-            })),
-            BorderColor::all(Color::WHITE),
-            ZIndex(10),
-        ))
-        .with_children(|col| {
-            col.spawn((
-                UiMessage,
-                Text::new(""),
-                TextFont {
-                    font: font.clone(),
-                    font_size: 16.0,
-                    ..default()
-                },
-                TextColor(Color::WHITE),
-            ));
-        });
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum InputAction {
+    Command(CommandKind),
+    Up,         // コマンド・予約のカーソルを上へ
+    Down,       // コマンド・予約のカーソルを下へ
+    Left,       // コマンドのカーソルを左の列へ
+    Right,      // コマンドのカーソルを右の列へ
+    Choose,     // カーソルのコマンドを選ぶ
+    Decide,     // 選択を決定
+    Execute,    // 予約を実行
+    Reselect,   // 予約をリセットして選び直し
+    Undo,       // 直前の選択を取り消し
+    Clear,      // 選択を全クリア
+    Remove,     // カーソル位置の予約を削除
+    AutoBattle, // オートバトルの切替
+}
 
-    // 敵UI（中央配置）: 画像の上にHP/ブレイクゲージと次の行動を表示
-    let dragon = asset_server.load("images/dragon.png");
-    commands
-        .spawn((
-            UiEnemy,
-            Node {
-                width: percent(100),
-                height: percent(100),
-                position_type: PositionType::Absolute,
-                justify_content: JustifyContent::Center,
-                align_items: AlignItems::Center,
-                ..default()
+impl InputAction {
+    // キー設定画面・設定ファイルの並び順
+    const ALL: [InputAction; 22] = [
+        InputAction::Command(CommandKind::Attack),
+        InputAction::Command(CommandKind::Skill),
+        InputAction::Command(CommandKind::Heal),
+        InputAction::Command(CommandKind::Defend),
+        InputAction::Command(CommandKind::Wait),
+        InputAction::Command(CommandKind::EnhanceAttack),
+        InputAction::Command(CommandKind::EnhanceSkill),
+        InputAction::Command(CommandKind::EnhanceHeal),
+        InputAction::Command(CommandKind::EnhanceDefend),
+        InputAction::Command(CommandKind::Finisher),
+        InputAction::Up,
+        InputAction::Down,
+        InputAction::Left,
+        InputAction::Right,
+        InputAction::Choose,
+        InputAction::Decide,
+        InputAction::Execute,
+        InputAction::Reselect,
+        InputAction::Undo,
+        InputAction::Clear,
+        InputAction::Remove,
+        InputAction::AutoBattle,
+    ];
+
+    // 設定ファイルでの名前
+    fn name(self) -> &'static str {
+        match self {
+            InputAction::Command(cmd) => cmd.name(),
+            InputAction::Up => "up",
+            InputAction::Down => "down",
+            InputAction::Left => "left",
+            InputAction::Right => "right",
+            InputAction::Choose => "choose",
+            InputAction::Decide => "decide",
+            InputAction::Execute => "execute",
+            InputAction::Reselect => "reselect",
+            InputAction::Undo => "undo",
+            InputAction::Clear => "clear",
+            InputAction::Remove => "remove",
+            InputAction::AutoBattle => "auto_battle",
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            InputAction::Command(cmd) => cmd.label(),
+            InputAction::Up => "カーソル上",
+            InputAction::Down => "カーソル下",
+            InputAction::Left => "カーソル左",
+            InputAction::Right => "カーソル右",
+            InputAction::Choose => "選ぶ",
+            InputAction::Decide => "決定",
+            InputAction::Execute => "予約を実行",
+            InputAction::Reselect => "選び直し",
+            InputAction::Undo => "直前取り消し",
+            InputAction::Clear => "全クリア",
+            InputAction::Remove => "予約を削除",
+            InputAction::AutoBattle => "オートバトル",
+        }
+    }
+
+    // 受け付ける場面（1=コマンド選択 / 2=予約確認）。場面が重なる行動どうしは同じキーを使えない
+    fn scenes(self) -> u8 {
+        match self {
+            InputAction::Command(_)
+            | InputAction::Up
+            | InputAction::Down
+            | InputAction::AutoBattle => 3,
+            InputAction::Left
+            | InputAction::Right
+            | InputAction::Choose
+            | InputAction::Decide
+            | InputAction::Undo
+            | InputAction::Clear => 1,
+            InputAction::Execute | InputAction::Reselect | InputAction::Remove => 2,
+        }
+    }
+
+    fn default_bindings(self) -> Vec<Binding> {
+        use Binding::{Key, Pad};
+        match self {
+            InputAction::Command(cmd) => match cmd {
+                CommandKind::Attack => vec![Key(KeyCode::KeyA)],
+                CommandKind::Skill => vec![Key(KeyCode::KeyS)],
+                CommandKind::Heal => vec![Key(KeyCode::KeyH)],
+                CommandKind::Defend => vec![Key(KeyCode::KeyD)],
+                CommandKind::Wait => vec![Key(KeyCode::KeyW)],
+                // 強化はショルダーボタンで直接選べる
+                CommandKind::EnhanceAttack => {
+                    vec![Key(KeyCode::KeyZ), Pad(GamepadButton::LeftTrigger)]
+                }
+                CommandKind::EnhanceSkill => {
+                    vec![Key(KeyCode::KeyX), Pad(GamepadButton::RightTrigger)]
+                }
+                CommandKind::EnhanceHeal => {
+                    vec![Key(KeyCode::KeyC), Pad(GamepadButton::LeftTrigger2)]
+                }
+                CommandKind::EnhanceDefend => {
+                    vec![Key(KeyCode::KeyV), Pad(GamepadButton::RightTrigger2)]
+                }
+                CommandKind::Finisher => vec![Key(KeyCode::KeyF)],
             },
-            ZIndex(0),
-        ))
-        .with_children(|center| {
-            // 画像コンテナ（相対位置指定にしてオーバーレイをAbsoluteで配置）
-            center
-                .spawn((
-                    Node {
-                        width: Val::Px(512.0),
-                        height: Val::Px(384.0),
-                        position_type: PositionType::Relative,
-                        border: UiRect::all(Val::Px(1.0)),
-                        ..default()
+            InputAction::Up => vec![Key(KeyCode::ArrowUp), Pad(GamepadButton::DPadUp)],
+            InputAction::Down => vec![Key(KeyCode::ArrowDown), Pad(GamepadButton::DPadDown)],
+            InputAction::Left => vec![Key(KeyCode::ArrowLeft), Pad(GamepadButton::DPadLeft)],
+            InputAction::Right => vec![Key(KeyCode::ArrowRight), Pad(GamepadButton::DPadRight)],
+            InputAction::Choose => vec![Key(KeyCode::Space), Pad(GamepadButton::South)],
+            InputAction::Decide => vec![Key(KeyCode::Enter), Pad(GamepadButton::North)],
+            InputAction::Execute => vec![
+                Key(KeyCode::KeyY),
+                Key(KeyCode::Enter),
+                Pad(GamepadButton::South),
+            ],
+            InputAction::Reselect => vec![
+                Key(KeyCode::KeyN),
+                Key(KeyCode::Escape),
+                Pad(GamepadButton::East),
+            ],
+            InputAction::Undo => vec![Key(KeyCode::Backspace), Pad(GamepadButton::East)],
+            InputAction::Clear => vec![Key(KeyCode::Escape), Pad(GamepadButton::West)],
+            InputAction::Remove => vec![
+                Key(KeyCode::Delete),
+                Key(KeyCode::Backspace),
+                Pad(GamepadButton::West),
+            ],
+            InputAction::AutoBattle => vec![Key(KeyCode::KeyG), Pad(GamepadButton::Select)],
+        }
+    }
+}
+
+// 行動に割り当てる入力（キーボードのキーかゲームパッドのボタン）
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Binding {
+    Key(KeyCode),
+    Pad(GamepadButton),
+}
+
+impl Binding {
+    // 設定ファイルでの名前（KeyCode の名前 / ゲームパッドは Pad + GamepadButton の名前）
+    fn name(self) -> String {
+        match self {
+            Binding::Key(key) => format!("{key:?}"),
+            Binding::Pad(button) => format!("Pad{button:?}"),
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Self> {
+        BINDABLE_KEYS
+            .into_iter()
+            .map(Binding::Key)
+            .chain(BINDABLE_BUTTONS.into_iter().map(Binding::Pad))
+            .find(|b| b.name() == name)
+    }
+
+    // 画面表示用の短い名前（KeyA → A / Escape → Esc / ゲームパッドは Xbox 配置で (A) など）
+    fn label(self) -> String {
+        let name = self.name();
+        match self {
+            Binding::Key(KeyCode::Escape) => "Esc".to_string(),
+            Binding::Key(KeyCode::Delete) => "Del".to_string(),
+            Binding::Key(KeyCode::ArrowUp) => "↑".to_string(),
+            Binding::Key(KeyCode::ArrowDown) => "↓".to_string(),
+            Binding::Key(KeyCode::ArrowLeft) => "←".to_string(),
+            Binding::Key(KeyCode::ArrowRight) => "→".to_string(),
+            Binding::Key(_) => name
+                .strip_prefix("Key")
+                .or_else(|| name.strip_prefix("Digit"))
+                .unwrap_or(&name)
+                .to_string(),
+            Binding::Pad(button) => {
+                let short = match button {
+                    GamepadButton::South => "A",
+                    GamepadButton::East => "B",
+                    GamepadButton::West => "X",
+                    GamepadButton::North => "Y",
+                    GamepadButton::LeftTrigger => "LB",
+                    GamepadButton::RightTrigger => "RB",
+                    GamepadButton::LeftTrigger2 => "LT",
+                    GamepadButton::RightTrigger2 => "RT",
+                    GamepadButton::Select => "Back",
+                    GamepadButton::LeftThumb => "LS",
+                    GamepadButton::RightThumb => "RS",
+                    GamepadButton::DPadUp => "↑",
+                    GamepadButton::DPadDown => "↓",
+                    GamepadButton::DPadLeft => "←",
+                    GamepadButton::DPadRight => "→",
+                    _ => &name["Pad".len()..],
+                };
+                format!("({short})")
+            }
+        }
+    }
+}
+
+// 割り当てられるキー（Shift は予約の編集、P/U/T/数字はポーズ・練習・訓練・2P対戦で使うので除く）
+const BINDABLE_KEYS: [KeyCode; 48] = [
+    KeyCode::KeyA,
+    KeyCode::KeyB,
+    KeyCode::KeyC,
+    KeyCode::KeyD,
+    KeyCode::KeyE,
+    KeyCode::KeyF,
+    KeyCode::KeyG,
+    KeyCode::KeyH,
+    KeyCode::KeyI,
+    KeyCode::KeyJ,
+    KeyCode::KeyK,
+    KeyCode::KeyL,
+    KeyCode::KeyM,
+    KeyCode::KeyN,
+    KeyCode::KeyO,
+    KeyCode::KeyQ,
+    KeyCode::KeyR,
+    KeyCode::KeyS,
+    KeyCode::KeyV,
+    KeyCode::KeyW,
+    KeyCode::KeyX,
+    KeyCode::KeyY,
+    KeyCode::KeyZ,
+    KeyCode::Digit0,
+    KeyCode::ArrowUp,
+    KeyCode::ArrowDown,
+    KeyCode::ArrowLeft,
+    KeyCode::ArrowRight,
+    KeyCode::Enter,
+    KeyCode::Space,
+    KeyCode::Backspace,
+    KeyCode::Delete,
+    KeyCode::Escape,
+    KeyCode::Tab,
+    KeyCode::Insert,
+    KeyCode::Home,
+    KeyCode::End,
+    KeyCode::PageUp,
+    KeyCode::PageDown,
+    KeyCode::Minus,
+    KeyCode::Equal,
+    KeyCode::Comma,
+    KeyCode::Period,
+    KeyCode::Slash,
+    KeyCode::Semicolon,
+    KeyCode::Quote,
+    KeyCode::BracketLeft,
+    KeyCode::BracketRight,
+];
+
+// 割り当てられるゲームパッドのボタン（左スティックは十字キーとして扱う）
+const BINDABLE_BUTTONS: [GamepadButton; 16] = [
+    GamepadButton::South,
+    GamepadButton::East,
+    GamepadButton::North,
+    GamepadButton::West,
+    GamepadButton::LeftTrigger,
+    GamepadButton::RightTrigger,
+    GamepadButton::LeftTrigger2,
+    GamepadButton::RightTrigger2,
+    GamepadButton::Select,
+    GamepadButton::Start,
+    GamepadButton::LeftThumb,
+    GamepadButton::RightThumb,
+    GamepadButton::DPadUp,
+    GamepadButton::DPadDown,
+    GamepadButton::DPadLeft,
+    GamepadButton::DPadRight,
+];
+
+// 接続中の全ゲームパッドをまとめたボタン入力（どのパッドからでも同じ操作になる）
+#[derive(Resource, Default)]
+struct PadInput {
+    buttons: ButtonInput<GamepadButton>,
+    connected: bool,
+}
+
+// 画面のボタン（マウス・タッチ）で押された行動。このフレームの分だけ持つ
+#[derive(Resource, Default)]
+struct PointerInput(Vec<InputAction>);
+
+// 今フレームの入力（キーボード・ゲームパッド・画面のボタン）。InputMap の判定に渡す
+#[derive(Clone, Copy)]
+struct Controls<'a> {
+    keyboard: &'a ButtonInput<KeyCode>,
+    pad: &'a PadInput,
+    pointer: &'a PointerInput,
+}
+
+impl Controls<'_> {
+    fn just_pressed(&self, binding: Binding) -> bool {
+        match binding {
+            Binding::Key(key) => self.keyboard.just_pressed(key),
+            Binding::Pad(button) => self.pad.buttons.just_pressed(button),
+        }
+    }
+
+    // このフレームで押された、割り当てに使える入力
+    fn first_just_pressed(&self) -> Option<Binding> {
+        BINDABLE_KEYS
+            .into_iter()
+            .map(Binding::Key)
+            .chain(BINDABLE_BUTTONS.into_iter().map(Binding::Pad))
+            .find(|&b| self.just_pressed(b))
+    }
+}
+
+// 全ゲームパッドのボタンを PadInput にまとめる。左スティックを倒すと十字キーを押したことにする
+fn gamepad_input_system(gamepads: Query<&Gamepad>, mut pad: ResMut<PadInput>) {
+    const STICK_THRESHOLD: f32 = 0.6;
+    pad.buttons.clear();
+    pad.connected = !gamepads.is_empty();
+    let mut down: Vec<GamepadButton> = Vec::new();
+    for gamepad in &gamepads {
+        down.extend(gamepad.get_pressed().copied());
+        let stick = gamepad.left_stick();
+        if stick.y > STICK_THRESHOLD {
+            down.push(GamepadButton::DPadUp);
+        } else if stick.y < -STICK_THRESHOLD {
+            down.push(GamepadButton::DPadDown);
+        }
+        if stick.x > STICK_THRESHOLD {
+            down.push(GamepadButton::DPadRight);
+        } else if stick.x < -STICK_THRESHOLD {
+            down.push(GamepadButton::DPadLeft);
+        }
+    }
+    for button in BINDABLE_BUTTONS {
+        if down.contains(&button) {
+            pad.buttons.press(button);
+        } else {
+            pad.buttons.release(button);
+        }
+    }
+}
+
+#[derive(Resource, Clone, PartialEq, Debug)]
+struct InputMap(Vec<(InputAction, Vec<Binding>)>);
+
+impl Default for InputMap {
+    fn default() -> Self {
+        InputMap(
+            InputAction::ALL
+                .into_iter()
+                .map(|a| (a, a.default_bindings()))
+                .collect(),
+        )
+    }
+}
+
+impl InputMap {
+    fn bindings(&self, action: InputAction) -> &[Binding] {
+        self.0
+            .iter()
+            .find(|(a, _)| *a == action)
+            .map_or(&[], |(_, bindings)| bindings.as_slice())
+    }
+
+    fn just_pressed(&self, controls: Controls, action: InputAction) -> bool {
+        controls.pointer.0.contains(&action)
+            || self
+                .bindings(action)
+                .iter()
+                .any(|&b| controls.just_pressed(b))
+    }
+
+    // このフレームで押されたコマンド（CommandKind::ALL の順）
+    fn commands_just_pressed(&self, controls: Controls) -> Vec<CommandKind> {
+        CommandKind::ALL
+            .into_iter()
+            .filter(|&cmd| self.just_pressed(controls, InputAction::Command(cmd)))
+            .collect()
+    }
+
+    // ヘルプ表示用（"Y/Enter"。割り当てが無ければ "未設定"）。pad=false ならゲームパッドのボタンは出さない
+    fn label(&self, action: InputAction, pad: bool) -> String {
+        let labels = self
+            .bindings(action)
+            .iter()
+            .filter(|b| pad || matches!(b, Binding::Key(_)))
+            .map(|b| b.label())
+            .collect::<Vec<_>>();
+        if labels.is_empty() {
+            return "未設定".to_string();
+        }
+        labels.join("/")
+    }
+
+    // "A=攻撃 S=強攻撃" のようなキー一覧
+    fn help(&self, actions: &[InputAction], pad: bool) -> String {
+        actions
+            .iter()
+            .map(|&a| format!("{}={}", self.label(a, pad), a.label()))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+
+    // action のキー（ゲームパッドのボタンなら、ボタン）を binding だけにする。
+    // 同じ場面で binding を使っていた行動からは外し、その行動を返す
+    fn bind(&mut self, action: InputAction, binding: Binding) -> Vec<InputAction> {
+        let same_device = |b: &Binding| {
+            matches!(
+                (b, binding),
+                (Binding::Key(_), Binding::Key(_)) | (Binding::Pad(_), Binding::Pad(_))
+            )
+        };
+        let mut unbound = Vec::new();
+        for (a, bindings) in self.0.iter_mut() {
+            if *a == action {
+                bindings.retain(|b| !same_device(b));
+                bindings.push(binding);
+            } else if a.scenes() & action.scenes() != 0 && bindings.contains(&binding) {
+                bindings.retain(|&b| b != binding);
+                unbound.push(*a);
+            }
+        }
+        unbound
+    }
+
+    // 1行に「行動名 = キー名, キー名」。書かれていない行動は既定のキーのまま
+    fn parse(src: &str) -> Result<Self, String> {
+        let mut map = InputMap::default();
+        for (n, line) in src.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (name, keys) = line
+                .split_once('=')
+                .ok_or_else(|| format!("{}行目: '=' がありません", n + 1))?;
+            let action = InputAction::ALL
+                .into_iter()
+                .find(|a| a.name() == name.trim())
+                .ok_or_else(|| format!("{}行目: 不明な行動 '{}'", n + 1, name.trim()))?;
+            let mut parsed = Vec::new();
+            for key in keys.split(',').map(str::trim).filter(|k| !k.is_empty()) {
+                parsed.push(
+                    Binding::from_name(key)
+                        .ok_or_else(|| format!("{}行目: 使えないキー '{key}'", n + 1))?,
+                );
+            }
+            if let Some((_, bindings)) = map.0.iter_mut().find(|(a, _)| *a == action) {
+                *bindings = parsed;
+            }
+        }
+        // 同じ場面で同じキーを2つの行動に割り当てていないか
+        for (i, (a, a_keys)) in map.0.iter().enumerate() {
+            for (b, b_keys) in &map.0[i + 1..] {
+                if a.scenes() & b.scenes() == 0 {
+                    continue;
+                }
+                if let Some(&key) = a_keys.iter().find(|k| b_keys.contains(k)) {
+                    return Err(format!(
+                        "{} が {} と {} の両方に割り当てられています",
+                        key.name(),
+                        a.name(),
+                        b.name()
+                    ));
+                }
+            }
+        }
+        Ok(map)
+    }
+
+    fn to_config(&self) -> String {
+        let mut out = String::from(
+            "# キー設定（行動名 = キー名, キー名 ...）。ゲーム内のキー設定画面で変えると上書きされる\n# キー名は Bevy の KeyCode の名前（KeyA, Digit0, Enter, Escape, Backspace, Delete, Space など）\n# ゲームパッドは Pad + GamepadButton の名前（PadSouth, PadEast, PadLeftTrigger, PadDPadUp など）\n",
+        );
+        for (action, bindings) in &self.0 {
+            let names = bindings.iter().map(|b| b.name()).collect::<Vec<_>>();
+            let line = format!("{} = {}", action.name(), names.join(", "));
+            out.push_str(line.trim_end());
+            out.push('\n');
+        }
+        out
+    }
+}
+
+// config/keybinds.txt、無ければ assets/keybinds.txt を読む（どちらも無い環境では既定のキー）
+fn load_input_map() -> Result<InputMap, String> {
+    match std::fs::read_to_string(USER_KEYBINDS_PATH)
+        .or_else(|_| std::fs::read_to_string(KEYBINDS_PATH))
+    {
+        Ok(src) => InputMap::parse(&src),
+        Err(_) => Ok(InputMap::default()),
+    }
+}
+
+// 変更は config/keybinds.txt に書く（assets/keybinds.txt は書き換えない）
+#[cfg(not(target_arch = "wasm32"))]
+fn save_input_map(map: &InputMap) -> Result<(), String> {
+    let path = std::path::Path::new(USER_KEYBINDS_PATH);
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    std::fs::write(path, map.to_config()).map_err(|e| e.to_string())
+}
+
+// ブラウザ版はファイルに書けないので保存しない（そのページを開いている間だけ有効）
+#[cfg(target_arch = "wasm32")]
+fn save_input_map(_map: &InputMap) -> Result<(), String> {
+    Ok(())
+}
+
+// ================== Boss Slain Banner ==================
+#[derive(Component)]
+struct BossSlainText; // ボス撃破表示用
+
+#[derive(Component)]
+struct BossSlainBanner {
+    elapsed: f32,
+    phase: BannerPhase,
+    hold: f32,              // 表示維持の秒数
+    text_color: LinearRgba, // 文字色（αはフェードで上書き）
+}
+
+// 勝敗のバナー（練習モードで決着後に巻き戻したら消す）
+#[derive(Component)]
+struct BattleEndBanner;
+
+// バナー背面の黒帯（グラデーション）
+#[derive(Component)]
+struct BossSlainBackdrop;
+#[derive(Component)]
+struct BossSlainBackdropCenter; // 中央の帯（不透明）
+#[derive(Component)]
+struct BossSlainBackdropRow; // グラデーション行
+
+enum BannerPhase {
+    FadeIn,
+    Hold,
+    FadeOut,
+}
+
+// ================== Setup ==================
+// コマンド入力パネルのキー一覧の行（通常 / 強化 / 奥義）
+const COMMAND_HELP_ROWS: [&[InputAction]; 3] = [
+    &[
+        InputAction::Command(CommandKind::Attack),
+        InputAction::Command(CommandKind::Skill),
+        InputAction::Command(CommandKind::Heal),
+        InputAction::Command(CommandKind::Defend),
+        InputAction::Command(CommandKind::Wait),
+    ],
+    &[
+        InputAction::Command(CommandKind::EnhanceAttack),
+        InputAction::Command(CommandKind::EnhanceSkill),
+        InputAction::Command(CommandKind::EnhanceHeal),
+        InputAction::Command(CommandKind::EnhanceDefend),
+    ],
+    &[InputAction::Command(CommandKind::Finisher)],
+];
+
+fn command_prompt(input: &InputMap) -> String {
+    format!(
+        "コマンドを選択してください ({} / {} / {} / {})",
+        input.help(COMMAND_HELP_ROWS[0], false),
+        input.help(&[InputAction::Undo], false),
+        input.help(&[InputAction::Clear], false),
+        input.help(&[InputAction::Decide], false)
+    )
+}
+
+// 戦闘の状態（エンティティとリソース）。画面ありでもヘッドレスでも共通
+fn setup_battle(
+    mut commands: Commands,
+    difficulty: Res<Difficulty>,
+    seed: Res<BattleSeed>,
+    practice: Option<ResMut<Practice>>,
+    training: Option<Res<Training>>,
+    input: Option<Res<InputMap>>,
+) {
+    let difficulty = *difficulty;
+    let seed = seed.0.unwrap_or_else(rand::random);
+    commands.spawn((DespawnOnExit(BattleSession::Open), Player, create_player()));
+    commands.spawn((
+        DespawnOnExit(BattleSession::Open),
+        Enemy,
+        create_enemy(difficulty),
+    ));
+    commands.insert_resource(BattlePhase::AwaitCommand);
+    commands.insert_resource(Turn(1));
+    // 初期ログと敵行動決定
+    let mut rng = BattleRng::seeded(seed);
+    let first_action = plan_opening_action(&mut rng, difficulty);
+    let mut initial_log = vec![
+        format!("難易度: {}", difficulty.label()),
+        format!("初期敵行動: {}", first_action.revealed_name(difficulty)),
+        // ヘッドレスではキー入力が無いので既定のキーで表示する
+        command_prompt(&input.as_deref().cloned().unwrap_or_default()),
+    ];
+    if let Some(mut practice) = practice {
+        practice.history.clear();
+        if practice.enabled {
+            initial_log.push(format!("練習モード (シード {seed}) U=1ターン戻す"));
+        }
+    }
+    if training.is_some_and(|t| t.enabled) {
+        initial_log.push("訓練モード T=設定画面".to_string());
+    }
+    // コンボのリセット条件（読み込みに失敗したら既定の条件を使う）
+    let combo_rules = load_combo_rules().unwrap_or_else(|e| {
+        initial_log.push(format!(
+            "コンボ設定の読み込みに失敗: {e} / 既定の条件を使用"
+        ));
+        ComboResetRules::default()
+    });
+    // オートバトル用ガンビット（読み込みに失敗したら既定のルールを使う）
+    let script = load_gambit_script().unwrap_or_else(|e| {
+        initial_log.push(format!(
+            "ガンビットの読み込みに失敗: {e} / 既定のルールを使用"
+        ));
+        GambitScript::parse(DEFAULT_GAMBITS).unwrap_or_default()
+    });
+    commands.insert_resource(AutoBattle {
+        enabled: false,
+        script,
+        timer: 0.0,
+        fire: false,
+    });
+    commands.insert_resource(CombatLog(initial_log));
+    commands.insert_resource(DefendNextAttack::default());
+    commands.insert_resource(GuardCounterReady::default());
+    commands.insert_resource(CommandQueue::default());
+    commands.insert_resource(PlayerComboState::default());
+    commands.insert_resource(combo_rules);
+    commands.insert_resource(PendingSelections::default());
+    commands.insert_resource(first_action);
+    commands.insert_resource(rng);
+    commands.insert_resource(TurnEvents::default());
+    commands.insert_resource(EnemyMoveChoice::default());
+    commands.insert_resource(Momentum { current: 0 });
+    commands.insert_resource(Overdrive::default());
+    commands.insert_resource(ConsecutiveBatch::default());
+    commands.insert_resource(CommandBuffs::default());
+    commands.insert_resource(EnemyDamagePopup::default());
+    commands.insert_resource(FinisherCinematic::default());
+    commands.insert_resource(BattleStats {
+        difficulty,
+        ..default()
+    });
+}
+
+fn setup_camera(mut commands: Commands) {
+    commands.spawn(Camera2d);
+}
+
+// 戦闘画面のUI（ルートはすべて戦闘終了で消える）
+fn setup(mut commands: Commands, asset_server: Res<AssetServer>, input: Res<InputMap>) {
+    let font = asset_server.load("fonts/x12y16pxMaruMonica.ttf");
+
+    // 画面下のログメッセージ（白枠、最大10行）
+    commands
+        .spawn((
+            DespawnOnExit(BattleSession::Open),
+            Node {
+                width: Val::Px(750.0),
+                height: Val::Auto,
+                position_type: PositionType::Absolute,
+                right: Val::Px(12.0),
+                bottom: Val::Px(12.0),
+                border: UiRect::all(Val::Px(1.0)),
+                padding: UiRect::all(Val::Px(8.0)),
+                ..default()
+            },
+            BackgroundColor(Color::from(LinearRgba {
+                red: 0.0,
+                green: 0.0,
+                blue: 0.0,
+                alpha: 0.6,
+            })),
+            BorderColor::all(Color::WHITE),
+            ZIndex(10),
+        ))
+        .with_children(|col| {
+            col.spawn((
+                UiMessage,
+                Text::new(""),
+                TextFont {
+                    font: font.clone(),
+                    font_size: 16.0,
+                    ..default()
+                },
+                TextColor(Color::WHITE),
+            ));
+        });
+
+    // 敵UI（中央配置）: 画像の上にHP/ブレイクゲージと次の行動を表示
+    let dragon = asset_server.load("images/dragon.png");
+    commands
+        .spawn((
+            DespawnOnExit(BattleSession::Open),
+            UiEnemy,
+            Node {
+                width: percent(100),
+                height: percent(100),
+                position_type: PositionType::Absolute,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            ZIndex(0),
+        ))
+        .with_children(|center| {
+            // 画像コンテナ（相対位置指定にしてオーバーレイをAbsoluteで配置）
+            center
+                .spawn((
+                    Node {
+                        width: Val::Px(512.0),
+                        height: Val::Px(384.0),
+                        position_type: PositionType::Relative,
+                        border: UiRect::all(Val::Px(1.0)),
+                        ..default()
                     },
                     BorderColor::all(Color::WHITE),
                     ImageNode::new(dragon.clone()),
@@ -733,12 +1252,24 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
                                 },
                                 TextColor(Color::WHITE),
                             ));
+                            // 予告パネル（残りステップと予想ダメージ）
+                            col.spawn((
+                                UiEnemyTelegraphText,
+                                Text::new(""),
+                                TextFont {
+                                    font: font.clone(),
+                                    font_size: 14.0,
+                                    ..default()
+                                },
+                                TextColor(Color::srgb(0.85, 0.85, 0.85)),
+                            ));
                         });
                 });
         });
     // 右上にプレイヤーステータス枠（HP/スタミナの文字とゲージ、モメンタム表示）
     commands
         .spawn((
+            DespawnOnExit(BattleSession::Open),
             UiPlayerStatus,
             Node {
                 width: Val::Px(280.0),
@@ -855,6 +1386,49 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
                 },
                 TextColor(Color::WHITE),
             ));
+            // オーバードライブ表示テキスト
+            col.spawn((
+                UiOverdriveText,
+                Text::new("オーバードライブ: --- / 100"),
+                TextFont {
+                    font: font.clone(),
+                    font_size: 16.0,
+                    ..default()
+                },
+                TextColor(Color::WHITE),
+            ));
+            // オーバードライブゲージ（枠）
+            col.spawn((
+                Node {
+                    width: percent(100),
+                    height: Val::Px(8.0),
+                    border: UiRect::all(Val::Px(1.0)),
+                    ..default()
+                },
+                BackgroundColor(Color::from(LinearRgba {
+                    red: 0.15,
+                    green: 0.15,
+                    blue: 0.15,
+                    alpha: 1.0,
+                })),
+                BorderColor::all(Color::WHITE),
+            ))
+            .with_children(|g| {
+                g.spawn((
+                    UiOverdriveGaugeFill,
+                    Node {
+                        width: percent(0),
+                        height: percent(100),
+                        ..default()
+                    },
+                    BackgroundColor(Color::from(LinearRgba {
+                        red: 0.95,
+                        green: 0.55,
+                        blue: 0.15,
+                        alpha: 1.0,
+                    })),
+                ));
+            });
             // 強化状態表示テキスト
             col.spawn((
                 UiBuffsText,
@@ -866,16 +1440,29 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
                 },
                 TextColor(Color::WHITE),
             ));
+            // コンボ表示テキスト
+            col.spawn((
+                UiComboText,
+                Text::new("コンボ: なし"),
+                TextFont {
+                    font: font.clone(),
+                    font_size: 16.0,
+                    ..default()
+                },
+                TextColor(Color::WHITE),
+            ));
         });
 
     // 画面右端のコマンド入力表示（白枠）
     commands
         .spawn((
+            DespawnOnExit(BattleSession::Open),
             UiCommand,
             Node {
-                width: Val::Px(320.0),
+                width: Val::Px(if USE_DQ_LIKE_LAYOUT { 360.0 } else { 320.0 }),
                 height: Val::Auto,
                 position_type: PositionType::Absolute,
+                flex_direction: FlexDirection::Column,
                 left: Val::Px(12.0),
                 bottom: Val::Px(16.0),
                 border: UiRect::all(Val::Px(1.0)),
@@ -887,6 +1474,73 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
             Visibility::Hidden, // 初期は非表示
             ZIndex(10),
         ))
+        .with_children(|col| {
+            col.spawn((
+                Text::new(""),
+                TextFont {
+                    font: font.clone(),
+                    font_size: 16.0,
+                    ..default()
+                },
+                TextColor(Color::WHITE),
+            ));
+            if USE_DQ_LIKE_LAYOUT {
+                spawn_command_menu(col, &font);
+            }
+        });
+
+    // 予測プレビュー（コマンド入力パネルの右）
+    commands
+        .spawn((
+            DespawnOnExit(BattleSession::Open),
+            UiBatchPreview,
+            Node {
+                width: Val::Px(400.0),
+                height: Val::Auto,
+                position_type: PositionType::Absolute,
+                left: Val::Px(if USE_DQ_LIKE_LAYOUT { 384.0 } else { 344.0 }),
+                bottom: Val::Px(16.0),
+                border: UiRect::all(Val::Px(1.0)),
+                padding: UiRect::all(Val::Px(8.0)),
+                ..default()
+            },
+            BackgroundColor(Color::BLACK),
+            BorderColor::all(Color::WHITE),
+            Visibility::Hidden,
+            ZIndex(10),
+        ))
+        .with_children(|col| {
+            col.spawn((
+                Text::new(""),
+                TextFont {
+                    font: font.clone(),
+                    font_size: 14.0,
+                    ..default()
+                },
+                TextColor(Color::WHITE),
+            ));
+        });
+
+    // 訓練モードの設定画面（画面上部の中央）
+    commands
+        .spawn((
+            DespawnOnExit(BattleSession::Open),
+            UiTrainingPanel,
+            Node {
+                width: Val::Px(460.0),
+                height: Val::Auto,
+                position_type: PositionType::Absolute,
+                left: Val::Percent(30.0),
+                top: Val::Px(60.0),
+                border: UiRect::all(Val::Px(1.0)),
+                padding: UiRect::all(Val::Px(8.0)),
+                ..default()
+            },
+            BackgroundColor(Color::BLACK),
+            BorderColor::all(Color::WHITE),
+            Visibility::Hidden,
+            ZIndex(20),
+        ))
         .with_children(|col| {
             col.spawn((
                 Text::new(""),
@@ -902,13 +1556,15 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
     // コマンド説明（コマンド入力パネルの上に固定表示）
     commands
         .spawn((
+            DespawnOnExit(BattleSession::Open),
             UiCommandHelp,
             Node {
                 width: Val::Px(320.0),
                 height: Val::Auto,
                 position_type: PositionType::Absolute,
                 left: Val::Px(12.0),
-                bottom: Val::Px(180.0), // 入力パネルの上に来るようマージン多め
+                // 入力パネルの上に来るようマージン多め（コマンド一覧がある分さらに上）
+                bottom: Val::Px(if USE_DQ_LIKE_LAYOUT { 320.0 } else { 180.0 }),
                 border: UiRect::all(Val::Px(1.0)),
                 padding: UiRect::all(Val::Px(8.0)),
                 ..default()
@@ -933,7 +1589,11 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
 回復:   消費15/回復50 (強化中: 消費20 / 回復60)
 防御:   消費10/次の敵攻撃を無効化 (強化中: 消費5)
 待機:   消費0/スタミナ+60
-強化:   モメンタム50消費 / 11ターン持続\n",
+強化:   モメンタム50消費 / 11ターン持続
+奥義:   モメンタム100消費/威力120/ブレイク50
+        強化中のコマンド1つにつき威力+30,ブレイク+15
+連続:   2件目モメンタム+15, 3件目+25, 以降+10ずつ
+        選択数は基本3 + モメンタム50ごとに+1\n",
                 ),
                 TextFont {
                     font: font.clone(),
@@ -943,17 +1603,133 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
                 TextColor(Color::WHITE),
             ));
         });
-    println!(
-        "ゲーム開始: A=攻撃 S=強攻撃 H=回復(+50) D=防御 W=待機(+スタミナ50回復) / Backspace=直前取り消し / Esc=全クリア / Enter=決定"
-    );
+    println!("ゲーム開始: {}", command_prompt(&input));
 }
 
 // ================== Input & Battle Resolution ==================
+// assets/gambits.txt を読む（ファイルが無い環境では同梱の既定ルール）
+fn load_gambit_script() -> Result<GambitScript, String> {
+    let src = std::fs::read_to_string("assets/gambits.txt")
+        .unwrap_or_else(|_| DEFAULT_GAMBITS.to_string());
+    GambitScript::parse(&src)
+}
+
+// assets/combo_rules.txt を読む（ファイルが無い環境では同梱の既定条件）
+fn load_combo_rules() -> Result<ComboResetRules, String> {
+    let src = std::fs::read_to_string("assets/combo_rules.txt")
+        .unwrap_or_else(|_| DEFAULT_COMBO_RULES.to_string());
+    ComboResetRules::parse(&src)
+}
+
+// オートバトル: G で切替。一定間隔でガンビットからバッチを選び、決定/予約実行を自動で行う
+fn auto_battle_system(
+    time: Res<Time>,
+    keys: (
+        Res<ButtonInput<KeyCode>>,
+        Res<PadInput>,
+        Res<PointerInput>,
+        Res<InputMap>,
+    ),
+    phase: Res<BattlePhase>,
+    queue: Res<CommandQueue>,
+    player_q: Query<(&Hp, &Stamina, &BatchLimit), With<Player>>,
+    enemy_q: Query<(&BreakValue, &BreakState), With<Enemy>>,
+    enemy_view: (Res<EnemyPlannedAction>, Res<Difficulty>),
+    gauges: (Res<Momentum>, Res<Overdrive>),
+    buffs: Res<CommandBuffs>,
+    guard: Res<GuardCounterReady>,
+    mut pending: ResMut<PendingSelections>,
+    mut auto: ResMut<AutoBattle>,
+    mut log: ResMut<CombatLog>,
+) {
+    const INTERVAL: f32 = 0.6;
+    let (momentum, overdrive) = gauges;
+    let (keyboard, pad, pointer, input) = keys;
+    let controls = Controls {
+        keyboard: &keyboard,
+        pad: &pad,
+        pointer: &pointer,
+    };
+    if input.just_pressed(controls, InputAction::AutoBattle) {
+        auto.enabled = !auto.enabled;
+        auto.timer = INTERVAL;
+        log.0.push(if auto.enabled {
+            format!("オートバトル開始 (ガンビット{}件)", auto.script.0.len())
+        } else {
+            "オートバトル停止".to_string()
+        });
+    }
+    if !auto.enabled
+        || !matches!(
+            *phase,
+            BattlePhase::AwaitCommand | BattlePhase::ConfirmQueued
+        )
+    {
+        return;
+    }
+    auto.timer -= time.delta_secs();
+    if auto.timer > 0.0 {
+        return;
+    }
+    auto.timer = INTERVAL;
+
+    // 予約が残っている/手動で選択中ならそれをそのまま実行する
+    if *phase == BattlePhase::ConfirmQueued || !queue.0.is_empty() || !pending.0.is_empty() {
+        auto.fire = true;
+        return;
+    }
+    let Ok((p_hp, p_sta, p_limit)) = player_q.single() else {
+        return;
+    };
+    let Ok((e_break, e_bstate)) = enemy_q.single() else {
+        return;
+    };
+    // 難易度で伏せられた予定は見ない（プレイヤーと同じ情報で判断する）
+    let enemy_next = EnemyNextKind::of(&enemy_view.0, *enemy_view.1);
+    let view = GambitView {
+        hp: p_hp.current,
+        hp_max: p_hp.max,
+        stamina: p_sta.current,
+        momentum: momentum.current,
+        overdrive: overdrive.gauge,
+        overdrive_ready: overdrive.is_ready(),
+        enemy_break: e_break.current,
+        enemy_broken: e_bstate.remaining_turns > 0,
+        enemy_next,
+        guard_counter: guard.0,
+        buffs: [
+            buffs.attack > 0,
+            buffs.skill > 0,
+            buffs.heal > 0,
+            buffs.defend > 0,
+        ],
+    };
+    // どのルールにも当てはまらなければ待機。最大選択数を超えた分は捨てる
+    let (mut commands, reason) = match auto.script.choose(&view) {
+        Some((i, g)) => (g.commands.clone(), format!("#{} {}", i + 1, g.source)),
+        None => (vec![CommandKind::Wait], "該当ルールなし".to_string()),
+    };
+    commands.truncate(p_limit.current(&momentum, &overdrive));
+    let names = commands
+        .iter()
+        .map(|c| c.label())
+        .collect::<Vec<_>>()
+        .join(", ");
+    log.0.push(format!("オート: {names} ({reason})"));
+    pending.0.extend(commands);
+    auto.fire = true;
+}
+
 fn player_input_system(
-    keyboard: Res<ButtonInput<KeyCode>>,
+    keys: (
+        Res<ButtonInput<KeyCode>>,
+        Res<PadInput>,
+        Res<PointerInput>,
+        Res<InputMap>,
+    ),
     mut phase: ResMut<BattlePhase>,
     mut turn: ResMut<Turn>,
-    mut player_q: Query<(&Attack, &mut Hp), (With<Player>, Without<Enemy>)>,
+    mut player_q: Query<(&Attack, &mut Hp, &BatchLimit), (With<Player>, Without<Enemy>)>,
     mut player_sta_q: Query<&mut Stamina, With<Player>>,
     mut enemy_q: Query<
         (
@@ -966,514 +1742,104 @@ fn player_input_system(
         (With<Enemy>, Without<Player>),
     >,
     mut log: ResMut<CombatLog>,
-    mut def_guard: ParamSet<(ResMut<DefendNextAttack>, ResMut<GuardCounterReady>)>,
+    mut def_guard: (ResMut<DefendNextAttack>, ResMut<GuardCounterReady>),
     mut queue: ResMut<CommandQueue>,
-    mut chain_state: ResMut<PlayerChainState>,
-    mut pending: ResMut<PendingSelections>,
-    mut planned: ResMut<EnemyPlannedAction>,
-    mut momentum: ResMut<Momentum>,
+    mut combo: (
+        ResMut<PlayerComboState>,
+        Res<ComboResetRules>,
+        Res<Difficulty>,
+    ),
+    mut selection: (
+        ResMut<PendingSelections>,
+        ResMut<AutoBattle>,
+        Res<Hotseat>,
+        ResMut<CommandCursor>,
+    ),
+    mut enemy_plan: (
+        ResMut<EnemyPlannedAction>,
+        ResMut<BattleRng>,
+        ResMut<TurnEvents>,
+        ResMut<EnemyMoveChoice>,
+    ),
+    mut gauges: (ResMut<Momentum>, ResMut<Overdrive>, ResMut<BattleStats>),
     mut buffs: ResMut<CommandBuffs>,
     mut batch: ResMut<ConsecutiveBatch>,
-    mut enemy_damage_popup: ResMut<EnemyDamagePopup>,
+    mut effects: (ResMut<EnemyDamagePopup>, ResMut<FinisherCinematic>),
 ) {
     if *phase == BattlePhase::Finished {
         return;
     }
-    let Ok((p_attack, mut p_hp)) = player_q.single_mut() else {
+    let Ok((p_attack, mut p_hp, p_limit)) = player_q.single_mut() else {
         return;
     };
     let Ok(mut p_sta) = player_sta_q.single_mut() else {
         return;
     };
+    let (momentum, overdrive, stats) = &mut gauges;
+    // 集計用（バッチ内のモメンタム加算より前の値）
+    let momentum_before = momentum.current;
+    let (pending, auto, hotseat, cursor) = &mut selection;
+    let controls = Controls {
+        keyboard: &keys.0,
+        pad: &keys.1,
+        pointer: &keys.2,
+    };
+    let input = &*keys.3;
+    // オートバトルの自動入力（実行・決定キーと同じ扱い）
+    let auto_fire = std::mem::take(&mut auto.fire);
+    // 2P対戦: ドラゴン側が次の行動を選ぶまでターンを進めない
+    let waiting_dragon =
+        hotseat.0 && enemy_plan.3.0.is_none() && enemy_choice_needed(&enemy_plan.0);
     let Ok((e_attack, mut e_hp, mut e_break, mut e_bstate, mut e_bregen)) = enemy_q.single_mut()
     else {
         return;
     };
 
-    // def_guard は必要に応じて分割取得します
-
-    // 連続コマンド確認フェーズの処理（Y=実行 / N=選択しなおし）
+    let mut commands_to_process: Vec<CommandKind> = Vec::new();
+    // 連続コマンド確認フェーズの処理（実行 / 選び直し。キーは InputMap）
     if *phase == BattlePhase::ConfirmQueued {
         // キューが空なら待機に戻る
         if queue.0.front().is_none() {
             *phase = BattlePhase::AwaitCommand;
             return;
         }
-        // 実行確定（YまたはEnter）
-        if keyboard.just_pressed(KeyCode::KeyY) || keyboard.just_pressed(KeyCode::Enter) {
-            if let Some(next) = queue.0.pop_front() {
-                // 実行前に現在の実行回数で加算判定（2回目:+15, 3回目:+25）
-                match batch.executed + 1 {
-                    1 => {
-                        let before = momentum.current;
-                        momentum.current = (momentum.current + 15).min(100);
-                        let gained = momentum.current - before;
-                        if gained > 0 {
-                            log.0.push(format!(
-                                "モメンタムが{}増加 ({} → {} / 100)",
-                                gained, before, momentum.current
-                            ));
-                        }
-                    }
-                    2 => {
-                        let before = momentum.current;
-                        momentum.current = (momentum.current + 25).min(100);
-                        let gained = momentum.current - before;
-                        if gained > 0 {
-                            log.0.push(format!(
-                                "モメンタムが{}増加 ({} → {} / 100)",
-                                gained, before, momentum.current
-                            ));
-                        }
-                    }
-                    _ => {}
-                }
-                batch.executed += 1;
-                // この後の通常解決フローで処理する
-                let mut commands_to_process: Vec<CommandKind> = vec![next];
-                // 共通のコマンド解決処理
-                let mut resolve_command = |cmd: CommandKind| {
-                    *phase = BattlePhase::InBattle;
-                    let guard_ready_at_start = {
-                        let g = def_guard.p1();
-                        g.0
-                    };
-                    let name = match cmd {
-                        CommandKind::Attack => "攻撃",
-                        CommandKind::Skill => "強攻撃",
-                        CommandKind::Heal => "回復",
-                        CommandKind::Defend => "防御",
-                        CommandKind::Wait => "待機",
-                        CommandKind::EnhanceAttack => "攻撃強化",
-                        CommandKind::EnhanceSkill => "強攻撃強化",
-                        CommandKind::EnhanceHeal => "回復強化",
-                        CommandKind::EnhanceDefend => "防御強化",
-                    };
-                    log.0
-                        .push(format!("ターン {} プレイヤーは{}を選択", turn.0, name));
-                    // 連撃判定（直前が攻撃または強攻撃 かつ 今回が攻撃）
-                    let is_chain =
-                        chain_state.last_was_attack && matches!(cmd, CommandKind::Attack);
-
-                    // コストチェック（実行時にも確認）。不足なら行動失敗。
-                    let cost = match cmd {
-                        CommandKind::Attack => {
-                            if is_chain {
-                                5
-                            } else {
-                                15
-                            }
-                        }
-                        CommandKind::Skill => 25,
-                        CommandKind::Heal => {
-                            if buffs.heal > 0 {
-                                20
-                            } else {
-                                15
-                            }
-                        }
-                        CommandKind::Defend => {
-                            if buffs.defend > 0 {
-                                5
-                            } else {
-                                10
-                            }
-                        }
-                        CommandKind::Wait => 0,
-                        CommandKind::EnhanceAttack
-                        | CommandKind::EnhanceSkill
-                        | CommandKind::EnhanceHeal
-                        | CommandKind::EnhanceDefend => 0,
-                    };
-                    if p_sta.current < cost {
-                        log.0.push("スタミナ不足で行動できませんでした".to_string());
-                        // 実行失敗なので連撃を継続させない
-                        chain_state.last_was_attack = false;
-                    } else {
-                        p_sta.current -= cost;
-
-                        match cmd {
-                            CommandKind::EnhanceAttack => {
-                                if buffs.attack > 0 {
-                                    log.0
-                                        .push("攻撃は既に強化中のため強化できません".to_string());
-                                } else if momentum.current < 50 {
-                                    log.0.push(
-                                        "モメンタム不足で強化できませんでした (必要50)".to_string(),
-                                    );
-                                } else {
-                                    momentum.current -= 50;
-                                    buffs.attack = 11;
-                                    log.0.push(
-                                        "攻撃を強化した (11ターン持続, モメンタム-50)".to_string(),
-                                    );
-                                }
-                            }
-                            CommandKind::EnhanceSkill => {
-                                if buffs.skill > 0 {
-                                    log.0
-                                        .push("強攻撃は既に強化中のため強化できません".to_string());
-                                } else if momentum.current < 50 {
-                                    log.0.push(
-                                        "モメンタム不足で強化できませんでした (必要50)".to_string(),
-                                    );
-                                } else {
-                                    momentum.current -= 50;
-                                    buffs.skill = 11;
-                                    log.0.push(
-                                        "強攻撃を強化した (11ターン持続, モメンタム-50)"
-                                            .to_string(),
-                                    );
-                                }
-                            }
-                            CommandKind::EnhanceHeal => {
-                                if buffs.heal > 0 {
-                                    log.0
-                                        .push("回復は既に強化中のため強化できません".to_string());
-                                } else if momentum.current < 50 {
-                                    log.0.push(
-                                        "モメンタム不足で強化できませんでした (必要50)".to_string(),
-                                    );
-                                } else {
-                                    momentum.current -= 50;
-                                    buffs.heal = 11;
-                                    log.0.push(
-                                        "回復を強化した (11ターン持続, モメンタム-50)".to_string(),
-                                    );
-                                }
-                            }
-                            CommandKind::EnhanceDefend => {
-                                if buffs.defend > 0 {
-                                    log.0
-                                        .push("防御は既に強化中のため強化できません".to_string());
-                                } else if momentum.current < 50 {
-                                    log.0.push(
-                                        "モメンタム不足で強化できませんでした (必要50)".to_string(),
-                                    );
-                                } else {
-                                    momentum.current -= 50;
-                                    buffs.defend = 11;
-                                    log.0.push(
-                                        "防御を強化した (11ターン持続, モメンタム-50)".to_string(),
-                                    );
-                                }
-                            }
-                            CommandKind::Heal => {
-                                let amount = if buffs.heal > 0 { 60 } else { 50 };
-                                let before = p_hp.current;
-                                p_hp.current = (p_hp.current + amount).min(p_hp.max);
-                                let healed = p_hp.current - before;
-                                log.0.push(format!(
-                                    "プレイヤーは{}回復 (HP {} / {})",
-                                    healed, p_hp.current, p_hp.max
-                                ));
-                            }
-                            CommandKind::Defend => {
-                                {
-                                    let mut d = def_guard.p0();
-                                    d.0 = true;
-                                }
-                                {
-                                    let mut g = def_guard.p1();
-                                    g.0 = true; // 次プレイヤー行動のガードカウンター猶予
-                                }
-                                log.0.push(
-                                    "プレイヤーは防御態勢に入った (次の敵攻撃は無効)".to_string(),
-                                );
-                                log.0.push(
-                                    "ガードカウンターの構え! 次の行動で強攻撃が強化".to_string(),
-                                );
-                            }
-                            CommandKind::Attack => {
-                                let base = if buffs.attack > 0 { 25 } else { p_attack.0 };
-                                let mut dmg = base;
-                                let mut break_bonus = 0;
-                                if e_bstate.remaining_turns > 0 {
-                                    break_bonus = 30 + base * 2;
-                                    dmg = base + break_bonus;
-                                }
-                                e_hp.current = (e_hp.current - dmg).max(0);
-                                // 敵ダメージポップアップ設定
-                                enemy_damage_popup.amount = dmg;
-                                enemy_damage_popup.timer = 1.2;
-                                if is_chain {
-                                    if break_bonus > 0 {
-                                        log.0.push(format!(
-                                            "連撃! 敵に{}ダメージ (基本{} + ブレイク補正{} = 合計{}, 敵HP {} / {})",
-                                            dmg, base, break_bonus, dmg, e_hp.current, e_hp.max
-                                        ));
-                                    } else {
-                                        log.0.push(format!(
-                                            "連撃! 敵に{}ダメージ (消費スタミナ半減, 敵HP {} / {})",
-                                            dmg, e_hp.current, e_hp.max
-                                        ));
-                                    }
-                                } else {
-                                    if break_bonus > 0 {
-                                        log.0.push(format!(
-                                            "敵に{}ダメージ (基本{} + ブレイク補正{} = 合計{}, 敵HP {} / {})",
-                                            dmg, base, break_bonus, dmg, e_hp.current, e_hp.max
-                                        ));
-                                    } else {
-                                        log.0.push(format!(
-                                            "敵に{}ダメージ (敵HP {} / {})",
-                                            dmg, e_hp.current, e_hp.max
-                                        ));
-                                    }
-                                }
-                                // ブレイク値加算（攻撃時の固定増加量: 通常15・強化時25）
-                                let before_break = e_break.current;
-                                let add_break = if buffs.attack > 0 { 25 } else { 10 };
-                                e_break.current += add_break;
-                                log.0.push(format!(
-                                    "ブレイク値 +{} ({} → {} / 100)",
-                                    add_break, before_break, e_break.current
-                                ));
-                                // ダメージを受けたので自然回復量をリセット
-                                e_bregen.amount = 1;
-                            }
-                            CommandKind::Skill => {
-                                let mut base = if buffs.skill > 0 { 45 } else { 25 };
-                                let is_guard_counter = guard_ready_at_start;
-                                if is_guard_counter {
-                                    base += 5; // ガードカウンター: 威力+5
-                                }
-                                let mut dmg = base;
-                                let mut break_bonus = 0;
-                                if e_bstate.remaining_turns > 0 {
-                                    break_bonus = 30 + base * 2;
-                                    dmg = base + break_bonus;
-                                }
-                                e_hp.current = (e_hp.current - dmg).max(0);
-                                // 敵ダメージポップアップ設定
-                                enemy_damage_popup.amount = dmg;
-                                enemy_damage_popup.timer = 1.2;
-                                if is_guard_counter {
-                                    if break_bonus > 0 {
-                                        log.0.push(format!(
-                                            "ガードカウンター! 敵に{}ダメージ (基本{} + ブレイク補正{} = 合計{}, 敵HP {} / {})",
-                                            dmg, base, break_bonus, dmg, e_hp.current, e_hp.max
-                                        ));
-                                    } else {
-                                        log.0.push(format!(
-                                            "ガードカウンター! 敵に{}ダメージ (敵HP {} / {})",
-                                            dmg, e_hp.current, e_hp.max
-                                        ));
-                                    }
-                                } else {
-                                    if break_bonus > 0 {
-                                        log.0.push(format!(
-                                            "敵に{}ダメージ (基本{} + ブレイク補正{} = 合計{}, 敵HP {} / {})",
-                                            dmg, base, break_bonus, dmg, e_hp.current, e_hp.max
-                                        ));
-                                    } else {
-                                        log.0.push(format!(
-                                            "敵に{}ダメージ (敵HP {} / {})",
-                                            dmg, e_hp.current, e_hp.max
-                                        ));
-                                    }
-                                }
-                                let before_break = e_break.current;
-                                let mut add_break = if buffs.skill > 0 { 40 } else { 25 };
-                                if is_guard_counter {
-                                    add_break += 20; // ガードカウンター: ブレイク+20
-                                }
-                                e_break.current += add_break;
-                                log.0.push(format!(
-                                    "ブレイク値 +{} ({} → {} / 100)",
-                                    add_break, before_break, e_break.current
-                                ));
-                                e_bregen.amount = 1;
-                            }
-                            CommandKind::Wait => {
-                                let before = p_sta.current;
-                                p_sta.current = (p_sta.current + 60).min(p_sta.max);
-                                let recovered = p_sta.current - before;
-                                log.0.push(format!(
-                                    "プレイヤーは待機してスタミナを{}回復 (Stamina {} / {})",
-                                    recovered, p_sta.current, p_sta.max
-                                ));
-                            }
-                        }
-                        // 実行成功: 直前が攻撃または強攻撃だったかを更新（強攻撃後の攻撃も連撃にする）
-                        chain_state.last_was_attack =
-                            matches!(cmd, CommandKind::Attack | CommandKind::Skill);
-                        // ガードカウンター猶予の消費: 防御以外の行動で消費
-                        if !matches!(cmd, CommandKind::Defend) {
-                            let mut g = def_guard.p1();
-                            g.0 = false;
-                        }
-                    }
-
-                    // プレイヤーの攻撃/強攻撃後にブレイク判定。閾値到達でこのターンの敵行動をキャンセルし、次ターンから4ターンブレイク。
-                    let mut enemy_action_canceled_this_turn = false;
-                    if e_break.current >= 100 && e_bstate.remaining_turns == 0 {
-                        enemy_action_canceled_this_turn = true;
-                        e_bstate.remaining_turns = 4; // 次ターンから4ターン行動不能
-                        log.0.push(
-                            "敵がブレイク状態に入る!（次のターンから4ターン行動不能・被ダメ2倍）"
-                                .to_string(),
-                        );
-                    }
-
-                    if e_hp.current > 0 {
-                        // 事前決定済みの敵行動を実行
-                        if e_bstate.remaining_turns > 0 {
-                            // ブレイク中は行動不能
-                            log.0.push("敵はブレイク中のため行動不能".to_string());
-                        } else if enemy_action_canceled_this_turn {
-                            // このターンの行動はキャンセル
-                            log.0.push("敵の行動はブレイクによりキャンセル".to_string());
-                        } else {
-                            let action = &mut planned.0;
-                            let step = action.current_step().unwrap();
-                            match step.specification {
-                                ActionStepSpecificationEnum::Attack(spec) => {
-                                    let mut incoming = (e_attack.0 as f32 * spec.power) as i32;
-                                    {
-                                        let mut d = def_guard.p0();
-                                        if d.0 {
-                                            incoming = 0;
-                                            d.0 = false; // 一度きり
-                                        }
-                                    }
-                                    p_hp.current = (p_hp.current - incoming).max(0);
-                                    log.0.push(format!(
-                                        "敵の行動: {} → {}ダメージ (プレイヤーHP {} / {})",
-                                        step.name, incoming, p_hp.current, p_hp.max
-                                    ));
-                                }
-                                ActionStepSpecificationEnum::Wait(_) => {
-                                    log.0.push(format!("敵の行動: {} (何もしない)", step.name));
-                                }
-                                ActionStepSpecificationEnum::Heal(spec) => {
-                                    // プレイヤーがこのターンに攻撃していた場合、敵の回復量は半減
-                                    let base_heal = spec.amount;
-                                    let heal_amount = if matches!(
-                                        cmd,
-                                        CommandKind::Attack | CommandKind::Skill
-                                    ) {
-                                        base_heal / 2
-                                    } else {
-                                        base_heal
-                                    };
-                                    let before = e_hp.current;
-                                    e_hp.current = (e_hp.current + heal_amount).min(e_hp.max);
-                                    let healed = e_hp.current - before;
-                                    log.0.push(format!(
-                                        "敵の行動: {} → HPを{}回復 (敵HP {} / {})",
-                                        step.name, healed, e_hp.current, e_hp.max
-                                    ));
-                                }
-                            }
-                            action.next();
-                        }
-                    }
-                    // 次ターンの敵行動を事前決定（敵が生きている場合）
-                    if e_hp.current > 0 && p_hp.current > 0 {
-                        if planned.0.is_finished() {
-                            // 現在の行動が完了している場合、新たに行動を決定
-
-                            let roll: f32 = rand::random::<f32>();
-                            // 敵HPが半分以下なら、回復とため開始を選択肢に含める
-                            let next = if e_hp.current * 2 <= e_hp.max {
-                                // 攻撃 / 待機 / 回復 / ため(準備)
-                                match () {
-                                    _ if roll < 0.1 => create_enemy_wait(),
-                                    _ if roll < 0.2 => create_enemy_heal(),
-                                    _ if roll < 0.3 => create_enemy_attack(),
-                                    _ if roll < 0.5 => create_enemy_claw_combo_strong(),
-                                    _ if roll < 0.7 => create_enemy_claw_strong(),
-                                    _ if roll < 0.8 => create_enemy_stomp(),
-                                    _ => create_enemy_fire_breath(),
-                                }
-                            } else {
-                                match () {
-                                    _ if roll < 0.3 => create_enemy_wait(),
-                                    _ if roll < 0.6 => create_enemy_attack(),
-                                    _ if roll < 0.8 => create_enemy_claw_combo(),
-                                    _ if roll < 0.9 => create_enemy_claw_strong(),
-                                    _ => create_enemy_stomp(),
-                                }
-                            };
-
-                            // TODO: 毎回生成してるのやめる
-                            planned.0 = ActionProcess::from(&Arc::new(next));
-                        }
-                        log.0.push(format!(
-                            "次ターン敵行動予定: {}",
-                            planned.0.current_step().unwrap().name
-                        ));
-                    }
-                    // ターン終了時、ブレイク残りターンのデクリメント（ブレイク中のみ）。解除時にブレイク値リセット。
-                    if e_bstate.remaining_turns > 0 {
-                        e_bstate.remaining_turns = e_bstate.remaining_turns.saturating_sub(1);
-                        if e_bstate.remaining_turns == 0 {
-                            e_break.current = 0;
-                            log.0.push(
-                                "敵のブレイク状態が解除。ブレイク値を0にリセット".to_string(),
-                            );
-                            // 0になったので自然回復量もリセット
-                            e_bregen.amount = 1;
-                        }
-                    }
-                    // ターン終了時、攻撃/強攻撃が無ければ自然回復: 1,2,4,...と倍増。0到達またはダメージ受けで1へリセット。
-                    if !matches!(cmd, CommandKind::Attack | CommandKind::Skill) {
-                        let before = e_break.current;
-                        e_break.current = (e_break.current - e_bregen.amount).max(0);
-                        if e_break.current != before {
-                            log.0.push(format!(
-                                "敵のブレイク値が自然回復: {} → {} (回復量 {})",
-                                before, e_break.current, e_bregen.amount
-                            ));
-                        }
-                        if e_break.current == 0 {
-                            e_bregen.amount = 1;
-                        } else {
-                            e_bregen.amount = (e_bregen.amount * 2).max(1);
-                        }
-                    }
-                    // ターン終了時、強化の残りターンをデクリメント
-                    let prev = (buffs.attack, buffs.skill, buffs.heal, buffs.defend);
-                    if buffs.attack > 0 {
-                        buffs.attack -= 1;
-                        if buffs.attack == 0 && prev.0 > 0 {
-                            log.0.push("攻撃の強化が解除された".to_string());
-                        }
-                    }
-                    if buffs.skill > 0 {
-                        buffs.skill -= 1;
-                        if buffs.skill == 0 && prev.1 > 0 {
-                            log.0.push("強攻撃の強化が解除された".to_string());
-                        }
-                    }
-                    if buffs.heal > 0 {
-                        buffs.heal -= 1;
-                        if buffs.heal == 0 && prev.2 > 0 {
-                            log.0.push("回復の強化が解除された".to_string());
-                        }
-                    }
-                    if buffs.defend > 0 {
-                        buffs.defend -= 1;
-                        if buffs.defend == 0 && prev.3 > 0 {
-                            log.0.push("防御の強化が解除された".to_string());
-                        }
-                    }
-                    turn.0 += 1;
-                    *phase = BattlePhase::AwaitCommand;
-                };
-                // 今回は1件だけ処理（各ターン1コマンドのルール）
-                resolve_command(commands_to_process[0]);
+        // 予約の編集（置換/挿入/入れ替え/削除）。編集したフレームは実行しない。
+        // 挿入の上限は確定時に固定した最大選択数（途中でオーバードライブが終わっても減らない）
+        let limit = batch.limit;
+        if edit_queue(controls, input, &mut queue, &mut batch, limit, &mut log) {
+            if queue.0.is_empty() {
+                // 全て削除したらバッチ終了
+                *phase = BattlePhase::AwaitCommand;
             }
             return;
         }
-        // 再選択（NまたはEsc）: 以降の予約コマンドをリセット
-        if keyboard.just_pressed(KeyCode::KeyN) || keyboard.just_pressed(KeyCode::Escape) {
+        // 実行確定
+        let confirm = input.just_pressed(controls, InputAction::Execute);
+        if (confirm || auto_fire) && waiting_dragon {
+            if confirm {
+                log_waiting_dragon(&mut log);
+            }
+            return;
+        }
+        if confirm || auto_fire {
+            if let Some(next) = queue.0.pop_front() {
+                // 実行前にバッチ内の位置で加算判定（2件目:+15, 3件目:+25, ...）
+                advance_batch(&mut batch, momentum, &mut log);
+                // この後の通常解決フローで処理する
+                commands_to_process.push(next);
+            }
+        } else if input.just_pressed(controls, InputAction::Reselect) {
+            // 再選択: 以降の予約コマンドをリセット
             let cleared = queue.0.len();
             queue.0.clear();
             pending.0.clear();
             batch.total = 0;
             batch.executed = 0;
+            batch.cursor = 0;
+            batch.edited = false;
+            if combo.1.on_queue_reset {
+                combo.0.reset();
+            }
             if cleared > 0 {
                 log.0.push(
                     "連続コマンドの予約をリセットしました。コマンドを選び直してください"
@@ -1482,141 +1848,74 @@ fn player_input_system(
             }
             *phase = BattlePhase::AwaitCommand;
             return;
+        } else {
+            // 入力待ち
+            return;
         }
-        // 入力待ち
-        return;
-    }
-
-    // フェーズが待機でない場合は何もしない
-    if *phase != BattlePhase::AwaitCommand {
+    } else if *phase != BattlePhase::AwaitCommand {
+        // フェーズが待機でない場合は何もしない
         return;
-    }
-
-    // 予約キューがあれば、先頭を実行するか確認フェーズに遷移
-    let mut commands_to_process: Vec<CommandKind> = Vec::new();
-    if let Some(_next) = queue.0.front() {
+    } else if queue.0.front().is_some() {
+        // 予約キューがあれば、先頭を実行するか確認フェーズに遷移
         // コマンド入力パネルで確認表示を行うため、ここではログ出力しない
         *phase = BattlePhase::ConfirmQueued;
         return;
     } else {
         // 未確定選択へ追加（このフレームで押されたキー）
         let mut added: Vec<&'static str> = Vec::new();
-        // 最大選択数制限（3件）
-        const MAX_SELECT: usize = 3;
-        let at_limit = pending.0.len() >= MAX_SELECT;
-
-        // 取り消し操作: Backspace=直前取り消し / Escape=全クリア（ログには出さない）
-        if keyboard.just_pressed(KeyCode::Escape) {
-            if !pending.0.is_empty() {
-                pending.0.clear();
-            }
-        }
-        if keyboard.just_pressed(KeyCode::Backspace) {
-            if let Some(removed) = pending.0.pop() {
-                let _ = removed; // ログは出さない
-            }
-        }
-        if keyboard.just_pressed(KeyCode::KeyA) {
-            if !at_limit {
-                pending.0.push(CommandKind::Attack);
-                added.push("攻撃");
-            } else {
-                log.0
-                    .push("これ以上選択を追加できません (最大3件)".to_string());
-            }
-        }
-        if keyboard.just_pressed(KeyCode::KeyS) {
-            if !at_limit {
-                pending.0.push(CommandKind::Skill);
-                added.push("強攻撃");
-            } else {
-                log.0
-                    .push("これ以上選択を追加できません (最大3件)".to_string());
-            }
-        }
-        if keyboard.just_pressed(KeyCode::KeyH) {
-            if !at_limit {
-                pending.0.push(CommandKind::Heal);
-                added.push("回復");
-            } else {
-                log.0
-                    .push("これ以上選択を追加できません (最大3件)".to_string());
-            }
+        // 最大選択数制限（プレイヤーの BatchLimit による）
+        let max_select = p_limit.current(momentum, overdrive);
+        let at_limit = pending.0.len() >= max_select;
+
+        // 取り消し操作: 直前取り消し / 全クリア（ログには出さない）
+        if input.just_pressed(controls, InputAction::Clear) {
+            pending.0.clear();
         }
-        if keyboard.just_pressed(KeyCode::KeyD) {
-            if !at_limit {
-                pending.0.push(CommandKind::Defend);
-                added.push("防御");
-            } else {
-                log.0
-                    .push("これ以上選択を追加できません (最大3件)".to_string());
-            }
+        if input.just_pressed(controls, InputAction::Undo) {
+            pending.0.pop();
         }
-        if keyboard.just_pressed(KeyCode::KeyW) {
-            if !at_limit {
-                pending.0.push(CommandKind::Wait);
-                added.push("待機");
-            } else {
-                log.0
-                    .push("これ以上選択を追加できません (最大3件)".to_string());
-            }
+        // 通常コマンド・強化・奥義（奥義はモメンタム100を消費）
+        // カーソル移動（上下は列の中、左右で列を移る）と、カーソルのコマンドを選ぶ
+        let (mut col, mut row) = (cursor.0 / COMMAND_MENU_ROWS, cursor.0 % COMMAND_MENU_ROWS);
+        if input.just_pressed(controls, InputAction::Up) {
+            row = (row + COMMAND_MENU_ROWS - 1) % COMMAND_MENU_ROWS;
         }
-        // 強化: Z=攻撃強化 X=強攻撃強化 C=回復強化 V=防御強化
-        if keyboard.just_pressed(KeyCode::KeyZ) {
-            if !at_limit {
-                pending.0.push(CommandKind::EnhanceAttack);
-                added.push("攻撃強化");
-            } else {
-                log.0
-                    .push("これ以上選択を追加できません (最大3件)".to_string());
-            }
+        if input.just_pressed(controls, InputAction::Down) {
+            row = (row + 1) % COMMAND_MENU_ROWS;
         }
-        if keyboard.just_pressed(KeyCode::KeyX) {
-            if !at_limit {
-                pending.0.push(CommandKind::EnhanceSkill);
-                added.push("強攻撃強化");
-            } else {
-                log.0
-                    .push("これ以上選択を追加できません (最大3件)".to_string());
-            }
+        if input.just_pressed(controls, InputAction::Left)
+            || input.just_pressed(controls, InputAction::Right)
+        {
+            col = 1 - col;
         }
-        if keyboard.just_pressed(KeyCode::KeyC) {
-            if !at_limit {
-                pending.0.push(CommandKind::EnhanceHeal);
-                added.push("回復強化");
-            } else {
-                log.0
-                    .push("これ以上選択を追加できません (最大3件)".to_string());
-            }
+        cursor.0 = col * COMMAND_MENU_ROWS + row;
+        let mut chosen = input.commands_just_pressed(controls);
+        if input.just_pressed(controls, InputAction::Choose) {
+            chosen.push(CommandKind::ALL[cursor.0]);
         }
-        if keyboard.just_pressed(KeyCode::KeyV) {
+        for cmd in chosen {
             if !at_limit {
-                pending.0.push(CommandKind::EnhanceDefend);
-                added.push("防御強化");
+                pending.0.push(cmd);
+                added.push(cmd.label());
             } else {
                 log.0
-                    .push("これ以上選択を追加できません (最大3件)".to_string());
+                    .push(format!("これ以上選択を追加できません (最大{max_select}件)"));
             }
         }
         // 選択追加のログは出さず、UI側表示に任せる
 
-        // Enterで確定: 先頭を実行、2つ目以降を予約キューへ
-        if keyboard.just_pressed(KeyCode::Enter) && !pending.0.is_empty() {
+        // 決定: 先頭を実行、2つ目以降を予約キューへ
+        let confirm = input.just_pressed(controls, InputAction::Decide);
+        if (confirm || auto_fire) && !pending.0.is_empty() && waiting_dragon {
+            if confirm {
+                log_waiting_dragon(&mut log);
+            }
+        } else if (confirm || auto_fire) && !pending.0.is_empty() {
             // 確定時、選択した全コマンドをログ出力
             let all_names = pending
                 .0
                 .iter()
-                .map(|c| match c {
-                    CommandKind::Attack => "攻撃",
-                    CommandKind::Skill => "強攻撃",
-                    CommandKind::Heal => "回復",
-                    CommandKind::Defend => "防御",
-                    CommandKind::Wait => "待機",
-                    CommandKind::EnhanceAttack => "攻撃強化",
-                    CommandKind::EnhanceSkill => "強攻撃強化",
-                    CommandKind::EnhanceHeal => "回復強化",
-                    CommandKind::EnhanceDefend => "防御強化",
-                })
+                .map(|c| c.label())
                 .collect::<Vec<_>>()
                 .join(", ");
             log.0.push(format!("選択確定: {}", all_names));
@@ -1626,9 +1925,8 @@ fn player_input_system(
             for &cmd in pending.0.iter().skip(1) {
                 queue.0.push_back(cmd);
             }
-            // 連続バッチ総数の記録と実行済み数のリセット
-            batch.total = pending.0.len();
-            batch.executed = 0;
+            // 連続バッチ総数の記録（オーバードライブ準備完了ならこのバッチで発動）
+            start_batch(&mut batch, overdrive, &mut log, pending.0.len(), max_select);
             // モメンタム増加は実行選択時に行うため、ここでは加算しない
             // ログ出力
             if pending.0.len() > 1 {
@@ -1636,17 +1934,7 @@ fn player_input_system(
                     .0
                     .iter()
                     .skip(1)
-                    .map(|c| match c {
-                        CommandKind::Attack => "攻撃",
-                        CommandKind::Skill => "強攻撃",
-                        CommandKind::Heal => "回復",
-                        CommandKind::Defend => "防御",
-                        CommandKind::Wait => "待機",
-                        CommandKind::EnhanceAttack => "攻撃強化",
-                        CommandKind::EnhanceSkill => "強攻撃強化",
-                        CommandKind::EnhanceHeal => "回復強化",
-                        CommandKind::EnhanceDefend => "防御強化",
-                    })
+                    .map(|c| c.label())
                     .collect::<Vec<_>>()
                     .join(", ");
                 log.0.push(format!(
@@ -1663,427 +1951,355 @@ fn player_input_system(
     if commands_to_process.is_empty() {
         return; // 入力も予約もなし
     }
+    let (defend, guard) = &mut def_guard;
+    let (combo, combo_rules, difficulty) = &mut combo;
+    let (enemy_damage_popup, finisher_cinematic) = &mut effects;
+    let (planned, rng, events, enemy_choice) = &mut enemy_plan;
+    let ctx = BattleCtx {
+        phase: &mut phase,
+        turn: &mut turn,
+        p_attack,
+        p_hp: &mut p_hp,
+        p_sta: &mut p_sta,
+        e_attack,
+        e_hp: &mut e_hp,
+        e_break: &mut e_break,
+        e_bstate: &mut e_bstate,
+        e_bregen: &mut e_bregen,
+        log: &mut log,
+        defend,
+        guard,
+        combo,
+        combo_rules,
+        planned,
+        momentum,
+        overdrive,
+        buffs: &mut buffs,
+        enemy_damage_popup,
+        finisher_cinematic,
+        rng,
+        events,
+        enemy_choice,
+        difficulty,
+    };
+    // 今回は1件だけ処理（各ターン1コマンドのルール）
+    resolve_command(ctx, commands_to_process[0]);
+    stats.record_turn(
+        &events.0,
+        momentum_before,
+        momentum.current,
+        e_bstate.remaining_turns > 0,
+    );
+}
 
-    // 共通のコマンド解決処理
-    let mut resolve_command = |cmd: CommandKind| {
-        *phase = BattlePhase::InBattle;
-        let guard_ready_at_start = {
-            let g = def_guard.p1();
-            g.0
-        };
-        let name = match cmd {
-            CommandKind::Attack => "攻撃",
-            CommandKind::Skill => "強攻撃",
-            CommandKind::Heal => "回復",
-            CommandKind::Defend => "防御",
-            CommandKind::Wait => "待機",
-            CommandKind::EnhanceAttack => "攻撃強化",
-            CommandKind::EnhanceSkill => "強攻撃強化",
-            CommandKind::EnhanceHeal => "回復強化",
-            CommandKind::EnhanceDefend => "防御強化",
-        };
-        log.0
-            .push(format!("ターン {} プレイヤーは{}を選択", turn.0, name));
-        // 連撃判定（直前が攻撃または強攻撃 かつ 今回が攻撃）
-        let is_chain = chain_state.last_was_attack && matches!(cmd, CommandKind::Attack);
-
-        // コストチェック（実行時にも確認）。不足なら行動失敗。
-        let cost = match cmd {
-            CommandKind::Attack => {
-                if is_chain {
-                    5
-                } else {
-                    15
-                }
-            }
-            CommandKind::Skill => 25,
-            CommandKind::Heal => {
-                if buffs.heal > 0 {
-                    20
-                } else {
-                    15
-                }
-            }
-            CommandKind::Defend => {
-                if buffs.defend > 0 {
-                    5
-                } else {
-                    10
-                }
-            }
-            CommandKind::Wait => 0,
-            CommandKind::EnhanceAttack
-            | CommandKind::EnhanceSkill
-            | CommandKind::EnhanceHeal
-            | CommandKind::EnhanceDefend => 0,
-        };
-        if p_sta.current < cost {
-            log.0.push("スタミナ不足で行動できませんでした".to_string());
-            // 実行失敗なので連撃を継続させない
-            chain_state.last_was_attack = false;
+fn log_waiting_dragon(log: &mut CombatLog) {
+    log.0
+        .push("ドラゴン側(2P)が次の行動を選ぶのを待っています (数字キー)".to_string());
+}
+
+// 2P対戦: ドラゴン側は数字キーで次の行動を予約する。選んだ内容は次ターンの予告になるまで伏せる
+fn dragon_input_system(
+    keyboard: Res<ButtonInput<KeyCode>>,
+    hotseat: Res<Hotseat>,
+    phase: Res<BattlePhase>,
+    enemy_q: Query<&Hp, With<Enemy>>,
+    mut choice: ResMut<EnemyMoveChoice>,
+    mut log: ResMut<CombatLog>,
+) {
+    if !hotseat.0 || *phase == BattlePhase::Finished {
+        return;
+    }
+    let Ok(e_hp) = enemy_q.single() else {
+        return;
+    };
+    // 選べる行動はAIと同じ候補（HP半分以下で回復・大技が解禁）
+    let table = enemy_action_table(e_hp);
+    for (&key, &(_, create)) in DRAGON_KEYS.iter().zip(table) {
+        if keyboard.just_pressed(key) {
+            log.0.push(if choice.0.is_some() {
+                "ドラゴン側が次の行動を選び直した".to_string()
+            } else {
+                "ドラゴン側が次の行動を選択した".to_string()
+            });
+            choice.0 = Some(create());
+        }
+    }
+}
+
+// 確認フェーズでの予約編集（InputMap のキーを QueueEdit にして apply_queue_edit へ）。何か編集したら true
+// ↑↓=カーソル移動 / Shift+↑↓=入れ替え / コマンドキー=置換 / Shift+コマンドキー=挿入 / Backspace,Delete=削除
+fn edit_queue(
+    controls: Controls,
+    input: &InputMap,
+    queue: &mut CommandQueue,
+    batch: &mut ConsecutiveBatch,
+    limit: usize,
+    log: &mut CombatLog,
+) -> bool {
+    if queue.0.is_empty() {
+        return false;
+    }
+    let shift = controls
+        .keyboard
+        .any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]);
+    let cursor = batch.cursor.min(queue.0.len() - 1);
+    let edit = if input.just_pressed(controls, InputAction::Up) && cursor > 0 {
+        if shift {
+            QueueEdit::SwapUp
+        } else {
+            QueueEdit::CursorUp
+        }
+    } else if input.just_pressed(controls, InputAction::Down) && cursor + 1 < queue.0.len() {
+        if shift {
+            QueueEdit::SwapDown
         } else {
-            p_sta.current -= cost;
+            QueueEdit::CursorDown
+        }
+    } else if input.just_pressed(controls, InputAction::Remove) {
+        QueueEdit::Remove
+    } else if let Some(&cmd) = input.commands_just_pressed(controls).first() {
+        if shift {
+            QueueEdit::Insert(cmd)
+        } else {
+            QueueEdit::Replace(cmd)
+        }
+    } else {
+        return false;
+    };
+    apply_queue_edit(&mut queue.0, batch, limit, log, edit);
+    true
+}
 
-            match cmd {
-                CommandKind::EnhanceAttack => {
-                    if buffs.attack > 0 {
-                        log.0
-                            .push("攻撃は既に強化中のため強化できません".to_string());
-                    } else if momentum.current < 50 {
-                        log.0
-                            .push("モメンタム不足で強化できませんでした (必要50)".to_string());
-                    } else {
-                        momentum.current -= 50;
-                        buffs.attack = 11;
-                        log.0
-                            .push("攻撃を強化した (11ターン持続, モメンタム-50)".to_string());
-                    }
-                }
-                CommandKind::EnhanceSkill => {
-                    if buffs.skill > 0 {
-                        log.0
-                            .push("強攻撃は既に強化中のため強化できません".to_string());
-                    } else if momentum.current < 50 {
-                        log.0
-                            .push("モメンタム不足で強化できませんでした (必要50)".to_string());
-                    } else {
-                        momentum.current -= 50;
-                        buffs.skill = 11;
-                        log.0
-                            .push("強攻撃を強化した (11ターン持続, モメンタム-50)".to_string());
-                    }
-                }
-                CommandKind::EnhanceHeal => {
-                    if buffs.heal > 0 {
-                        log.0
-                            .push("回復は既に強化中のため強化できません".to_string());
-                    } else if momentum.current < 50 {
-                        log.0
-                            .push("モメンタム不足で強化できませんでした (必要50)".to_string());
-                    } else {
-                        momentum.current -= 50;
-                        buffs.heal = 11;
-                        log.0
-                            .push("回復を強化した (11ターン持続, モメンタム-50)".to_string());
-                    }
-                }
-                CommandKind::EnhanceDefend => {
-                    if buffs.defend > 0 {
-                        log.0
-                            .push("防御は既に強化中のため強化できません".to_string());
-                    } else if momentum.current < 50 {
-                        log.0
-                            .push("モメンタム不足で強化できませんでした (必要50)".to_string());
-                    } else {
-                        momentum.current -= 50;
-                        buffs.defend = 11;
-                        log.0
-                            .push("防御を強化した (11ターン持続, モメンタム-50)".to_string());
-                    }
-                }
-                CommandKind::Heal => {
-                    let amount = if buffs.heal > 0 { 60 } else { 50 };
-                    let before = p_hp.current;
-                    p_hp.current = (p_hp.current + amount).min(p_hp.max);
-                    let healed = p_hp.current - before;
-                    log.0.push(format!(
-                        "プレイヤーは{}回復 (HP {} / {})",
-                        healed, p_hp.current, p_hp.max
-                    ));
-                }
-                CommandKind::Defend => {
-                    {
-                        let mut d = def_guard.p0();
-                        d.0 = true;
-                    }
-                    {
-                        let mut g = def_guard.p1();
-                        g.0 = true; // 次プレイヤー行動のガードカウンター猶予
-                    }
-                    log.0
-                        .push("プレイヤーは防御態勢に入った (次の敵攻撃は無効)".to_string());
-                    log.0
-                        .push("ガードカウンターの構え! 次の行動で強攻撃が強化".to_string());
-                }
-                CommandKind::Attack => {
-                    let base = if buffs.attack > 0 { 25 } else { p_attack.0 };
-                    let mut dmg = base;
-                    let mut break_bonus = 0;
-                    if e_bstate.remaining_turns > 0 {
-                        break_bonus = 30 + base * 2;
-                        dmg = base + break_bonus;
-                    }
-                    e_hp.current = (e_hp.current - dmg).max(0);
-                    // 敵ダメージポップアップ設定
-                    enemy_damage_popup.amount = dmg;
-                    enemy_damage_popup.timer = 1.2;
-                    if is_chain {
-                        if break_bonus > 0 {
-                            log.0.push(format!(
-                                "連撃! 敵に{}ダメージ (基本{} + ブレイク補正{} = 合計{}, 敵HP {} / {})",
-                                dmg, base, break_bonus, dmg, e_hp.current, e_hp.max
-                            ));
-                        } else {
-                            log.0.push(format!(
-                                "連撃! 敵に{}ダメージ (消費スタミナ半減, 敵HP {} / {})",
-                                dmg, e_hp.current, e_hp.max
-                            ));
-                        }
-                    } else {
-                        if break_bonus > 0 {
-                            log.0.push(format!(
-                                "敵に{}ダメージ (基本{} + ブレイク補正{} = 合計{}, 敵HP {} / {})",
-                                dmg, base, break_bonus, dmg, e_hp.current, e_hp.max
-                            ));
-                        } else {
-                            log.0.push(format!(
-                                "敵に{}ダメージ (敵HP {} / {})",
-                                dmg, e_hp.current, e_hp.max
-                            ));
-                        }
-                    }
-                    // ブレイク値加算（攻撃時の固定増加量: 通常15・強化時25）
-                    let before_break = e_break.current;
-                    let add_break = if buffs.attack > 0 { 25 } else { 10 };
-                    e_break.current += add_break;
-                    log.0.push(format!(
-                        "ブレイク値 +{} ({} → {} / 100)",
-                        add_break, before_break, e_break.current
-                    ));
-                    // ダメージを受けたので自然回復量をリセット
-                    e_bregen.amount = 1;
-                }
-                CommandKind::Skill => {
-                    let mut base = if buffs.skill > 0 { 45 } else { 25 };
-                    let is_guard_counter = guard_ready_at_start;
-                    if is_guard_counter {
-                        base += 5; // ガードカウンター: 威力+5
-                    }
-                    let mut dmg = base;
-                    let mut break_bonus = 0;
-                    if e_bstate.remaining_turns > 0 {
-                        break_bonus = 30 + base * 2;
-                        dmg = base + break_bonus;
-                    }
-                    e_hp.current = (e_hp.current - dmg).max(0);
-                    // 敵ダメージポップアップ設定
-                    enemy_damage_popup.amount = dmg;
-                    enemy_damage_popup.timer = 1.2;
-                    if is_guard_counter {
-                        if break_bonus > 0 {
-                            log.0.push(format!(
-                                "ガードカウンター! 敵に{}ダメージ (基本{} + ブレイク補正{} = 合計{}, 敵HP {} / {})",
-                                dmg, base, break_bonus, dmg, e_hp.current, e_hp.max
-                            ));
-                        } else {
-                            log.0.push(format!(
-                                "ガードカウンター! 敵に{}ダメージ (敵HP {} / {})",
-                                dmg, e_hp.current, e_hp.max
-                            ));
-                        }
-                    } else {
-                        if break_bonus > 0 {
-                            log.0.push(format!(
-                                "敵に{}ダメージ (基本{} + ブレイク補正{} = 合計{}, 敵HP {} / {})",
-                                dmg, base, break_bonus, dmg, e_hp.current, e_hp.max
-                            ));
-                        } else {
-                            log.0.push(format!(
-                                "敵に{}ダメージ (敵HP {} / {})",
-                                dmg, e_hp.current, e_hp.max
-                            ));
-                        }
-                    }
-                    let before_break = e_break.current;
-                    let mut add_break = if buffs.skill > 0 { 40 } else { 25 };
-                    if is_guard_counter {
-                        add_break += 20; // ガードカウンター: ブレイク+20
-                    }
-                    e_break.current += add_break;
-                    log.0.push(format!(
-                        "ブレイク値 +{} ({} → {} / 100)",
-                        add_break, before_break, e_break.current
-                    ));
-                    e_bregen.amount = 1;
-                }
-                CommandKind::Wait => {
-                    let before = p_sta.current;
-                    p_sta.current = (p_sta.current + 60).min(p_sta.max);
-                    let recovered = p_sta.current - before;
-                    log.0.push(format!(
-                        "プレイヤーは待機してスタミナを{}回復 (Stamina {} / {})",
-                        recovered, p_sta.current, p_sta.max
-                    ));
-                }
-            }
-            // 実行成功: 直前が攻撃または強攻撃だったかを更新（強攻撃後の攻撃も連撃にする）
-            chain_state.last_was_attack = matches!(cmd, CommandKind::Attack | CommandKind::Skill);
-            // ガードカウンター猶予の消費: 防御以外の行動で消費
-            if !matches!(cmd, CommandKind::Defend) {
-                let mut g = def_guard.p1();
-                g.0 = false;
-            }
-        }
+// ================== Headless ==================
+// --headless: 1行1バッチのJSON（["attack","skill"] または {"batch":[...]}）を標準入力から読み、
+// 解決中の BattleEvent と各バッチ後の状態を1行ずつJSONで標準出力へ書く。確認フェーズは挟まない
+//   {"type":"event","turn":3,"event":{"kind":"damage_dealt","source":"skill","amount":25}}
+//   {"type":"state","observation":{...},"log":[...],"finished":false,"won":false}
+//   {"type":"error","message":"..."}
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum HeadlessRequest {
+    Batch(Vec<CommandRef>),
+    Object { batch: Vec<CommandRef> },
+}
 
-        // プレイヤーの攻撃/強攻撃後にブレイク判定。閾値到達でこのターンの敵行動をキャンセルし、次ターンから4ターンブレイク。
-        let mut enemy_action_canceled_this_turn = false;
-        if e_break.current >= 100 && e_bstate.remaining_turns == 0 {
-            enemy_action_canceled_this_turn = true;
-            e_bstate.remaining_turns = 4; // 次ターンから4ターン行動不能
-            log.0.push(
-                "敵がブレイク状態に入る!（次のターンから4ターン行動不能・被ダメ2倍）".to_string(),
-            );
-        }
-
-        if e_hp.current > 0 {
-            // 事前決定済みの敵行動を実行
-            if e_bstate.remaining_turns > 0 {
-                // ブレイク中は行動不能
-                log.0.push("敵はブレイク中のため行動不能".to_string());
-            } else if enemy_action_canceled_this_turn {
-                // このターンの行動はキャンセル
-                log.0.push("敵の行動はブレイクによりキャンセル".to_string());
-            } else {
-                let action = &mut planned.0;
-                let step = action.current_step().unwrap();
-                match step.specification {
-                    ActionStepSpecificationEnum::Attack(spec) => {
-                        let mut incoming = (e_attack.0 as f32 * spec.power) as i32;
-                        {
-                            let mut d = def_guard.p0();
-                            if d.0 {
-                                incoming = 0;
-                                d.0 = false; // 一度きり
-                            }
-                        }
-                        p_hp.current = (p_hp.current - incoming).max(0);
-                        log.0.push(format!(
-                            "敵の行動: {} → {}ダメージ (プレイヤーHP {} / {})",
-                            step.name, incoming, p_hp.current, p_hp.max
-                        ));
-                    }
-                    ActionStepSpecificationEnum::Wait(_) => {
-                        log.0.push(format!("敵の行動: {} (何もしない)", step.name));
-                    }
-                    ActionStepSpecificationEnum::Heal(spec) => {
-                        // プレイヤーがこのターンに攻撃していた場合、敵の回復量は半減
-                        let base_heal = spec.amount;
-                        let heal_amount = if matches!(cmd, CommandKind::Attack | CommandKind::Skill)
-                        {
-                            base_heal / 2
-                        } else {
-                            base_heal
-                        };
-                        let before = e_hp.current;
-                        e_hp.current = (e_hp.current + heal_amount).min(e_hp.max);
-                        let healed = e_hp.current - before;
-                        log.0.push(format!(
-                            "敵の行動: {} → HPを{}回復 (敵HP {} / {})",
-                            step.name, healed, e_hp.current, e_hp.max
-                        ));
-                    }
-                }
-                action.next();
-            }
-        }
-        // 次ターンの敵行動を事前決定（敵が生きている場合）
-        if e_hp.current > 0 && p_hp.current > 0 {
-            if planned.0.is_finished() {
-                // 現在の行動が完了している場合、新たに行動を決定
-
-                let roll: f32 = rand::random::<f32>();
-                // 敵HPが半分以下なら、回復とため開始を選択肢に含める
-                let next = if e_hp.current * 2 <= e_hp.max {
-                    // 攻撃 / 待機 / 回復 / ため(準備)
-                    match () {
-                        _ if roll < 0.1 => create_enemy_wait(),
-                        _ if roll < 0.2 => create_enemy_heal(),
-                        _ if roll < 0.3 => create_enemy_attack(),
-                        _ if roll < 0.5 => create_enemy_claw_combo_strong(),
-                        _ if roll < 0.7 => create_enemy_claw_strong(),
-                        _ if roll < 0.8 => create_enemy_stomp(),
-                        _ => create_enemy_fire_breath(),
-                    }
-                } else {
-                    match () {
-                        _ if roll < 0.3 => create_enemy_wait(),
-                        _ if roll < 0.6 => create_enemy_attack(),
-                        _ if roll < 0.8 => create_enemy_claw_combo(),
-                        _ if roll < 0.9 => create_enemy_claw_strong(),
-                        _ => create_enemy_stomp(),
-                    }
-                };
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum HeadlessOutput<'a> {
+    Event {
+        turn: u32,
+        event: &'a BattleEvent,
+    },
+    State {
+        difficulty: Difficulty,
+        observation: Observation,
+        log: &'a [String],
+        finished: bool,
+        won: bool,
+    },
+    Error {
+        message: String,
+    },
+}
 
-                // TODO: 毎回生成してるのやめる
-                planned.0 = ActionProcess::from(&Arc::new(next));
-            }
-            log.0.push(format!(
-                "次ターン敵行動予定: {}",
-                planned.0.current_step().unwrap().name
-            ));
-        }
-        // ターン終了時、ブレイク残りターンのデクリメント（ブレイク中のみ）。解除時にブレイク値リセット。
-        if e_bstate.remaining_turns > 0 {
-            e_bstate.remaining_turns = e_bstate.remaining_turns.saturating_sub(1);
-            if e_bstate.remaining_turns == 0 {
-                e_break.current = 0;
-                log.0
-                    .push("敵のブレイク状態が解除。ブレイク値を0にリセット".to_string());
-                // 0になったので自然回復量もリセット
-                e_bregen.amount = 1;
+fn headless_print(out: &HeadlessOutput) {
+    if let Ok(line) = serde_json::to_string(out) {
+        println!("{line}");
+    }
+}
+
+// 標準入力の行（別スレッドで読む。システムの中で読むとスケジュールが止まる）
+#[derive(Resource)]
+struct HeadlessInput(Mutex<Receiver<String>>);
+
+fn spawn_stdin_reader() -> HeadlessInput {
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            if tx.send(line).is_err() {
+                break;
             }
         }
-        // ターン終了時、攻撃/強攻撃が無ければ自然回復: 1,2,4,...と倍増。0到達またはダメージ受けで1へリセット。
-        if !matches!(cmd, CommandKind::Attack | CommandKind::Skill) {
-            let before = e_break.current;
-            e_break.current = (e_break.current - e_bregen.amount).max(0);
-            if e_break.current != before {
-                log.0.push(format!(
-                    "敵のブレイク値が自然回復: {} → {} (回復量 {})",
-                    before, e_break.current, e_bregen.amount
-                ));
-            }
-            if e_break.current == 0 {
-                e_bregen.amount = 1;
-            } else {
-                e_bregen.amount = (e_bregen.amount * 2).max(1);
-            }
+        // ここで tx が破棄され、受信側は入力の終わりを知る
+    });
+    HeadlessInput(Mutex::new(rx))
+}
+
+fn headless_system(
+    mut started: Local<bool>,
+    input: Res<HeadlessInput>,
+    mut exit: MessageWriter<AppExit>,
+    mut phase: ResMut<BattlePhase>,
+    mut turn: ResMut<Turn>,
+    mut player_q: Query<
+        (&Attack, &mut Hp, &mut Stamina, &BatchLimit),
+        (With<Player>, Without<Enemy>),
+    >,
+    mut enemy_q: Query<
+        (
+            &Attack,
+            &mut Hp,
+            &mut BreakValue,
+            &mut BreakState,
+            &mut BreakRegen,
+        ),
+        (With<Enemy>, Without<Player>),
+    >,
+    mut log: ResMut<CombatLog>,
+    mut def_guard: (ResMut<DefendNextAttack>, ResMut<GuardCounterReady>),
+    mut combo: (
+        ResMut<PlayerComboState>,
+        Res<ComboResetRules>,
+        Res<Difficulty>,
+    ),
+    mut enemy_plan: (
+        ResMut<EnemyPlannedAction>,
+        ResMut<BattleRng>,
+        ResMut<TurnEvents>,
+        ResMut<EnemyMoveChoice>,
+    ),
+    mut gauges: (ResMut<Momentum>, ResMut<Overdrive>),
+    mut buffs: ResMut<CommandBuffs>,
+    mut batch: ResMut<ConsecutiveBatch>,
+    mut effects: (ResMut<EnemyDamagePopup>, ResMut<FinisherCinematic>),
+) {
+    let Ok((p_attack, mut p_hp, mut p_sta, p_limit)) = player_q.single_mut() else {
+        return;
+    };
+    let Ok((e_attack, mut e_hp, mut e_break, mut e_bstate, mut e_bregen)) = enemy_q.single_mut()
+    else {
+        return;
+    };
+    let (defend, guard) = &mut def_guard;
+    let (combo, combo_rules, difficulty) = &mut combo;
+    let (planned, rng, events, enemy_choice) = &mut enemy_plan;
+    let (momentum, overdrive) = &mut gauges;
+    let (enemy_damage_popup, finisher_cinematic) = &mut effects;
+    let mut ctx = BattleCtx {
+        phase: &mut phase,
+        turn: &mut turn,
+        p_attack,
+        p_hp: &mut p_hp,
+        p_sta: &mut p_sta,
+        e_attack,
+        e_hp: &mut e_hp,
+        e_break: &mut e_break,
+        e_bstate: &mut e_bstate,
+        e_bregen: &mut e_bregen,
+        log: &mut log,
+        defend,
+        guard,
+        combo,
+        combo_rules,
+        planned,
+        momentum,
+        overdrive,
+        buffs: &mut buffs,
+        enemy_damage_popup,
+        finisher_cinematic,
+        rng,
+        events,
+        enemy_choice,
+        difficulty,
+    };
+    // 最初のフレームは初期状態だけ出す
+    if !*started {
+        *started = true;
+        let max_select = p_limit.current(ctx.momentum, ctx.overdrive);
+        headless_print(&HeadlessOutput::State {
+            difficulty: *ctx.difficulty,
+            observation: Observation::from_ctx(&ctx, max_select),
+            log: &ctx.log.0,
+            finished: false,
+            won: false,
+        });
+        return;
+    }
+    let Ok(lines) = input.0.lock() else {
+        return;
+    };
+    // 届いている行をすべて処理する（まだ無ければ次のフレームへ）
+    loop {
+        if *ctx.phase == BattlePhase::Finished {
+            exit.write(AppExit::Success);
+            return;
         }
-        // ターン終了時、強化の残りターンをデクリメント
-        let prev = (buffs.attack, buffs.skill, buffs.heal, buffs.defend);
-        if buffs.attack > 0 {
-            buffs.attack -= 1;
-            if buffs.attack == 0 && prev.0 > 0 {
-                log.0.push("攻撃の強化が解除された".to_string());
+        let line = match lines.try_recv() {
+            Ok(line) => line,
+            Err(TryRecvError::Empty) => return,
+            Err(TryRecvError::Disconnected) => {
+                exit.write(AppExit::Success);
+                return;
             }
+        };
+        if line.trim().is_empty() {
+            continue;
         }
-        if buffs.skill > 0 {
-            buffs.skill -= 1;
-            if buffs.skill == 0 && prev.1 > 0 {
-                log.0.push("強攻撃の強化が解除された".to_string());
+        let parsed = serde_json::from_str::<HeadlessRequest>(&line)
+            .map_err(|e| e.to_string())
+            .and_then(|req| match req {
+                HeadlessRequest::Batch(items) | HeadlessRequest::Object { batch: items } => {
+                    parse_batch(&items)
+                }
+            });
+        let mut commands = match parsed {
+            Ok(c) if !c.is_empty() => c,
+            Ok(_) => {
+                headless_print(&HeadlessOutput::Error {
+                    message: "空のバッチです".to_string(),
+                });
+                continue;
             }
-        }
-        if buffs.heal > 0 {
-            buffs.heal -= 1;
-            if buffs.heal == 0 && prev.2 > 0 {
-                log.0.push("回復の強化が解除された".to_string());
+            Err(message) => {
+                headless_print(&HeadlessOutput::Error { message });
+                continue;
             }
+        };
+
+        let log_start = ctx.log.0.len();
+        let max_select = p_limit.current(ctx.momentum, ctx.overdrive);
+        if commands.len() > max_select {
+            commands.truncate(max_select);
+            ctx.log
+                .0
+                .push(format!("最大{max_select}件を超えた分は破棄しました"));
         }
-        if buffs.defend > 0 {
-            buffs.defend -= 1;
-            if buffs.defend == 0 && prev.3 > 0 {
-                log.0.push("防御の強化が解除された".to_string());
-            }
+        execute_batch(
+            ctx.reborrow(),
+            &mut batch,
+            &commands,
+            max_select,
+            |ctx, turn, _| {
+                for event in &ctx.events.0 {
+                    headless_print(&HeadlessOutput::Event { turn, event });
+                }
+            },
+        );
+        let finished = *ctx.phase == BattlePhase::Finished;
+        if finished {
+            ctx.log.0.push(if ctx.e_hp.current <= 0 {
+                "勝利! 敵を倒しました".to_string()
+            } else {
+                "敗北... プレイヤーのHPが0です".to_string()
+            });
         }
-        turn.0 += 1;
-        *phase = BattlePhase::AwaitCommand;
-    };
-
-    // 今回は1件だけ処理（各ターン1コマンドのルール）
-    resolve_command(commands_to_process[0]);
+        let max_select = p_limit.current(ctx.momentum, ctx.overdrive);
+        headless_print(&HeadlessOutput::State {
+            difficulty: *ctx.difficulty,
+            observation: Observation::from_ctx(&ctx, max_select),
+            log: &ctx.log.0[log_start..],
+            finished,
+            won: finished && ctx.e_hp.current <= 0,
+        });
+    }
 }
 
 // ================== End Check ==================
+// 決着したら Finished にして勝敗バナーを出す。
+// 敵UIは ui_update_enemy_system が敵HPから決めるので、巻き戻しで戻すのはバナーだけ
 fn battle_end_check_system(
     mut phase: ResMut<BattlePhase>,
     player_q: Query<&Hp, With<Player>>,
@@ -2091,15 +2307,7 @@ fn battle_end_check_system(
     mut log: ResMut<CombatLog>,
     mut commands: Commands,
     asset_server: Res<AssetServer>,
-    mut vis_params: ParamSet<(
-        Query<&mut Visibility, With<UiEnemy>>,
-        Query<&mut Visibility, With<UiEnemyBreakLabel>>,
-    )>,
-    mut ui_enemy_next_text_q: Query<&mut Text, With<UiEnemyNextActionText>>,
-    mut gauge_params_end: ParamSet<(
-        Query<&mut Node, With<UiEnemyHpGaugeFill>>,
-        Query<&mut Node, With<UiEnemyBreakGaugeFill>>,
-    )>,
+    banner_q: Query<(), With<BossSlainBanner>>,
 ) {
     if *phase == BattlePhase::Finished {
         return;
@@ -2110,92 +2318,143 @@ fn battle_end_check_system(
     let Ok(e_hp) = enemy_q.single() else {
         return;
     };
-    if e_hp.current <= 0 {
+    // 少し遅らせてからバナー表示（敵消失後に表示）。奥義演出中ならその後に出す
+    let delay = if banner_q.is_empty() { 0.3 } else { 2.3 };
+    let font = asset_server.load("fonts/x12y16pxMaruMonica.ttf");
+    let banner = if e_hp.current <= 0 {
         *phase = BattlePhase::Finished;
         log.0.push("勝利! 敵を倒しました".to_string());
-
-        // 敵UIを即時非表示（HP表示などは一瞬で消す）
-        if let Ok(mut vis) = vis_params.p0().single_mut() {
-            *vis = Visibility::Hidden;
-        }
-        if let Ok(mut br_vis) = vis_params.p1().single_mut() {
-            *br_vis = Visibility::Hidden;
-        }
-        if let Ok(mut next_text) = ui_enemy_next_text_q.single_mut() {
-            next_text.0 = String::new();
-        }
-        if let Ok(mut hp_node) = gauge_params_end.p0().single_mut() {
-            hp_node.width = percent(0);
-        }
-        if let Ok(mut br_node) = gauge_params_end.p1().single_mut() {
-            br_node.width = percent(0);
-        }
-        // 少し遅らせてからバナー表示（敵消失後に表示）
-        let font = asset_server.load("fonts/x12y16pxMaruMonica.ttf");
-        commands
-            .spawn((
-                BossSlainBanner {
-                    elapsed: -0.3, // 0.3秒遅延してからフェードイン開始
-                    phase: BannerPhase::FadeIn,
+        spawn_cinematic_banner(
+            &mut commands,
+            BossSlainBanner {
+                elapsed: -delay, // 遅延してからフェードイン開始
+                phase: BannerPhase::FadeIn,
+                hold: 3.0,
+                text_color: LinearRgba {
+                    red: 0.83,
+                    green: 0.72,
+                    blue: 0.20,
+                    alpha: 1.0,
                 },
-                Node {
-                    width: percent(100),
-                    height: percent(100),
-                    justify_content: JustifyContent::Center,
-                    align_items: AlignItems::Center,
-                    position_type: PositionType::Absolute,
-                    ..default()
+            },
+            "DRAGON SLAIN",
+            font,
+            96.0,
+        )
+    } else if p_hp.current <= 0 {
+        *phase = BattlePhase::Finished;
+        log.0.push("敗北... プレイヤーのHPが0です".to_string());
+        // 敗北バナー（撃破と同じ演出を暗い赤で）。消えたら結果画面から再戦できる
+        spawn_cinematic_banner(
+            &mut commands,
+            BossSlainBanner {
+                elapsed: -delay,
+                phase: BannerPhase::FadeIn,
+                hold: 2.0,
+                text_color: LinearRgba {
+                    red: 0.60,
+                    green: 0.08,
+                    blue: 0.08,
+                    alpha: 1.0,
                 },
-                ZIndex(100),
-            ))
-            .with_children(|builder| {
-                // 背景の黒帯（左右いっぱい、上下グラデ）
-                builder
-                    .spawn((
-                        BossSlainBackdrop,
+            },
+            "DEFEATED",
+            font,
+            96.0,
+        )
+    } else {
+        return;
+    };
+    commands.entity(banner).insert(BattleEndBanner);
+}
+
+// 演出用バナー（中央の黒帯＋大きな文字）を生成。フェードは boss_slain_banner_system が担当
+fn spawn_cinematic_banner(
+    commands: &mut Commands,
+    banner: BossSlainBanner,
+    text: &str,
+    font: Handle<Font>,
+    font_size: f32,
+) -> Entity {
+    let text_color = banner.text_color;
+    commands
+        .spawn((
+            DespawnOnExit(BattleSession::Open),
+            banner,
+            Node {
+                width: percent(100),
+                height: percent(100),
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                position_type: PositionType::Absolute,
+                ..default()
+            },
+            ZIndex(100),
+        ))
+        .with_children(|builder| {
+            // 背景の黒帯（左右いっぱい、上下グラデ）
+            builder
+                .spawn((
+                    BossSlainBackdrop,
+                    Node {
+                        width: percent(100),
+                        height: Val::Auto,
+                        position_type: PositionType::Absolute,
+                        left: Val::Px(0.0),
+                        right: Val::Px(0.0),
+                        // 画面全高に広げ、中央帯＋上下グラデを内包
+                        top: Val::Px(0.0),
+                        bottom: Val::Px(0.0),
+                        justify_content: JustifyContent::Center,
+                        align_items: AlignItems::Center,
+                        flex_direction: FlexDirection::Column,
+                        row_gap: Val::Px(0.0),
+                        ..default()
+                    },
+                    ZIndex(100),
+                ))
+                .with_children(|back| {
+                    // 上グラデーション（薄→濃へ）
+                    for _ in 0..6 {
+                        back.spawn((
+                            BossSlainBackdropRow,
+                            Node {
+                                width: percent(100),
+                                height: Val::Px(12.0),
+                                ..default()
+                            },
+                            BackgroundColor(Color::from(LinearRgba {
+                                red: 0.0,
+                                green: 0.0,
+                                blue: 0.0,
+                                alpha: 0.0, // フェーズで乗算する
+                            })),
+                        ));
+                    }
+
+                    // 中央帯（不透明に近い）
+                    back.spawn((
+                        BossSlainBackdropCenter,
                         Node {
                             width: percent(100),
-                            height: Val::Auto,
-                            position_type: PositionType::Absolute,
-                            left: Val::Px(0.0),
-                            right: Val::Px(0.0),
-                            // 画面全高に広げ、中央帯＋上下グラデを内包
-                            top: Val::Px(0.0),
-                            bottom: Val::Px(0.0),
-                            justify_content: JustifyContent::Center,
-                            align_items: AlignItems::Center,
-                            flex_direction: FlexDirection::Column,
-                            row_gap: Val::Px(0.0),
+                            height: Val::Px(140.0),
                             ..default()
                         },
-                        ZIndex(100),
-                    ))
-                    .with_children(|back| {
-                        // 上グラデーション（薄→濃へ）
-                        for i in (0..6u8).rev() {
-                            let alpha = (i as f32) * 0.08; // 0.0, 0.08, ...
-                            back.spawn((
-                                BossSlainBackdropRow(i),
-                                Node {
-                                    width: percent(100),
-                                    height: Val::Px(12.0),
-                                    ..default()
-                                },
-                                BackgroundColor(Color::from(LinearRgba {
-                                    red: 0.0,
-                                    green: 0.0,
-                                    blue: 0.0,
-                                    alpha: 0.0, // フェーズで乗算する
-                                })),
-                            ));
-                        }
+                        BackgroundColor(Color::from(LinearRgba {
+                            red: 0.0,
+                            green: 0.0,
+                            blue: 0.0,
+                            alpha: 0.0, // フェーズで乗算する
+                        })),
+                    ));
 
-                        // 中央帯（不透明に近い）
+                    // 下グラデーション（濃→薄へ）
+                    for _ in 0..6 {
                         back.spawn((
-                            BossSlainBackdropCenter,
+                            BossSlainBackdropRow,
                             Node {
                                 width: percent(100),
-                                height: Val::Px(140.0),
+                                height: Val::Px(12.0),
                                 ..default()
                             },
                             BackgroundColor(Color::from(LinearRgba {
@@ -2205,48 +2464,22 @@ fn battle_end_check_system(
                                 alpha: 0.0, // フェーズで乗算する
                             })),
                         ));
+                    }
+                });
 
-                        // 下グラデーション（濃→薄へ）
-                        for i in 0..6u8 {
-                            let alpha = (i as f32) * 0.08; // 0.0, 0.08, ...
-                            back.spawn((
-                                BossSlainBackdropRow(10 + i),
-                                Node {
-                                    width: percent(100),
-                                    height: Val::Px(12.0),
-                                    ..default()
-                                },
-                                BackgroundColor(Color::from(LinearRgba {
-                                    red: 0.0,
-                                    green: 0.0,
-                                    blue: 0.0,
-                                    alpha: 0.0, // フェーズで乗算する
-                                })),
-                            ));
-                        }
-                    });
-
-                builder.spawn((
-                    BossSlainText,
-                    Text::new("DRAGON SLAIN"),
-                    TextFont {
-                        font: font.clone(),
-                        font_size: 96.0,
-                        ..default()
-                    },
-                    TextColor(Color::from(LinearRgba {
-                        red: 0.83,
-                        green: 0.72,
-                        blue: 0.20,
-                        alpha: 0.0,
-                    })),
-                    ZIndex(101),
-                ));
-            });
-    } else if p_hp.current <= 0 {
-        *phase = BattlePhase::Finished;
-        log.0.push("敗北... プレイヤーのHPが0です".to_string());
-    }
+            builder.spawn((
+                BossSlainText,
+                Text::new(text),
+                TextFont {
+                    font,
+                    font_size,
+                    ..default()
+                },
+                TextColor(Color::from(text_color.with_alpha(0.0))),
+                ZIndex(101),
+            ));
+        })
+        .id()
 }
 
 fn ui_update_system(
@@ -2259,8 +2492,7 @@ fn ui_update_system(
     buffs: Res<CommandBuffs>,
     pending: Res<PendingSelections>,
     queue: Res<CommandQueue>,
-    // mut ui_q: Query<&mut Children, With<UiRoot>>,
-    planned: Res<EnemyPlannedAction>,
+    difficulty: Res<Difficulty>,
     mut ui_staus_q: Query<&mut Text, (With<UiStatus>, Without<UiPhase>, Without<UiLog>)>,
     // プレイヤーステータス（右上）の更新用: テキスト群（HP、スタミナ、モメンタム）
     // 右上プレイヤーステータスは別システムで更新（引数が多すぎるため分割）
@@ -2321,7 +2553,6 @@ fn ui_update_system(
     let Ok(mut ui_log_text) = ui_log_q.single_mut() else {
         return;
     };
-
     // 強化反映後の有効値
     let atk_power = if buffs.attack > 0 { 25 } else { 10 };
     let heal_amount = if buffs.heal > 0 { 60 } else { 50 };
@@ -2330,7 +2561,7 @@ fn ui_update_system(
     let def_cost = if buffs.defend > 0 { 5 } else { 10 };
 
     ui_status_text.0 = format!(
-        "プレイヤーHP: {} / {}\nスタミナ: {} / {}\nモメンタム: {} / 100\n強化 残り(攻:{} 強:{} 回:{} 防:{})\n\n敵HP: {} / {}\n敵ブレイク値: {} / 100\n敵状態: {}\n\n",
+        "プレイヤーHP: {} / {}\nスタミナ: {} / {}\nモメンタム: {} / 100\n強化 残り(攻:{} 強:{} 回:{} 防:{})\n\n敵HP: {} / {}\n敵ブレイク値: {} / {}\n敵状態: {}\n\n",
         p_hp.current,
         p_hp.max,
         p_sta.current,
@@ -2343,6 +2574,7 @@ fn ui_update_system(
         e_hp.current,
         e_hp.max,
         e_break.current,
+        difficulty.break_threshold(),
         if e_bstate.remaining_turns > 0 {
             "ブレイク中"
         } else {
@@ -2371,179 +2603,1506 @@ fn ui_update_system(
         Color::WHITE
     };
 
-    // 強攻撃の有効値表示は別システムで更新
+    // 強攻撃の有効値表示は別システムで更新
+
+    let Ok((mut eff_heal_text, mut eff_heal_color)) = ui_eff_heal_q.single_mut() else {
+        return;
+    };
+    eff_heal_text.0 = format!("回復 量:{} 消費:{}\n", heal_amount, heal_cost);
+    eff_heal_color.0 = if buffs.heal > 0 {
+        Color::from(LinearRgba {
+            red: 0.95,
+            green: 0.85,
+            blue: 0.35,
+            alpha: 1.0,
+        })
+    } else {
+        Color::WHITE
+    };
+
+    let Ok((mut eff_def_text, mut eff_def_color)) = ui_eff_def_q.single_mut() else {
+        return;
+    };
+    eff_def_text.0 = format!("防御 消費:{}\n\n", def_cost);
+    eff_def_color.0 = if buffs.defend > 0 {
+        Color::from(LinearRgba {
+            red: 0.95,
+            green: 0.85,
+            blue: 0.35,
+            alpha: 1.0,
+        })
+    } else {
+        Color::WHITE
+    };
+
+    // 選択中コマンド表示用の文字列
+    let selected_str = if pending.0.is_empty() {
+        "(なし)".to_string()
+    } else {
+        pending
+            .0
+            .iter()
+            .map(|c| c.label())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+    let phase_str = match *phase {
+        BattlePhase::AwaitCommand => format!(
+            "コマンド入力待ち \nコマンドを選択してください(複数可)\n A=攻撃 S=強攻撃 H=回復 D=防御 W=待機\n Z=攻撃強化 / X=強攻撃強化 / C=回復強化 / V=防御強化\n F=奥義(モメンタム100)\n Backspace=直前取り消し / Esc=全クリア\n Enter=決定\n [選択中] {selected_str}"
+        ),
+        BattlePhase::ConfirmQueued => {
+            let next_name = if let Some(next) = queue.0.front() {
+                next.label()
+            } else {
+                "(なし)"
+            };
+            format!(
+                "連続コマンド確認\n次の予約: {}\n Y=実行 / N=選択しなおし(以降リセット)",
+                next_name
+            )
+        }
+        BattlePhase::InBattle => "処理中".to_string(),
+        BattlePhase::Finished => "終了".to_string(),
+    };
+    ui_phase_text.0 = format!("フェーズ: {phase_str}\n\n");
+
+    let mut log_text = String::from("ログ:\n");
+    let log_max_lines = 30;
+    let start = if log.0.len() > log_max_lines {
+        log.0.len() - log_max_lines
+    } else {
+        0
+    };
+    for line in &log.0[start..] {
+        log_text.push_str(line);
+        log_text.push('\n');
+    }
+    ui_log_text.0 = log_text;
+}
+
+// コマンド入力表示（右端パネル）の表示制御と内容更新
+fn ui_update_command_system(
+    phase: Res<BattlePhase>,
+    planned: Res<EnemyPlannedAction>,
+    pending: Res<PendingSelections>,
+    queue: Res<CommandQueue>,
+    overdrive: Res<Overdrive>,
+    momentum: Res<Momentum>,
+    batch: Res<ConsecutiveBatch>,
+    auto: Res<AutoBattle>,
+    input: (Res<InputMap>, Res<PadInput>, Res<CommandCursor>),
+    dragon: (Res<Hotseat>, Res<EnemyMoveChoice>),
+    modes: (Res<Practice>, Res<Training>),
+    limit_q: Query<&BatchLimit, With<Player>>,
+    enemy_hp_q: Query<&Hp, With<Enemy>>,
+    mut cmd_panel_q: Query<(&mut Visibility, &Children), With<UiCommand>>,
+    mut texts: Query<&mut Text>,
+) {
+    let Ok((mut vis, children)) = cmd_panel_q.single_mut() else {
+        return;
+    };
+    let Ok(limit) = limit_q.single() else {
+        return;
+    };
+    let max_select = limit.current(&momentum, &overdrive);
+    // 2P対戦: ドラゴン側の行動一覧（選んだ内容は表示しない）
+    let (hotseat, choice) = dragon;
+    let dragon_str = match enemy_hp_q.single() {
+        Ok(e_hp) if hotseat.0 => {
+            let moves = enemy_action_table(e_hp)
+                .iter()
+                .zip(1..)
+                .map(|(&(_, create), n)| format!("{n}={}", create().name))
+                .collect::<Vec<_>>()
+                .join(" ");
+            let state = if choice.0.is_some() {
+                "選択済み"
+            } else if enemy_choice_needed(&planned) {
+                "未選択 (このターンに必要)"
+            } else {
+                "未選択"
+            };
+            format!("\n[2P ドラゴン] {state}\n{moves}")
+        }
+        _ => String::new(),
+    };
+    let (practice, training) = modes;
+    // ゲームパッドがつながっていればボタンも出す
+    let (input, pad, cursor) = input;
+    let practice_str = match (practice.enabled, training.enabled) {
+        (true, true) => "\n[練習] U=1ターン戻す [訓練] T=設定画面",
+        (true, false) => "\n[練習] U=1ターン戻す",
+        (false, true) => "\n[訓練] T=設定画面",
+        (false, false) => "",
+    };
+    match *phase {
+        BattlePhase::AwaitCommand => {
+            *vis = Visibility::Visible;
+            for child in children.iter() {
+                if let Ok(mut t) = texts.get_mut(child) {
+                    let selected_str = if pending.0.is_empty() {
+                        "(なし)".to_string()
+                    } else {
+                        pending
+                            .0
+                            .iter()
+                            .map(|c| c.label())
+                            .collect::<Vec<_>>()
+                            .join(", ")
+                    };
+                    let overdrive_str = if overdrive.is_ready() {
+                        "\n[オーバードライブ] 選択数+1・最初のコマンドは消費なし"
+                    } else {
+                        ""
+                    };
+                    let auto_str = if auto.enabled { "ON" } else { "OFF" };
+                    if USE_DQ_LIKE_LAYOUT {
+                        // コマンドとキーの一覧はパネル下のコマンド一覧に出す
+                        t.0 = format!(
+                            "[コマンド入力] {}:{auto_str}\n{}\n選択中 ({}/{max_select}): {selected_str}{overdrive_str}{practice_str}{dragon_str}",
+                            input.help(&[InputAction::AutoBattle], pad.connected),
+                            input.help(
+                                &[
+                                    InputAction::Choose,
+                                    InputAction::Undo,
+                                    InputAction::Clear,
+                                    InputAction::Decide
+                                ],
+                                pad.connected
+                            ),
+                            pending.0.len()
+                        );
+                        continue;
+                    }
+                    // カーソルのコマンドに >
+                    let row = |actions: &[InputAction]| {
+                        actions
+                            .iter()
+                            .map(|&a| {
+                                let mark = if a == InputAction::Command(CommandKind::ALL[cursor.0])
+                                {
+                                    ">"
+                                } else {
+                                    ""
+                                };
+                                format!("{mark}{}", input.help(&[a], pad.connected))
+                            })
+                            .collect::<Vec<_>>()
+                            .join(" ")
+                    };
+                    let [basic, enhance, finisher] = COMMAND_HELP_ROWS;
+                    t.0 = format!(
+                        "[コマンド入力] {}:{auto_str}\n{}\n{}\n{}(モメンタム100)\n{} \n選択中 ({}/{max_select}): {selected_str}{overdrive_str}{practice_str}{dragon_str}",
+                        input.help(&[InputAction::AutoBattle], pad.connected),
+                        row(basic),
+                        row(enhance),
+                        row(finisher),
+                        input.help(
+                            &[
+                                InputAction::Up,
+                                InputAction::Down,
+                                InputAction::Choose,
+                                InputAction::Undo,
+                                InputAction::Clear,
+                                InputAction::Decide
+                            ],
+                            pad.connected
+                        ),
+                        pending.0.len()
+                    );
+                }
+            }
+        }
+        BattlePhase::ConfirmQueued => {
+            *vis = Visibility::Visible;
+            for child in children.iter() {
+                if let Ok(mut t) = texts.get_mut(child) {
+                    // 予約一覧（カーソル位置に >）
+                    let mut list = String::new();
+                    for (i, cmd) in queue.0.iter().enumerate() {
+                        let mark = if i == batch.cursor { ">" } else { " " };
+                        list.push_str(&format!(
+                            "{mark}{}. {} (+モメンタム{})\n",
+                            batch.executed + 1 + i,
+                            cmd.label(),
+                            batch.gain_at(i)
+                        ));
+                    }
+                    let edited_str = if batch.edited {
+                        " [計画変更: 増加半減]"
+                    } else {
+                        ""
+                    };
+                    t.0 = format!(
+                        "[連続コマンド確認] {}/{}{edited_str}\n{list}{} / {}(以降の予約はリセット)\n{} Shift+{}{}=入れ替え\nコマンドキー=置換 Shift+キー=挿入 {}{practice_str}{dragon_str}",
+                        batch.executed + 1,
+                        batch.total,
+                        input.help(&[InputAction::Execute], pad.connected),
+                        input.help(&[InputAction::Reselect], pad.connected),
+                        input.help(&[InputAction::Up, InputAction::Down], pad.connected),
+                        input.label(InputAction::Up, false),
+                        input.label(InputAction::Down, false),
+                        input.help(&[InputAction::Remove], pad.connected),
+                    );
+                }
+            }
+        }
+        _ => {
+            *vis = Visibility::Hidden;
+        }
+    }
+}
+
+// ================== Menus ==================
+// タイトル・設定・結果・ポーズ画面の共通部品。ボタンはマウスでもキーでも同じ MenuAction を実行する
+#[derive(Component, Clone, Copy)]
+enum MenuAction {
+    StartBattle,
+    OpenSettings,
+    CloseSettings,
+    ToTitle,
+    Resume,
+    Restart,
+    Quit,
+    Pause,
+    OpenKeybinds,
+    CloseKeybinds,
+    ResetKeybinds,
+}
+
+// session: 今の戦闘の有無（設定画面の戻り先を決める）
+fn apply_menu_action(action: MenuAction, session: BattleSession, commands: &mut Commands) {
+    match action {
+        MenuAction::StartBattle => {
+            commands.set_state(BattleSession::Open);
+            commands.set_state(AppState::Battle);
+        }
+        MenuAction::OpenSettings => commands.set_state(AppState::Settings),
+        MenuAction::CloseSettings if session == BattleSession::Open => {
+            commands.set_state(AppState::Paused)
+        }
+        MenuAction::CloseSettings | MenuAction::ToTitle => {
+            commands.set_state(BattleSession::Closed);
+            commands.set_state(AppState::Title);
+        }
+        MenuAction::Resume => commands.set_state(AppState::Battle),
+        MenuAction::Restart => {
+            commands.queue(restart_battle);
+            commands.set_state(AppState::Battle);
+        }
+        MenuAction::Quit => {
+            commands.write_message(AppExit::Success);
+        }
+        MenuAction::Pause => commands.set_state(AppState::Paused),
+        MenuAction::OpenKeybinds => commands.set_state(AppState::Keybinds),
+        MenuAction::CloseKeybinds => commands.set_state(AppState::Settings),
+        MenuAction::ResetKeybinds => commands.queue(reset_keybinds),
+    }
+}
+
+// 画面中央の枠（文章＋ボタン列）。state を抜けると消える。文章のエンティティを返す
+fn spawn_menu_panel(
+    commands: &mut Commands,
+    state: AppState,
+    font: &Handle<Font>,
+    text: String,
+    buttons: &[(MenuAction, &str)],
+) -> Entity {
+    let mut text_entity = Entity::PLACEHOLDER;
+    commands
+        .spawn((
+            DespawnOnExit(state),
+            Node {
+                width: percent(100),
+                height: percent(100),
+                position_type: PositionType::Absolute,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..default()
+            },
+            ZIndex(50),
+        ))
+        .with_children(|screen| {
+            screen
+                .spawn((
+                    Node {
+                        width: Val::Px(460.0),
+                        flex_direction: FlexDirection::Column,
+                        row_gap: Val::Px(12.0),
+                        border: UiRect::all(Val::Px(1.0)),
+                        padding: UiRect::all(Val::Px(12.0)),
+                        ..default()
+                    },
+                    BackgroundColor(Color::BLACK),
+                    BorderColor::all(Color::WHITE),
+                ))
+                .with_children(|col| {
+                    text_entity = col
+                        .spawn((
+                            Text::new(text),
+                            TextFont {
+                                font: font.clone(),
+                                font_size: 18.0,
+                                ..default()
+                            },
+                            TextColor(Color::WHITE),
+                        ))
+                        .id();
+                    col.spawn(Node {
+                        column_gap: Val::Px(12.0),
+                        ..default()
+                    })
+                    .with_children(|row| {
+                        for &(action, label) in buttons {
+                            row.spawn((
+                                action,
+                                Button,
+                                Node {
+                                    padding: UiRect::axes(Val::Px(16.0), Val::Px(6.0)),
+                                    border: UiRect::all(Val::Px(1.0)),
+                                    ..default()
+                                },
+                                BackgroundColor(Color::BLACK),
+                                BorderColor::all(Color::WHITE),
+                            ))
+                            .with_child((
+                                Text::new(label),
+                                TextFont {
+                                    font: font.clone(),
+                                    font_size: 18.0,
+                                    ..default()
+                                },
+                                TextColor(Color::WHITE),
+                            ));
+                        }
+                    });
+                });
+        });
+    text_entity
+}
+
+// ボタンの見た目（ホバー・押下）とクリック時の実行
+fn menu_button_system(
+    mut commands: Commands,
+    mut buttons: Query<(&Interaction, &MenuAction, &mut BackgroundColor), Changed<Interaction>>,
+    session: Res<State<BattleSession>>,
+) {
+    for (interaction, &action, mut bg) in buttons.iter_mut() {
+        bg.0 = button_color(interaction);
+        if *interaction == Interaction::Pressed {
+            apply_menu_action(action, *session.get(), &mut commands);
+        }
+    }
+}
+
+fn button_color(interaction: &Interaction) -> Color {
+    match interaction {
+        Interaction::Pressed => Color::srgb(0.4, 0.4, 0.4),
+        Interaction::Hovered => Color::srgb(0.25, 0.25, 0.25),
+        Interaction::None => Color::BLACK,
+    }
+}
+
+// 選択中の難易度とモードの一覧（タイトルと設定画面で共通）
+fn mode_summary(
+    difficulty: Difficulty,
+    hotseat: &Hotseat,
+    practice: &Practice,
+    training: &Training,
+) -> String {
+    let mut modes = Vec::new();
+    if practice.enabled {
+        modes.push("練習");
+    }
+    if training.enabled {
+        modes.push("訓練");
+    }
+    if hotseat.0 {
+        modes.push("2P対戦");
+    }
+    format!(
+        "難易度: {}  モード: {}",
+        difficulty.label(),
+        if modes.is_empty() {
+            "通常".to_string()
+        } else {
+            modes.join("・")
+        }
+    )
+}
+
+fn setup_title(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    difficulty: Res<Difficulty>,
+    modes: (Res<Hotseat>, Res<Practice>, Res<Training>),
+) {
+    let font = asset_server.load("fonts/x12y16pxMaruMonica.ttf");
+    let (hotseat, practice, training) = modes;
+    let text = format!(
+        "COMMAND BATTLE\n\n{}\n\nEnter=戦闘開始  S=設定  Q=終了",
+        mode_summary(*difficulty, &hotseat, &practice, &training)
+    );
+    spawn_menu_panel(
+        &mut commands,
+        AppState::Title,
+        &font,
+        text,
+        &[
+            (MenuAction::StartBattle, "戦闘開始"),
+            (MenuAction::OpenSettings, "設定"),
+            (MenuAction::Quit, "終了"),
+        ],
+    );
+}
+
+fn title_input_system(mut commands: Commands, keyboard: Res<ButtonInput<KeyCode>>) {
+    let action = if keyboard.just_pressed(KeyCode::Enter) {
+        MenuAction::StartBattle
+    } else if keyboard.just_pressed(KeyCode::KeyS) {
+        MenuAction::OpenSettings
+    } else if keyboard.just_pressed(KeyCode::KeyQ) {
+        MenuAction::Quit
+    } else {
+        return;
+    };
+    apply_menu_action(action, BattleSession::Closed, &mut commands);
+}
+
+// ---- 設定画面: 難易度とモード（起動引数の値が初期値）とキー設定画面への入口
+const SETTINGS_ROWS: usize = 5;
+
+#[derive(Resource, Default)]
+struct SettingsCursor(usize);
+
+#[derive(Component)]
+struct UiSettingsText;
+
+fn setup_settings(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let font = asset_server.load("fonts/x12y16pxMaruMonica.ttf");
+    let text = spawn_menu_panel(
+        &mut commands,
+        AppState::Settings,
+        &font,
+        String::new(),
+        &[(MenuAction::CloseSettings, "戻る (Esc)")],
+    );
+    commands.entity(text).insert(UiSettingsText);
+}
+
+// ↑↓=項目 / ←→・Enter=変更 / Esc=戻る（タイトルかポーズ）
+// 戦闘中（ポーズから開いた場合）は難易度を変えられない
+fn settings_input_system(
+    mut commands: Commands,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    mut cursor: ResMut<SettingsCursor>,
+    mut difficulty: ResMut<Difficulty>,
+    mut modes: (ResMut<Hotseat>, ResMut<Practice>, ResMut<Training>),
+    session: Res<State<BattleSession>>,
+) {
+    let session = *session.get();
+    if keyboard.just_pressed(KeyCode::Escape) {
+        apply_menu_action(MenuAction::CloseSettings, session, &mut commands);
+        return;
+    }
+    if keyboard.just_pressed(KeyCode::ArrowUp) {
+        cursor.0 = (cursor.0 + SETTINGS_ROWS - 1) % SETTINGS_ROWS;
+    }
+    if keyboard.just_pressed(KeyCode::ArrowDown) {
+        cursor.0 = (cursor.0 + 1) % SETTINGS_ROWS;
+    }
+    let step: isize = if keyboard.just_pressed(KeyCode::ArrowLeft) {
+        -1
+    } else if keyboard.any_just_pressed([KeyCode::ArrowRight, KeyCode::Enter]) {
+        1
+    } else {
+        return;
+    };
+    let (hotseat, practice, training) = &mut modes;
+    match cursor.0 {
+        0 if session == BattleSession::Open => {}
+        0 => {
+            let all = Difficulty::ALL;
+            let i = all.iter().position(|d| d == &*difficulty).unwrap_or(0) as isize;
+            *difficulty = all[(i + step).rem_euclid(all.len() as isize) as usize];
+        }
+        1 => practice.enabled = !practice.enabled,
+        2 => training.enabled = !training.enabled,
+        3 => hotseat.0 = !hotseat.0,
+        _ => apply_menu_action(MenuAction::OpenKeybinds, session, &mut commands),
+    }
+}
+
+fn ui_update_settings_system(
+    cursor: Res<SettingsCursor>,
+    difficulty: Res<Difficulty>,
+    modes: (Res<Hotseat>, Res<Practice>, Res<Training>),
+    session: Res<State<BattleSession>>,
+    mut text_q: Query<&mut Text, With<UiSettingsText>>,
+) {
+    let Ok(mut text) = text_q.single_mut() else {
+        return;
+    };
+    let (hotseat, practice, training) = modes;
+    let on_off = |b: bool| if b { "ON" } else { "OFF" };
+    let rows = [
+        if *session.get() == BattleSession::Open {
+            format!("難易度: {}（戦闘中は変更不可）", difficulty.label())
+        } else {
+            format!("難易度: {}", difficulty.label())
+        },
+        format!("練習モード（U で巻き戻し）: {}", on_off(practice.enabled)),
+        format!("訓練モード（T で設定画面）: {}", on_off(training.enabled)),
+        format!("2P対戦（ホットシート）: {}", on_off(hotseat.0)),
+        "キー設定（Enter で開く）".to_string(),
+    ];
+    let mut lines = vec!["[設定]".to_string()];
+    for (i, row) in rows.iter().enumerate() {
+        let marker = if i == cursor.0 { "> " } else { "  " };
+        lines.push(format!("{marker}{row}"));
+    }
+    lines.push("↑↓=項目 ←→/Enter=変更 Esc=戻る".to_string());
+    text.0 = lines.join("\n");
+}
+
+// ---- キー設定画面: 戦闘中のキーを変えて config/keybinds.txt に保存する
+#[derive(Resource, Default)]
+struct KeybindCursor {
+    row: usize,
+    waiting: bool,  // 割り当てるキーの入力待ち
+    status: String, // 直前の操作の結果
+}
+
+#[derive(Component)]
+struct UiKeybindsText;
+
+fn setup_keybinds(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut cursor: ResMut<KeybindCursor>,
+) {
+    let font = asset_server.load("fonts/x12y16pxMaruMonica.ttf");
+    cursor.waiting = false;
+    cursor.status.clear();
+    let text = spawn_menu_panel(
+        &mut commands,
+        AppState::Keybinds,
+        &font,
+        String::new(),
+        &[
+            (MenuAction::ResetKeybinds, "既定に戻す (R)"),
+            (MenuAction::CloseKeybinds, "戻る (Esc)"),
+        ],
+    );
+    commands.entity(text).insert(UiKeybindsText);
+}
+
+// ↑↓=項目 / Enter=変更（次に押したキーかゲームパッドのボタンを割り当てる）/ R=既定に戻す / Esc=戻る
+fn keybinds_input_system(
+    mut commands: Commands,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    pad: Res<PadInput>,
+    mut cursor: ResMut<KeybindCursor>,
+    mut input: ResMut<InputMap>,
+    session: Res<State<BattleSession>>,
+) {
+    if cursor.waiting {
+        // 画面のボタンは割り当てに使わない
+        let controls = Controls {
+            keyboard: &keyboard,
+            pad: &pad,
+            pointer: &PointerInput::default(),
+        };
+        let Some(binding) = controls.first_just_pressed() else {
+            if keyboard.get_just_pressed().next().is_some() {
+                cursor.status = "そのキーは割り当てられません".to_string();
+            }
+            return;
+        };
+        cursor.waiting = false;
+        let action = InputAction::ALL[cursor.row];
+        let unbound = input.bind(action, binding);
+        let mut status = format!("{}を{}に割り当てました", binding.label(), action.label());
+        if !unbound.is_empty() {
+            let names = unbound.iter().map(|a| a.label()).collect::<Vec<_>>();
+            status.push_str(&format!("（{}から外しました）", names.join("・")));
+        }
+        if let Err(e) = save_input_map(&input) {
+            status.push_str(&format!(" / 保存に失敗: {e}"));
+        }
+        cursor.status = status;
+        return;
+    }
+    let rows = InputAction::ALL.len();
+    if keyboard.just_pressed(KeyCode::Escape) {
+        apply_menu_action(MenuAction::CloseKeybinds, *session.get(), &mut commands);
+    } else if keyboard.just_pressed(KeyCode::ArrowUp) {
+        cursor.row = (cursor.row + rows - 1) % rows;
+    } else if keyboard.just_pressed(KeyCode::ArrowDown) {
+        cursor.row = (cursor.row + 1) % rows;
+    } else if keyboard.just_pressed(KeyCode::Enter) {
+        cursor.waiting = true;
+        cursor.status = "割り当てるキーかボタンを押してください".to_string();
+    } else if keyboard.just_pressed(KeyCode::KeyR) {
+        apply_menu_action(MenuAction::ResetKeybinds, *session.get(), &mut commands);
+    }
+}
+
+fn reset_keybinds(world: &mut World) {
+    let map = InputMap::default();
+    let status = match save_input_map(&map) {
+        Ok(()) => "既定のキーに戻しました".to_string(),
+        Err(e) => format!("既定のキーに戻しました / 保存に失敗: {e}"),
+    };
+    world.insert_resource(map);
+    let mut cursor = world.resource_mut::<KeybindCursor>();
+    cursor.waiting = false;
+    cursor.status = status;
+}
+
+fn ui_update_keybinds_system(
+    cursor: Res<KeybindCursor>,
+    input: Res<InputMap>,
+    mut text_q: Query<&mut Text, With<UiKeybindsText>>,
+) {
+    let Ok(mut text) = text_q.single_mut() else {
+        return;
+    };
+    let mut lines = vec!["[キー設定]".to_string()];
+    for (i, &action) in InputAction::ALL.iter().enumerate() {
+        let marker = if i == cursor.row { "> " } else { "  " };
+        let keys = if i == cursor.row && cursor.waiting {
+            "（キーかボタンを押してください）".to_string()
+        } else {
+            input.label(action, true)
+        };
+        lines.push(format!("{marker}{}: {keys}", action.label()));
+    }
+    lines.push("↑↓=項目 Enter=変更 R=既定に戻す Esc=戻る".to_string());
+    if !cursor.status.is_empty() {
+        lines.push(cursor.status.clone());
+    }
+    text.0 = lines.join("\n");
+}
+
+// ---- 結果画面
+// 戦闘が終わり、撃破・敗北バナー（奥義演出含む）が消えたら結果画面へ
+fn battle_finish_system(
+    mut commands: Commands,
+    phase: Res<BattlePhase>,
+    player_q: Query<&Hp, With<Player>>,
+    banner_q: Query<(), With<BossSlainBanner>>,
+) {
+    if *phase != BattlePhase::Finished || !banner_q.is_empty() {
+        return;
+    }
+    let won = player_q.single().is_ok_and(|hp| hp.current > 0);
+    commands.insert_resource(BattleOutcome { won });
+    commands.set_state(BattleSession::Closed);
+    commands.set_state(AppState::Results);
+}
+
+fn setup_results(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    stats: Res<BattleStats>,
+    outcome: Res<BattleOutcome>,
+) {
+    let font = asset_server.load("fonts/x12y16pxMaruMonica.ttf");
+    let lines = [
+        if outcome.won { "勝利!" } else { "敗北..." }.to_string(),
+        format!("難易度: {}", stats.difficulty.label()),
+        format!("経過ターン: {}", stats.turns),
+        format!("与えたダメージ: {}", stats.damage_dealt),
+        format!("受けたダメージ: {}", stats.damage_taken),
+        format!("最大ダメージ: {}", stats.largest_hit),
+        format!("ブレイク中のターン: {}", stats.turns_broken),
+        format!(
+            "連撃: {}回  ガードカウンター: {}回  コンボ: {}回",
+            stats.chain_attacks, stats.guard_counters, stats.combos
+        ),
+        format!(
+            "モメンタム: 獲得{}  消費{}",
+            stats.momentum_earned, stats.momentum_spent
+        ),
+        format!("スタミナ不足: {}回", stats.stamina_failures),
+    ];
+    spawn_menu_panel(
+        &mut commands,
+        AppState::Results,
+        &font,
+        lines.join("\n"),
+        &[
+            (MenuAction::StartBattle, "もう一度 (R)"),
+            (MenuAction::ToTitle, "タイトルへ (T)"),
+            (MenuAction::Quit, "終了 (Q)"),
+        ],
+    );
+}
+
+// R=もう一度（同じ難易度・モードで新しい戦闘）/ T=タイトルへ / Q=終了
+fn results_input_system(mut commands: Commands, keyboard: Res<ButtonInput<KeyCode>>) {
+    let action = if keyboard.just_pressed(KeyCode::KeyR) {
+        MenuAction::StartBattle
+    } else if keyboard.just_pressed(KeyCode::KeyT) {
+        MenuAction::ToTitle
+    } else if keyboard.just_pressed(KeyCode::KeyQ) {
+        MenuAction::Quit
+    } else {
+        return;
+    };
+    apply_menu_action(action, BattleSession::Closed, &mut commands);
+}
+
+// ---- ポーズ（P で開閉。Esc は選択のクリアに使うので使わない）
+// 戦闘のシステムは AppState::Battle でしか動かないので、入力も演出もここで止まる
+fn setup_pause(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let font = asset_server.load("fonts/x12y16pxMaruMonica.ttf");
+    spawn_menu_panel(
+        &mut commands,
+        AppState::Paused,
+        &font,
+        "PAUSE\n\nP=再開  S=設定  R=最初から  Q=やめる（タイトルへ）".to_string(),
+        &[
+            (MenuAction::Resume, "再開"),
+            (MenuAction::OpenSettings, "設定"),
+            (MenuAction::Restart, "最初から"),
+            (MenuAction::ToTitle, "やめる"),
+        ],
+    );
+}
+
+// 仮想時間も止めておく（Time を見るものがポーズ中に進まないように）
+fn pause_time(mut time: ResMut<Time<Virtual>>) {
+    time.pause();
+}
+
+fn resume_time(mut time: ResMut<Time<Virtual>>) {
+    time.unpause();
+}
+
+fn pause_input_system(
+    mut commands: Commands,
+    keyboard: Res<ButtonInput<KeyCode>>,
+    state: Res<State<AppState>>,
+) {
+    let action = match state.get() {
+        AppState::Battle if keyboard.just_pressed(KeyCode::KeyP) => {
+            commands.set_state(AppState::Paused);
+            return;
+        }
+        AppState::Paused if keyboard.just_pressed(KeyCode::KeyP) => MenuAction::Resume,
+        AppState::Paused if keyboard.just_pressed(KeyCode::KeyS) => MenuAction::OpenSettings,
+        AppState::Paused if keyboard.just_pressed(KeyCode::KeyR) => MenuAction::Restart,
+        AppState::Paused if keyboard.just_pressed(KeyCode::KeyQ) => MenuAction::ToTitle,
+        _ => return,
+    };
+    apply_menu_action(action, BattleSession::Open, &mut commands);
+}
+
+// 戦闘を同じ設定で作り直す（ポーズの「最初から」。同じ状態への遷移では OnEnter が走らないので直接）
+fn restart_battle(world: &mut World) {
+    let entities: Vec<Entity> = world
+        .query_filtered::<Entity, With<DespawnOnExit<BattleSession>>>()
+        .iter(world)
+        .collect();
+    for entity in entities {
+        world.despawn(entity);
+    }
+    for result in [
+        world.run_system_cached(setup_battle),
+        world.run_system_cached(setup),
+        world.run_system_cached(training_setup),
+    ] {
+        if let Err(e) = result {
+            error!("戦闘の作り直しに失敗: {e}");
+        }
+    }
+}
+
+// ================== Battle Snapshot ==================
+// ECS上の戦闘状態を BattleState に写し取る（ログは空）
+fn capture_battle_state(world: &mut World) -> Option<BattleState> {
+    let (p_attack, p_hp, p_sta, p_limit) = world
+        .query_filtered::<(&Attack, &Hp, &Stamina, &BatchLimit), With<Player>>()
+        .single(world)
+        .ok()
+        .map(|(a, h, s, l)| (a.clone(), h.clone(), s.clone(), l.clone()))?;
+    let (e_attack, e_hp, e_break, e_bstate, e_bregen) = world
+        .query_filtered::<(&Attack, &Hp, &BreakValue, &BreakState, &BreakRegen), With<Enemy>>()
+        .single(world)
+        .ok()
+        .map(|(a, h, b, s, r)| (a.clone(), h.clone(), b.clone(), s.clone(), r.clone()))?;
+    Some(BattleState {
+        phase: world.resource::<BattlePhase>().clone(),
+        turn: world.resource::<Turn>().clone(),
+        p_attack,
+        p_hp,
+        p_sta,
+        p_limit,
+        e_attack,
+        e_hp,
+        e_break,
+        e_bstate,
+        e_bregen,
+        log: CombatLog(Vec::new()),
+        defend: world.resource::<DefendNextAttack>().clone(),
+        guard: world.resource::<GuardCounterReady>().clone(),
+        combo: world.resource::<PlayerComboState>().clone(),
+        combo_rules: world.resource::<ComboResetRules>().clone(),
+        planned: world.resource::<EnemyPlannedAction>().clone(),
+        momentum: world.resource::<Momentum>().clone(),
+        overdrive: world.resource::<Overdrive>().clone(),
+        buffs: world.resource::<CommandBuffs>().clone(),
+        batch: world.resource::<ConsecutiveBatch>().clone(),
+        enemy_damage_popup: EnemyDamagePopup::default(),
+        finisher_cinematic: FinisherCinematic::default(),
+        rng: world.resource::<BattleRng>().clone(),
+        events: TurnEvents::default(),
+        enemy_choice: world.resource::<EnemyMoveChoice>().clone(),
+        difficulty: *world.resource::<Difficulty>(),
+        stats: world.resource::<BattleStats>().clone(),
+    })
+}
+
+// BattleState の内容をECSに書き戻す（演出・ログ・選択中のコマンドは含まない）
+fn restore_battle_state(world: &mut World, state: &BattleState) {
+    if let Ok((mut a, mut h, mut s, mut l)) = world
+        .query_filtered::<(&mut Attack, &mut Hp, &mut Stamina, &mut BatchLimit), With<Player>>()
+        .single_mut(world)
+    {
+        *a = state.p_attack.clone();
+        *h = state.p_hp.clone();
+        *s = state.p_sta.clone();
+        *l = state.p_limit.clone();
+    }
+    if let Ok((mut a, mut h, mut b, mut bs, mut r)) = world
+        .query_filtered::<(
+            &mut Attack,
+            &mut Hp,
+            &mut BreakValue,
+            &mut BreakState,
+            &mut BreakRegen,
+        ), With<Enemy>>()
+        .single_mut(world)
+    {
+        *a = state.e_attack.clone();
+        *h = state.e_hp.clone();
+        *b = state.e_break.clone();
+        *bs = state.e_bstate.clone();
+        *r = state.e_bregen.clone();
+    }
+    world.insert_resource(state.phase.clone());
+    world.insert_resource(state.turn.clone());
+    world.insert_resource(state.defend.clone());
+    world.insert_resource(state.guard.clone());
+    world.insert_resource(state.combo.clone());
+    world.insert_resource(state.combo_rules.clone());
+    world.insert_resource(state.planned.clone());
+    world.insert_resource(state.momentum.clone());
+    world.insert_resource(state.overdrive.clone());
+    world.insert_resource(state.buffs.clone());
+    world.insert_resource(state.batch.clone());
+    world.insert_resource(state.rng.clone());
+    world.insert_resource(state.enemy_choice.clone());
+    world.insert_resource(state.stats.clone());
+    world.insert_resource(EnemyDamagePopup::default());
+    world.insert_resource(FinisherCinematic::default());
+}
+
+// ================== Practice Mode ==================
+// 各ターンの解決前（コマンド入力待ち・連続コマンド確認）の状態を1ターン1件で残す
+fn practice_snapshot_system(world: &mut World) {
+    if !world.resource::<Practice>().enabled {
+        return;
+    }
+    if !matches!(
+        *world.resource::<BattlePhase>(),
+        BattlePhase::AwaitCommand | BattlePhase::ConfirmQueued
+    ) {
+        return;
+    }
+    let turn = world.resource::<Turn>().0;
+    let practice = world.resource::<Practice>();
+    if practice
+        .history
+        .last()
+        .is_some_and(|s| s.state.turn.0 == turn)
+    {
+        return;
+    }
+    let Some(state) = capture_battle_state(world) else {
+        return;
+    };
+    let snapshot = TurnSnapshot {
+        state,
+        queue: world.resource::<CommandQueue>().clone(),
+        log: world.resource::<CombatLog>().0.clone(),
+    };
+    world.resource_mut::<Practice>().history.push(snapshot);
+}
+
+// U: 1つ前のターンの解決前に戻す（敵の乱数も戻るので、同じ入力なら敵も同じ行動をとる）
+fn practice_rewind_system(world: &mut World) {
+    if !world.resource::<Practice>().enabled
+        || !world
+            .resource::<ButtonInput<KeyCode>>()
+            .just_pressed(KeyCode::KeyU)
+    {
+        return;
+    }
+    let turn = world.resource::<Turn>().0;
+    let mut practice = world.resource_mut::<Practice>();
+    // 今のターンより前で一番新しいもの（それより後は捨てる）
+    let Some(index) = practice.history.iter().rposition(|s| s.state.turn.0 < turn) else {
+        world
+            .resource_mut::<CombatLog>()
+            .0
+            .push("これ以上戻せません".to_string());
+        return;
+    };
+    practice.history.truncate(index + 1);
+    let snapshot = &practice.history[index];
+    let (state, queue, mut log) = (
+        snapshot.state.clone(),
+        snapshot.queue.clone(),
+        snapshot.log.clone(),
+    );
+    restore_battle_state(world, &state);
+    world.insert_resource(queue);
+    world.resource_mut::<PendingSelections>().0.clear();
+    log.push(format!("ターン {} に巻き戻しました", state.turn.0));
+    world.insert_resource(CombatLog(log));
+    // 決着後に戻した場合は勝敗バナーを消す（敵UIは戻した敵HPに合わせて表示される）
+    let banners: Vec<Entity> = world
+        .query_filtered::<Entity, With<BattleEndBanner>>()
+        .iter(world)
+        .collect();
+    for banner in banners {
+        world.despawn(banner);
+    }
+}
+
+// ================== Training Mode ==================
+// 設定画面の行数（勇者HP/スタミナ/モメンタム/敵HP/ブレイク値/強化4種/敵行動固定/無限リソース/敵HP補充）
+const TRAINING_ROWS: usize = 12;
+
+impl TrainingSettings {
+    // 現在の戦闘状態を設定値として読み込む（敵行動固定・無限リソース・敵HP補充はそのまま）
+    fn load(&mut self, state: &BattleState) {
+        self.player_hp = state.p_hp.current;
+        self.player_stamina = state.p_sta.current;
+        self.momentum = state.momentum.current;
+        self.enemy_hp = state.e_hp.current;
+        self.enemy_break = state.e_break.current;
+        self.buffs = state.buffs.clone();
+    }
+
+    fn locked_action(&self) -> Option<Action> {
+        self.enemy_lock.checked_sub(1).map(|i| ENEMY_MOVES[i]())
+    }
+
+    fn row_text(&self, row: usize) -> String {
+        match row {
+            0 => format!("勇者HP: {}", self.player_hp),
+            1 => format!("勇者スタミナ: {}", self.player_stamina),
+            2 => format!("モメンタム: {}", self.momentum),
+            3 => format!("ドラゴンHP: {}", self.enemy_hp),
+            4 => format!("ブレイク値: {}", self.enemy_break),
+            5 => format!("攻撃強化: 残り{}ターン", self.buffs.attack),
+            6 => format!("強攻撃強化: 残り{}ターン", self.buffs.skill),
+            7 => format!("回復強化: 残り{}ターン", self.buffs.heal),
+            8 => format!("防御強化: 残り{}ターン", self.buffs.defend),
+            9 => match self.locked_action() {
+                None => "敵の行動: 固定しない".to_string(),
+                Some(action) if action.name == "待機" => {
+                    "敵の行動: 待機（何もしない）".to_string()
+                }
+                Some(action) => format!("敵の行動: {} に固定", action.name),
+            },
+            10 => format!("無限リソース: {}", if self.infinite { "ON" } else { "OFF" }),
+            _ => format!(
+                "ドラゴンHPの補充: {}",
+                if self.enemy_refill { "ON" } else { "OFF" }
+            ),
+        }
+    }
+
+    // row の値を steps 段階増減する（上限は現在の最大値・ブレイク閾値）
+    fn adjust(&mut self, row: usize, steps: i32, state: &BattleState) {
+        // 強化は使った直後の残りターンまで設定できる
+        let buff =
+            |turns: &mut u32| *turns = (*turns as i32 + steps).clamp(0, BUFF_TURNS as i32) as u32;
+        match row {
+            0 => self.player_hp = (self.player_hp + steps * 10).clamp(1, state.p_hp.max),
+            1 => self.player_stamina = (self.player_stamina + steps * 10).clamp(0, state.p_sta.max),
+            2 => self.momentum = (self.momentum + steps * 10).clamp(0, 100),
+            3 => self.enemy_hp = (self.enemy_hp + steps * 50).clamp(1, state.e_hp.max),
+            // 閾値に届くとその場でブレイクするので、設定できるのは閾値の手前まで
+            4 => {
+                self.enemy_break =
+                    (self.enemy_break + steps * 5).clamp(0, state.difficulty.break_threshold() - 1)
+            }
+            5 => buff(&mut self.buffs.attack),
+            6 => buff(&mut self.buffs.skill),
+            7 => buff(&mut self.buffs.heal),
+            8 => buff(&mut self.buffs.defend),
+            9 => {
+                let n = ENEMY_MOVES.len() as i32 + 1;
+                self.enemy_lock = (self.enemy_lock as i32 + steps).rem_euclid(n) as usize;
+            }
+            10 => self.infinite = !self.infinite,
+            _ => self.enemy_refill = !self.enemy_refill,
+        }
+    }
+}
+
+// 戦闘開始時に設定画面を開き、初期値を読み込む
+fn training_setup(world: &mut World) {
+    if !world.resource::<Training>().enabled {
+        return;
+    }
+    let Some(state) = capture_battle_state(world) else {
+        return;
+    };
+    let mut training = world.resource_mut::<Training>();
+    training.settings.load(&state);
+    training.open = true;
+    training.refilled_turn = 0;
+}
+
+fn training_closed(training: Res<Training>) -> bool {
+    !training.open
+}
+
+// 設定画面の操作と、敵行動の固定・無限リソースの維持
+// T=開閉 / ↑↓=項目 / ←→=増減（Shift で10倍）/ Enter=反映して閉じる / Esc=反映せず閉じる
+fn training_system(world: &mut World) {
+    if !world.resource::<Training>().enabled {
+        return;
+    }
+    let keyboard = world.resource::<ButtonInput<KeyCode>>().clone();
+    let phase = world.resource::<BattlePhase>().clone();
+    let open = world.resource::<Training>().open;
+    if !open {
+        if keyboard.just_pressed(KeyCode::KeyT) && phase == BattlePhase::AwaitCommand {
+            if let Some(state) = capture_battle_state(world) {
+                let mut training = world.resource_mut::<Training>();
+                training.settings.load(&state);
+                training.open = true;
+            }
+            return;
+        }
+        training_maintain(world);
+        return;
+    }
+    if keyboard.any_just_pressed([KeyCode::KeyT, KeyCode::Escape]) {
+        world.resource_mut::<Training>().open = false;
+        return;
+    }
+    if keyboard.just_pressed(KeyCode::Enter) {
+        training_apply(world);
+        return;
+    }
+    let Some(state) = capture_battle_state(world) else {
+        return;
+    };
+    let scale = if keyboard.any_pressed([KeyCode::ShiftLeft, KeyCode::ShiftRight]) {
+        10
+    } else {
+        1
+    };
+    let mut training = world.resource_mut::<Training>();
+    if keyboard.just_pressed(KeyCode::ArrowUp) {
+        training.cursor = (training.cursor + TRAINING_ROWS - 1) % TRAINING_ROWS;
+    }
+    if keyboard.just_pressed(KeyCode::ArrowDown) {
+        training.cursor = (training.cursor + 1) % TRAINING_ROWS;
+    }
+    let row = training.cursor;
+    if keyboard.just_pressed(KeyCode::ArrowRight) {
+        training.settings.adjust(row, scale, &state);
+    }
+    if keyboard.just_pressed(KeyCode::ArrowLeft) {
+        training.settings.adjust(row, -scale, &state);
+    }
+}
+
+// 設定を戦闘状態に書き込む。敵の行動を固定した場合は現在の予定も差し替える
+fn training_apply(world: &mut World) {
+    let Some(mut state) = capture_battle_state(world) else {
+        return;
+    };
+    let mut training = world.resource_mut::<Training>();
+    training.open = false;
+    training.refilled_turn = state.turn.0;
+    let settings = training.settings.clone();
+    state.p_hp.current = settings.player_hp;
+    state.p_sta.current = settings.player_stamina;
+    state.momentum.current = settings.momentum;
+    state.e_hp.current = settings.enemy_hp;
+    state.e_break.current = settings.enemy_break;
+    state.buffs = settings.buffs.clone();
+    if let Some(action) = settings.locked_action() {
+        state.planned = EnemyPlannedAction(ActionProcess::from(&Arc::new(action)));
+        state.enemy_choice = EnemyMoveChoice::default();
+    }
+    restore_battle_state(world, &state);
+    world
+        .resource_mut::<CombatLog>()
+        .0
+        .push("訓練設定を反映しました".to_string());
+}
+
+// 敵行動の固定（次の行動を毎回予約する）と、ターン開始時の無限リソース補充
+fn training_maintain(world: &mut World) {
+    let settings = world.resource::<Training>().settings.clone();
+    if let Some(action) = settings.locked_action() {
+        let mut choice = world.resource_mut::<EnemyMoveChoice>();
+        if choice.0.is_none() {
+            choice.0 = Some(action);
+        }
+    }
+    let turn = world.resource::<Turn>().0;
+    if !(settings.infinite || settings.enemy_refill)
+        || *world.resource::<BattlePhase>() != BattlePhase::AwaitCommand
+        || world.resource::<Training>().refilled_turn == turn
+    {
+        return;
+    }
+    world.resource_mut::<Training>().refilled_turn = turn;
+    if settings.infinite {
+        if let Ok((mut hp, mut sta)) = world
+            .query_filtered::<(&mut Hp, &mut Stamina), With<Player>>()
+            .single_mut(world)
+        {
+            hp.current = hp.current.max(settings.player_hp);
+            sta.current = sta.current.max(settings.player_stamina);
+        }
+        let mut momentum = world.resource_mut::<Momentum>();
+        momentum.current = momentum.current.max(settings.momentum);
+    }
+    if settings.enemy_refill
+        && let Ok(mut hp) = world
+            .query_filtered::<&mut Hp, With<Enemy>>()
+            .single_mut(world)
+    {
+        hp.current = hp.current.max(settings.enemy_hp);
+    }
+}
+
+fn ui_update_training_system(
+    training: Res<Training>,
+    mut panel_q: Query<(&mut Visibility, &Children), With<UiTrainingPanel>>,
+    mut texts: Query<&mut Text>,
+) {
+    let Ok((mut vis, children)) = panel_q.single_mut() else {
+        return;
+    };
+    if !training.open {
+        *vis = Visibility::Hidden;
+        return;
+    }
+    *vis = Visibility::Visible;
+    let mut lines = vec!["[訓練設定]".to_string()];
+    for row in 0..TRAINING_ROWS {
+        let marker = if row == training.cursor { "> " } else { "  " };
+        lines.push(format!("{marker}{}", training.settings.row_text(row)));
+    }
+    lines.push("↑↓=項目 ←→=増減 (Shift=10倍) Enter=反映 Esc/T=閉じる".to_string());
+    for child in children.iter() {
+        if let Ok(mut t) = texts.get_mut(child) {
+            t.0 = lines.join("\n");
+        }
+    }
+}
+
+// ================== Command Menu ==================
+// USE_DQ_LIKE_LAYOUT のコマンド一覧と操作ボタン。押した内容はキー・ゲームパッドと同じ InputAction として扱う
+
+// 操作ボタン（InputAction::scenes で、コマンド選択中と予約確認中のどちらに出すかを決める）
+const MENU_BUTTONS: [InputAction; 7] = [
+    InputAction::Decide,
+    InputAction::Undo,
+    InputAction::Clear,
+    InputAction::Execute,
+    InputAction::Reselect,
+    InputAction::Remove,
+    InputAction::AutoBattle,
+];
+
+// コマンド入力パネルの中身（2列のコマンド一覧と操作ボタン）
+fn spawn_command_menu(col: &mut ChildSpawnerCommands, font: &Handle<Font>) {
+    // 左列: 攻撃〜待機 / 右列: 強化4種と奥義（CommandKind::ALL を列ごとに並べる）
+    col.spawn(Node {
+        display: Display::Grid,
+        grid_template_columns: RepeatedGridTrack::flex(2, 1.0),
+        grid_template_rows: RepeatedGridTrack::auto(COMMAND_MENU_ROWS as u16),
+        grid_auto_flow: GridAutoFlow::Column,
+        margin: UiRect::top(Val::Px(8.0)),
+        ..default()
+    })
+    .with_children(|grid| {
+        for cmd in CommandKind::ALL {
+            grid.spawn((
+                UiCommandItem(cmd),
+                Button,
+                Node {
+                    padding: UiRect::axes(Val::Px(2.0), Val::Px(3.0)),
+                    ..default()
+                },
+            ))
+            .with_child((
+                Text::new(cmd.label()),
+                TextFont {
+                    font: font.clone(),
+                    font_size: 16.0,
+                    ..default()
+                },
+                TextColor(Color::WHITE),
+            ));
+        }
+    });
+    // タッチでも遊べるように、決定や取り消しもボタンで押せるようにする
+    col.spawn(Node {
+        flex_wrap: FlexWrap::Wrap,
+        column_gap: Val::Px(6.0),
+        row_gap: Val::Px(6.0),
+        margin: UiRect::top(Val::Px(8.0)),
+        ..default()
+    })
+    .with_children(|row| {
+        let button = (
+            Button,
+            Node {
+                padding: UiRect::axes(Val::Px(8.0), Val::Px(4.0)),
+                border: UiRect::all(Val::Px(1.0)),
+                ..default()
+            },
+            BackgroundColor(Color::BLACK),
+            BorderColor::all(Color::WHITE),
+        );
+        let label = |text: &str| {
+            (
+                Text::new(text),
+                TextFont {
+                    font: font.clone(),
+                    font_size: 14.0,
+                    ..default()
+                },
+                TextColor(Color::WHITE),
+            )
+        };
+        for action in MENU_BUTTONS {
+            row.spawn((UiInputButton(action), button.clone()))
+                .with_child(label(action.label()));
+        }
+        row.spawn((MenuAction::Pause, button))
+            .with_child(label("ポーズ"));
+    });
+}
+
+// 画面のボタンの入力を PointerInput へ。項目にマウスを乗せるとカーソルが移り、押す（タップする）と選ぶ
+fn command_menu_input_system(
+    items: Query<(&Interaction, &UiCommandItem), Changed<Interaction>>,
+    mut buttons: Query<(&Interaction, &UiInputButton, &mut BackgroundColor), Changed<Interaction>>,
+    mut pointer: ResMut<PointerInput>,
+    mut cursor: ResMut<CommandCursor>,
+) {
+    pointer.0.clear();
+    for (interaction, item) in &items {
+        if *interaction == Interaction::None {
+            continue;
+        }
+        if let Some(i) = CommandKind::ALL.iter().position(|&c| c == item.0) {
+            cursor.0 = i;
+        }
+        if *interaction == Interaction::Pressed {
+            pointer.0.push(InputAction::Command(item.0));
+        }
+    }
+    for (interaction, button, mut bg) in &mut buttons {
+        bg.0 = button_color(interaction);
+        if *interaction == Interaction::Pressed {
+            pointer.0.push(button.0);
+        }
+    }
+}
 
-    let Ok((mut eff_heal_text, mut eff_heal_color)) = ui_eff_heal_q.single_mut() else {
-        return;
-    };
-    eff_heal_text.0 = format!("回復 量:{} 消費:{}\n", heal_amount, heal_cost);
-    eff_heal_color.0 = if buffs.heal > 0 {
-        Color::from(LinearRgba {
-            red: 0.95,
-            green: 0.85,
-            blue: 0.35,
-            alpha: 1.0,
-        })
-    } else {
-        Color::WHITE
-    };
+// 一覧のカーソルと灰色表示（スタミナ・モメンタム不足、選択数の上限）、場面に合う操作ボタンだけ出す
+fn ui_update_command_menu_system(
+    phase: Res<BattlePhase>,
+    cursor: Res<CommandCursor>,
+    availability: Res<CommandAvailability>,
+    input: (Res<InputMap>, Res<PadInput>),
+    items: Query<(&UiCommandItem, &Children)>,
+    mut texts: Query<(&mut Text, &mut TextColor)>,
+    mut buttons: Query<(&UiInputButton, &mut Node)>,
+) {
+    let (input, pad) = input;
+    let selecting = *phase == BattlePhase::AwaitCommand;
+    for (i, (item, children)) in items.iter().enumerate() {
+        let i = CommandKind::ALL
+            .iter()
+            .position(|&c| c == item.0)
+            .unwrap_or(i);
+        let mark = if selecting && i == cursor.0 { ">" } else { " " };
+        let available = !selecting || availability.0.get(i).copied().unwrap_or(true);
+        for child in children.iter() {
+            if let Ok((mut text, mut color)) = texts.get_mut(child) {
+                text.0 = format!(
+                    "{mark}{} {}",
+                    item.0.label(),
+                    input.label(InputAction::Command(item.0), pad.connected)
+                );
+                color.0 = if available {
+                    Color::WHITE
+                } else {
+                    Color::srgb(0.45, 0.45, 0.45)
+                };
+            }
+        }
+    }
+    for (button, mut node) in &mut buttons {
+        let shown = match button.0.scenes() {
+            1 => selecting,
+            2 => !selecting,
+            _ => true,
+        };
+        node.display = if shown { Display::Flex } else { Display::None };
+    }
+}
 
-    let Ok((mut eff_def_text, mut eff_def_color)) = ui_eff_def_q.single_mut() else {
+// 小さい画面（スマートフォンのブラウザなど）では UI 全体を縮める。1280x720 が基準
+fn ui_scale_system(windows: Query<&Window, Changed<Window>>, mut scale: ResMut<UiScale>) {
+    let Ok(window) = windows.single() else {
         return;
     };
-    eff_def_text.0 = format!("防御 消費:{}\n\n", def_cost);
-    eff_def_color.0 = if buffs.defend > 0 {
-        Color::from(LinearRgba {
-            red: 0.95,
-            green: 0.85,
-            blue: 0.35,
-            alpha: 1.0,
-        })
-    } else {
-        Color::WHITE
-    };
+    let fit = (window.width() / 1280.0)
+        .min(window.height() / 720.0)
+        .min(1.0);
+    if fit > 0.0 && scale.0 != fit {
+        scale.0 = fit;
+    }
+}
 
-    let enemy_action_str = if let Some(step) = planned.0.current_step() {
-        step.name
-    } else {
-        "不明"
-    };
-    // 選択中コマンド表示用の文字列
-    let selected_str = if pending.0.is_empty() {
-        "(なし)".to_string()
+// ================== Batch Preview ==================
+// 選択中のバッチの予測と、続けて選べるコマンドを実際のルールで試し打ちして求める（状態は変えない）。
+// 選択・フェーズ・戦闘の状態が変わったフレームだけ計算し、結果は BatchPreview と CommandAvailability に残す
+fn batch_forecast_system(world: &mut World) {
+    let pending = world.resource::<PendingSelections>().0.clone();
+    let state = if *world.resource::<BattlePhase>() == BattlePhase::AwaitCommand {
+        capture_battle_state(world)
     } else {
-        pending
-            .0
-            .iter()
-            .map(|c| match c {
-                CommandKind::Attack => "攻撃",
-                CommandKind::Skill => "強攻撃",
-                CommandKind::Heal => "回復",
-                CommandKind::Defend => "防御",
-                CommandKind::Wait => "待機",
-                CommandKind::EnhanceAttack => "攻撃強化",
-                CommandKind::EnhanceSkill => "強攻撃強化",
-                CommandKind::EnhanceHeal => "回復強化",
-                CommandKind::EnhanceDefend => "防御強化",
-            })
-            .collect::<Vec<_>>()
-            .join(", ")
+        None
     };
-    let phase_str = match *phase {
-        BattlePhase::AwaitCommand => format!(
-            "コマンド入力待ち \nコマンドを選択してください(最大3つ)\n A=攻撃 S=強攻撃 H=回復 D=防御 W=待機\n Z=攻撃強化 / X=強攻撃強化 / C=回復強化 / V=防御強化\n Backspace=直前取り消し / Esc=全クリア\n Enter=決定\n [選択中] {selected_str}"
-        ),
-        BattlePhase::ConfirmQueued => {
-            let next_name = if let Some(next) = queue.0.front() {
-                match next {
-                    CommandKind::Attack => "攻撃",
-                    CommandKind::Skill => "強攻撃",
-                    CommandKind::Heal => "回復",
-                    CommandKind::Defend => "防御",
-                    CommandKind::Wait => "待機",
-                    CommandKind::EnhanceAttack => "攻撃強化",
-                    CommandKind::EnhanceSkill => "強攻撃強化",
-                    CommandKind::EnhanceHeal => "回復強化",
-                    CommandKind::EnhanceDefend => "防御強化",
-                }
+    let (steps, available) = match state {
+        Some(state) => (
+            if pending.is_empty() {
+                Vec::new()
             } else {
-                "(なし)"
-            };
-            format!(
-                "連続コマンド確認\n次の予約: {}\n Y=実行 / N=選択しなおし(以降リセット)",
-                next_name
-            )
-        }
-        BattlePhase::InBattle => "処理中".to_string(),
-        BattlePhase::Finished => "終了".to_string(),
+                state.preview_batch(&pending)
+            },
+            CommandKind::ALL
+                .iter()
+                .map(|&cmd| state.can_afford_next(&pending, cmd))
+                .collect(),
+        ),
+        None => (Vec::new(), Vec::new()),
     };
-    ui_phase_text.0 = format!("フェーズ: {phase_str}\n\n");
+    world.resource_mut::<BatchPreview>().0 = steps;
+    world.resource_mut::<CommandAvailability>().0 = available;
+}
 
-    let mut log_text = String::from("ログ:\n");
-    let log_max_lines = 30;
-    let start = if log.0.len() > log_max_lines {
-        log.0.len() - log_max_lines
-    } else {
-        0
-    };
-    for line in &log.0[start..] {
-        log_text.push_str(line);
-        log_text.push('\n');
-    }
-    ui_log_text.0 = log_text;
+// 予測に使うものが前回から変わったか（戦闘のリソースが無い間は false）
+fn forecast_inputs_changed(
+    If(selection): If<(Res<PendingSelections>, Res<BattlePhase>, Res<Turn>)>,
+    If(gauges): If<(
+        Res<Momentum>,
+        Res<Overdrive>,
+        Res<CommandBuffs>,
+        Res<ConsecutiveBatch>,
+    )>,
+    If(rules): If<(
+        Res<EnemyPlannedAction>,
+        Res<DefendNextAttack>,
+        Res<GuardCounterReady>,
+        Res<PlayerComboState>,
+        Res<Difficulty>,
+    )>,
+    units: Query<
+        (),
+        Or<(
+            Changed<Hp>,
+            Changed<Stamina>,
+            Changed<BatchLimit>,
+            Changed<Attack>,
+            Changed<BreakValue>,
+            Changed<BreakState>,
+            Changed<BreakRegen>,
+        )>,
+    >,
+) -> bool {
+    let (pending, phase, turn) = selection;
+    let (momentum, overdrive, buffs, batch) = gauges;
+    let (planned, defend, guard, combo, difficulty) = rules;
+    pending.is_changed()
+        || phase.is_changed()
+        || turn.is_changed()
+        || momentum.is_changed()
+        || overdrive.is_changed()
+        || buffs.is_changed()
+        || batch.is_changed()
+        || planned.is_changed()
+        || defend.is_changed()
+        || guard.is_changed()
+        || combo.is_changed()
+        || difficulty.is_changed()
+        || !units.is_empty()
 }
 
-// コマンド入力表示（右端パネル）の表示制御と内容更新
-fn ui_update_command_system(
-    phase: Res<BattlePhase>,
-    planned: Res<EnemyPlannedAction>,
-    pending: Res<PendingSelections>,
-    queue: Res<CommandQueue>,
-    mut cmd_panel_q: Query<(&mut Visibility, &Children), With<UiCommand>>,
+fn ui_update_batch_preview_system(
+    preview: Res<BatchPreview>,
+    mut panel_q: Query<(&mut Visibility, &Children), With<UiBatchPreview>>,
     mut texts: Query<&mut Text>,
 ) {
-    let Ok((mut vis, children)) = cmd_panel_q.single_mut() else {
+    let Ok((mut vis, children)) = panel_q.single_mut() else {
         return;
     };
-    match *phase {
-        BattlePhase::AwaitCommand => {
-            *vis = Visibility::Visible;
-            for child in children.iter() {
-                if let Ok(mut t) = texts.get_mut(child) {
-                    let selected_str = if pending.0.is_empty() {
-                        "(なし)".to_string()
-                    } else {
-                        pending
-                            .0
-                            .iter()
-                            .map(|c| match c {
-                                CommandKind::Attack => "攻撃",
-                                CommandKind::Skill => "強攻撃",
-                                CommandKind::Heal => "回復",
-                                CommandKind::Defend => "防御",
-                                CommandKind::Wait => "待機",
-                                CommandKind::EnhanceAttack => "攻撃強化",
-                                CommandKind::EnhanceSkill => "強攻撃強化",
-                                CommandKind::EnhanceHeal => "回復強化",
-                                CommandKind::EnhanceDefend => "防御強化",
-                            })
-                            .collect::<Vec<_>>()
-                            .join(", ")
-                    };
-                    t.0 = format!(
-                        "[コマンド入力] \nA=攻撃 S=強攻撃 H=回復 D=防御 W=待機\nZ=攻撃強化 X=強攻撃強化 C=回復強化 V=防御強化\nBackspace=直前取り消し Esc=全クリア Enter=決定 \n選択中: {selected_str}"
-                    );
-                }
-            }
+    if preview.0.is_empty() {
+        *vis = Visibility::Hidden;
+        return;
+    }
+    *vis = Visibility::Visible;
+    let mut lines = vec!["[予測] このバッチを決定すると".to_string()];
+    let mut notes = Vec::new();
+    for (i, step) in preview.0.iter().enumerate() {
+        let mut line = format!(
+            "{}. {}  スタミナ→{}  ダメージ{}  ブレイク+{}",
+            i + 1,
+            step.command.label(),
+            step.stamina_after,
+            step.damage,
+            step.break_gain
+        );
+        if step.break_triggered {
+            line.push_str("  ブレイク発生!");
         }
-        BattlePhase::ConfirmQueued => {
-            *vis = Visibility::Visible;
-            for child in children.iter() {
-                if let Ok(mut t) = texts.get_mut(child) {
-                    let next_name = if let Some(next) = queue.0.front() {
-                        match next {
-                            CommandKind::Attack => "攻撃",
-                            CommandKind::Skill => "強攻撃",
-                            CommandKind::Heal => "回復",
-                            CommandKind::Defend => "防御",
-                            CommandKind::Wait => "待機",
-                            CommandKind::EnhanceAttack => "攻撃強化",
-                            CommandKind::EnhanceSkill => "強攻撃強化",
-                            CommandKind::EnhanceHeal => "回復強化",
-                            CommandKind::EnhanceDefend => "防御強化",
-                        }
-                    } else {
-                        "(なし)"
-                    };
-                    t.0 = format!(
-                        "[連続コマンド確認]\n次の予約: {}\nY=実行 / N=選び直し(以降の予約はリセット)",
-                        next_name
-                    );
-                }
-            }
+        lines.push(line);
+        if let Some(warning) = step.warning {
+            notes.push(format!("! {}つ目は{warning}", i + 1));
         }
-        _ => {
-            *vis = Visibility::Hidden;
+        if step.enemy_defeated {
+            notes.push(format!("{}つ目で敵を倒せます", i + 1));
+        }
+    }
+    let damage: i32 = preview.0.iter().map(|s| s.damage).sum();
+    let break_gain: i32 = preview.0.iter().map(|s| s.break_gain).sum();
+    lines.push(format!("合計 ダメージ{damage}  ブレイク+{break_gain}"));
+    lines.extend(notes);
+    for child in children.iter() {
+        if let Ok(mut t) = texts.get_mut(child) {
+            t.0 = lines.join("\n");
         }
     }
 }
@@ -2651,11 +4210,73 @@ fn ui_update_player_status_system(
     }
 }
 
+// 右上プレイヤーステータスのコンボ表示（現在のコンボと成立間近の名前付きコンボ）
+fn ui_update_combo_system(
+    combo: Res<PlayerComboState>,
+    mut combo_text_q: Query<&mut Text, With<UiComboText>>,
+) {
+    let Ok(mut combo_text) = combo_text_q.single_mut() else {
+        return;
+    };
+    let current = combo.current();
+    if current.is_empty() {
+        combo_text.0 = "コンボ: なし".to_string();
+        return;
+    }
+    let chain = current
+        .iter()
+        .map(|c| c.label())
+        .collect::<Vec<_>>()
+        .join("→");
+    combo_text.0 = if let Some((pattern, rest)) = combo.in_progress() {
+        let rest = rest.iter().map(|c| c.label()).collect::<Vec<_>>().join("→");
+        format!("コンボ: {chain}\n  {}まで: {rest}", pattern.name)
+    } else {
+        format!("コンボ: {chain}")
+    };
+}
+
+// 右上プレイヤーステータスのオーバードライブ表示（テキスト＆ゲージ）
+fn ui_update_overdrive_system(
+    overdrive: Res<Overdrive>,
+    mut od_text_q: Query<(&mut Text, &mut TextColor), With<UiOverdriveText>>,
+    mut od_gauge_q: Query<&mut Node, With<UiOverdriveGaugeFill>>,
+) {
+    if let Ok((mut text, mut color)) = od_text_q.single_mut() {
+        text.0 = if overdrive.active {
+            "オーバードライブ: 発動中".to_string()
+        } else if overdrive.is_ready() {
+            "オーバードライブ: 準備完了".to_string()
+        } else {
+            format!("オーバードライブ: {} / {}", overdrive.gauge, OVERDRIVE_MAX)
+        };
+        color.0 = if overdrive.active || overdrive.is_ready() {
+            Color::from(LinearRgba {
+                red: 0.95,
+                green: 0.55,
+                blue: 0.15,
+                alpha: 1.0,
+            })
+        } else {
+            Color::WHITE
+        };
+    }
+    if let Ok(mut node) = od_gauge_q.single_mut() {
+        let ratio = if overdrive.active {
+            1.0
+        } else {
+            (overdrive.gauge as f32 / OVERDRIVE_MAX as f32).clamp(0.0, 1.0)
+        };
+        node.width = percent((ratio * 100.0).round());
+    }
+}
+
 // （演出簡易版につきフェード等の更新システムは未実装）
 fn boss_slain_banner_system(
     time: Res<Time>,
     mut commands: Commands,
     mut banner_q: Query<(Entity, &mut BossSlainBanner, &Children)>,
+    backdrop_q: Query<&Children, With<BossSlainBackdrop>>,
     mut text_colors: Query<&mut TextColor, With<BossSlainText>>,
     mut backdrop_colors: ParamSet<(
         Query<&mut BackgroundColor, With<BossSlainBackdropRow>>,
@@ -2663,84 +4284,46 @@ fn boss_slain_banner_system(
     )>,
 ) {
     const FADE_IN: f32 = 0.5;
-    const HOLD: f32 = 3.0;
     const FADE_OUT: f32 = 1.0;
 
     for (entity, mut banner, children) in banner_q.iter_mut() {
         banner.elapsed += time.delta().as_secs_f32();
-        match banner.phase {
+        let phase_alpha = match banner.phase {
             BannerPhase::FadeIn => {
-                let phase_alpha = (banner.elapsed / FADE_IN).clamp(0.0, 1.0);
-                for mut c in text_colors.iter_mut() {
-                    c.0 = Color::from(LinearRgba {
-                        red: 0.83,
-                        green: 0.72,
-                        blue: 0.20,
-                        alpha: phase_alpha,
-                    });
-                }
-                for mut bc in backdrop_colors.p1().iter_mut() {
-                    bc.0 = Color::from(LinearRgba {
-                        red: 0.0,
-                        green: 0.0,
-                        blue: 0.0,
-                        alpha: phase_alpha,
-                    });
-                }
-                for mut br in backdrop_colors.p0().iter_mut() {
-                    br.0 = Color::from(LinearRgba {
-                        red: 0.0,
-                        green: 0.0,
-                        blue: 0.0,
-                        alpha: 0.9 * phase_alpha,
-                    });
-                }
+                let a = (banner.elapsed / FADE_IN).clamp(0.0, 1.0);
                 if banner.elapsed >= FADE_IN {
                     banner.phase = BannerPhase::Hold;
                     banner.elapsed = 0.0;
                 }
+                a
             }
             BannerPhase::Hold => {
-                for mut c in text_colors.iter_mut() {
-                    c.0 = Color::from(LinearRgba {
-                        red: 0.83,
-                        green: 0.72,
-                        blue: 0.20,
-                        alpha: 1.0,
-                    });
-                }
-                for mut bc in backdrop_colors.p1().iter_mut() {
-                    bc.0 = Color::from(LinearRgba {
-                        red: 0.0,
-                        green: 0.0,
-                        blue: 0.0,
-                        alpha: 1.0,
-                    });
-                }
-                for mut br in backdrop_colors.p0().iter_mut() {
-                    br.0 = Color::from(LinearRgba {
-                        red: 0.0,
-                        green: 0.0,
-                        blue: 0.0,
-                        alpha: 0.9,
-                    });
-                }
-                if banner.elapsed >= HOLD {
+                if banner.elapsed >= banner.hold {
                     banner.phase = BannerPhase::FadeOut;
                     banner.elapsed = 0.0;
                 }
+                1.0
             }
             BannerPhase::FadeOut => {
-                let phase_alpha = 1.0 - (banner.elapsed / FADE_OUT).clamp(0.0, 1.0);
-                for mut c in text_colors.iter_mut() {
-                    c.0 = Color::from(LinearRgba {
-                        red: 0.83,
-                        green: 0.72,
-                        blue: 0.20,
-                        alpha: phase_alpha,
-                    });
+                if banner.elapsed >= FADE_OUT {
+                    // 完了後削除
+                    commands.entity(entity).despawn();
+                    continue;
                 }
-                for mut bc in backdrop_colors.p1().iter_mut() {
+                1.0 - (banner.elapsed / FADE_OUT).clamp(0.0, 1.0)
+            }
+        };
+
+        // このバナー配下の文字と黒帯だけを更新（複数バナーが同時に存在しても干渉しない）
+        for child in children.iter() {
+            if let Ok(mut c) = text_colors.get_mut(child) {
+                c.0 = Color::from(banner.text_color.with_alpha(phase_alpha));
+            }
+            let Ok(rows) = backdrop_q.get(child) else {
+                continue;
+            };
+            for row in rows.iter() {
+                if let Ok(mut bc) = backdrop_colors.p1().get_mut(row) {
                     bc.0 = Color::from(LinearRgba {
                         red: 0.0,
                         green: 0.0,
@@ -2748,7 +4331,7 @@ fn boss_slain_banner_system(
                         alpha: phase_alpha,
                     });
                 }
-                for mut br in backdrop_colors.p0().iter_mut() {
+                if let Ok(mut br) = backdrop_colors.p0().get_mut(row) {
                     br.0 = Color::from(LinearRgba {
                         red: 0.0,
                         green: 0.0,
@@ -2756,19 +4339,41 @@ fn boss_slain_banner_system(
                         alpha: 0.9 * phase_alpha,
                     });
                 }
-                if banner.elapsed >= FADE_OUT {
-                    // 完了後削除
-                    for i in 0..children.len() {
-                        let child = children[i];
-                        commands.entity(child).despawn();
-                    }
-                    commands.entity(entity).despawn();
-                }
             }
         }
     }
 }
 
+// 奥義発動時のバナー生成（DRAGON SLAIN と同じフェード演出を短めに使う）
+fn finisher_banner_system(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut cinematic: ResMut<FinisherCinematic>,
+) {
+    if !cinematic.0 {
+        return;
+    }
+    cinematic.0 = false;
+    let font = asset_server.load("fonts/x12y16pxMaruMonica.ttf");
+    spawn_cinematic_banner(
+        &mut commands,
+        BossSlainBanner {
+            elapsed: 0.0,
+            phase: BannerPhase::FadeIn,
+            hold: 0.8,
+            text_color: LinearRgba {
+                red: 0.95,
+                green: 0.40,
+                blue: 0.20,
+                alpha: 1.0,
+            },
+        },
+        "奥義・竜断ち",
+        font,
+        72.0,
+    );
+}
+
 // 強攻撃の有効値表示（ガードカウンターの反映もここで実施）
 fn ui_update_skill_effect_system(
     buffs: Res<CommandBuffs>,
@@ -2825,20 +4430,35 @@ fn ui_update_skill_effect_system(
     };
 }
 
-// 敵UI（中央配置）の更新（HP/ブレイクのゲージ幅、ブレイク中表示、次の行動）
+// 敵UI（中央配置）の更新（HP/ブレイクのゲージ幅、ブレイク中表示、次の行動、予告パネル）
 fn ui_update_enemy_system(
-    enemy_q: Query<(&Hp, &BreakValue, &BreakState), With<Enemy>>,
+    enemy_q: Query<(&Hp, &Attack, &BreakValue, &BreakState), With<Enemy>>,
     planned: Res<EnemyPlannedAction>,
+    view: (Res<Difficulty>, Res<DefendNextAttack>),
     mut gauge_params: ParamSet<(
         Query<&mut Node, With<UiEnemyHpGaugeFill>>,
         Query<&mut Node, With<UiEnemyBreakGaugeFill>>,
     )>,
-    mut br_label_q: Query<&mut Visibility, With<UiEnemyBreakLabel>>,
-    mut next_text_q: Query<&mut Text, With<UiEnemyNextActionText>>,
+    mut vis_params: ParamSet<(
+        Query<&mut Visibility, With<UiEnemy>>,
+        Query<&mut Visibility, With<UiEnemyBreakLabel>>,
+    )>,
+    mut next_text_q: Query<&mut Text, (With<UiEnemyNextActionText>, Without<UiEnemyTelegraphText>)>,
+    mut telegraph_q: Query<&mut Text, (With<UiEnemyTelegraphText>, Without<UiEnemyNextActionText>)>,
 ) {
-    let Ok((e_hp, e_break, e_bstate)) = enemy_q.single() else {
+    let Ok((e_hp, e_attack, e_break, e_bstate)) = enemy_q.single() else {
         return;
     };
+    let (difficulty, defend) = (*view.0, &view.1);
+    // 倒した敵のUIは消す（HP表示などは一瞬で消す）
+    let defeated = e_hp.current <= 0;
+    if let Ok(mut vis) = vis_params.p0().single_mut() {
+        *vis = if defeated {
+            Visibility::Hidden
+        } else {
+            Visibility::Visible
+        };
+    }
 
     if let Ok(mut hp_node) = gauge_params.p0().single_mut() {
         let ratio = if e_hp.max > 0 {
@@ -2849,24 +4469,79 @@ fn ui_update_enemy_system(
         hp_node.width = percent((ratio * 100.0).round());
     }
     if let Ok(mut br_node) = gauge_params.p1().single_mut() {
-        let ratio = (e_break.current as f32 / 100.0).clamp(0.0, 1.0);
+        let ratio = if defeated {
+            0.0
+        } else {
+            (e_break.current as f32 / difficulty.break_threshold() as f32).clamp(0.0, 1.0)
+        };
         br_node.width = percent((ratio * 100.0).round());
     }
-    if let Ok(mut vis) = br_label_q.single_mut() {
-        *vis = if e_bstate.remaining_turns > 0 {
+    if let Ok(mut vis) = vis_params.p1().single_mut() {
+        *vis = if e_bstate.remaining_turns > 0 && !defeated {
             Visibility::Visible
         } else {
             Visibility::Hidden
         };
     }
     if let Ok(mut t) = next_text_q.single_mut() {
-        let enemy_action_str = if let Some(step) = planned.0.current_step() {
-            step.name
+        t.0 = if defeated {
+            String::new()
         } else {
-            "不明"
+            format!("次の行動: {}", planned.revealed_name(difficulty))
+        };
+    }
+    if let Ok(mut t) = telegraph_q.single_mut() {
+        t.0 = telegraph_text(&planned, e_attack, defend, e_bstate, difficulty);
+    }
+}
+
+// 予告パネルの文字列（現在の行動の残りステップ。先頭が次のターン）
+fn telegraph_text(
+    planned: &EnemyPlannedAction,
+    e_attack: &Attack,
+    defend: &DefendNextAttack,
+    e_bstate: &BreakState,
+    difficulty: Difficulty,
+) -> String {
+    if difficulty.revealed_steps() == 0 {
+        return "（この難易度では行動予定は見えない）".to_string();
+    }
+    let (steps, hidden) = enemy_telegraph(planned, e_attack, defend, difficulty);
+    let mut lines = vec![format!(
+        "[{}] 残り{}ステップ",
+        planned.revealed_action_name(difficulty),
+        steps.len() + hidden
+    )];
+    if e_bstate.remaining_turns > 0 {
+        lines.push(format!(
+            "ブレイク中: あと{}ターン行動しない",
+            e_bstate.remaining_turns
+        ));
+    }
+    for (i, t) in steps.iter().enumerate() {
+        let mark = if i == 0 { ">" } else { " " };
+        let detail = match t.step.specification {
+            ActionStepSpecificationEnum::Attack(spec) => {
+                let damage = t.damage.unwrap_or_default();
+                if t.guarded {
+                    format!("威力{:.1} → {damage}ダメージ (防御で無効)", spec.power)
+                } else {
+                    format!("威力{:.1} → {damage}ダメージ", spec.power)
+                }
+            }
+            ActionStepSpecificationEnum::Wait(_) => "待機".to_string(),
+            // このターンに攻撃すると回復量は半減する
+            ActionStepSpecificationEnum::Heal(spec) if i == 0 => {
+                format!("HP+{} (攻撃すると半減)", spec.amount)
+            }
+            ActionStepSpecificationEnum::Heal(spec) => format!("HP+{}", spec.amount),
         };
-        t.0 = format!("次の行動: {}", enemy_action_str);
+        lines.push(format!("{mark} {}  {detail}", t.step.name));
+    }
+    if hidden > 0 {
+        lines.push(format!("  ……あと{hidden}ステップは不明"));
     }
+    lines.join("\n")
 }
 
 // 敵ダメージの一時表示更新（一定時間で非表示に戻す）
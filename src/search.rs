@@ -0,0 +1,195 @@
+// ================== Search Player ==================
+// バランス確認用の自動プレイヤー。BattleState をコピーして実際の戦闘ルールで先読みし、次のバッチを決める（expectimax）。
+//
+// 決定ノード: 次に確定するバッチ（長さ 1〜max_len の全コマンド列）。期待値が最大のものを選ぶ
+// 確率ノード: 敵が次の行動を選ぶ時点。pick_enemy_action と同じ重み（enemy_action_odds）で全候補に分岐し、確率で重み付けする
+// 葉: 戦闘終了なら勝敗とターン数、途中なら evaluate() の評価値
+//
+// 伏せられた行動予定（ナイトメア）は preview_batch と同じく「？？？」（何もしない）として扱い、探索からも見えないようにする
+use std::sync::Arc;
+
+use crate::battle::{
+    ActionProcess, BattleState, CommandKind, EnemyMoveChoice, EnemyPlannedAction,
+    enemy_action_odds, enemy_action_table, unknown_enemy_action,
+};
+
+// 勝敗がついた葉の評価値（ターン数で差をつける）
+const WIN_SCORE: f64 = 1_000_000.0;
+
+#[derive(Clone, Copy, Debug)]
+pub struct SearchPlayer {
+    pub depth: u32,     // 先読みするバッチ数（1=次のバッチだけ）
+    pub max_len: usize, // 1段目で試すバッチの最大長（2段目以降は1件だけ）
+}
+
+impl Default for SearchPlayer {
+    fn default() -> Self {
+        SearchPlayer {
+            depth: 1,
+            max_len: 3,
+        }
+    }
+}
+
+impl SearchPlayer {
+    // 次に確定するバッチを返す
+    pub fn plan(&self, state: &BattleState) -> Vec<CommandKind> {
+        let mut root = state.clone();
+        root.planned = state.planned.revealed(state.difficulty);
+        self.search(&root, self.depth, self.max_len)
+            .map(|(batch, _)| batch)
+            .unwrap_or_else(|| vec![CommandKind::Wait])
+    }
+
+    // 決定ノード: (最善バッチ, 期待値)
+    fn search(
+        &self,
+        state: &BattleState,
+        depth: u32,
+        max_len: usize,
+    ) -> Option<(Vec<CommandKind>, f64)> {
+        let limit = state.max_select().min(max_len);
+        let mut best: Option<(Vec<CommandKind>, f64)> = None;
+        let mut frontier: Vec<Vec<CommandKind>> = vec![Vec::new()];
+        for _ in 0..limit {
+            let mut next = Vec::new();
+            for prefix in &frontier {
+                for cmd in CommandKind::ALL {
+                    let mut batch = prefix.clone();
+                    batch.push(cmd);
+                    let value = self.expected_value(state, &batch, 0, depth);
+                    if best.as_ref().is_none_or(|(_, v)| value > *v) {
+                        best = Some((batch.clone(), value));
+                    }
+                    next.push(batch);
+                }
+            }
+            frontier = next;
+        }
+        best
+    }
+
+    // バッチを from 件目から1件ずつ実行する。敵が次の行動を選んだ時点が確率ノードで、
+    // その時点の状態を候補ごとに複製し、行動予定を差し替えて残りを続ける
+    fn expected_value(
+        &self,
+        state: &BattleState,
+        batch: &[CommandKind],
+        from: usize,
+        depth: u32,
+    ) -> f64 {
+        let mut sim = state.clone();
+        for i in from..batch.len() {
+            // 行動を選ぶ時点を見つけるため、2Pの予約がなければ仮の行動を置いておく
+            let placeholder = sim.enemy_choice.0.is_none();
+            if placeholder {
+                sim.enemy_choice = EnemyMoveChoice(Some(unknown_enemy_action(1)));
+            }
+            let go_on = sim.run_batch_step(batch, i);
+            let chosen = placeholder && sim.enemy_choice.0.is_none();
+            if placeholder {
+                sim.enemy_choice = EnemyMoveChoice::default();
+            }
+            // 選んだ行動がこの先に影響しないなら分岐しない
+            if chosen && go_on && (i + 1 < batch.len() || depth > 1) {
+                let odds =
+                    enemy_action_odds(enemy_action_table(&sim.e_hp), sim.difficulty.aggression());
+                return odds
+                    .into_iter()
+                    .map(|(p, action)| {
+                        let mut branch = sim.clone();
+                        branch.planned = EnemyPlannedAction(ActionProcess::from(&Arc::new(action)));
+                        p as f64 * self.expected_value(&branch, batch, i + 1, depth)
+                    })
+                    .sum();
+            }
+            if !go_on {
+                break;
+            }
+        }
+        if sim.is_over() || depth <= 1 {
+            evaluate(&sim)
+        } else {
+            // 2段目以降はバッチ長1に絞って先読み
+            self.search(&sim, depth - 1, 1)
+                .map_or_else(|| evaluate(&sim), |(_, v)| v)
+        }
+    }
+}
+
+// 戦況の評価値（大きいほどプレイヤー有利）
+pub fn evaluate(state: &BattleState) -> f64 {
+    let turn = state.turn.0 as f64;
+    let dealt = (state.e_hp.max - state.e_hp.current) as f64;
+    if state.player_won() {
+        return WIN_SCORE - turn * 100.0;
+    }
+    if state.p_hp.current <= 0 {
+        return -WIN_SCORE + dealt;
+    }
+    let buffs = [
+        state.buffs.attack,
+        state.buffs.skill,
+        state.buffs.heal,
+        state.buffs.defend,
+    ]
+    .iter()
+    .sum::<u32>() as f64;
+    dealt * 1.0
+        + state.p_hp.current as f64 * 3.0
+        + state.p_sta.current as f64 * 0.5
+        + state.momentum.current as f64 * 0.8
+        + state.overdrive.gauge as f64 * 0.3
+        + state
+            .e_break
+            .current
+            .min(state.difficulty.break_threshold()) as f64
+            * 100.0
+            / state.difficulty.break_threshold() as f64
+            * 0.6
+        + state.e_bstate.remaining_turns as f64 * 40.0
+        + buffs * 3.0
+        + if state.defend.0 { 15.0 } else { 0.0 }
+        + if state.guard.0 { 10.0 } else { 0.0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::battle::Difficulty;
+
+    #[test]
+    fn plan_finishes_low_hp_enemy() {
+        for difficulty in Difficulty::ALL {
+            let mut state = BattleState::with_difficulty(7, difficulty);
+            state.e_hp.current = 1;
+            let batch = SearchPlayer::default().plan(&state);
+            state.run_batch(&batch);
+            assert!(state.player_won(), "{difficulty:?}: {batch:?}");
+        }
+    }
+
+    #[test]
+    fn deeper_plan_finishes_low_hp_enemy() {
+        let mut state = BattleState::new(3);
+        state.e_hp.current = 1;
+        let player = SearchPlayer {
+            depth: 2,
+            max_len: 2,
+        };
+        let batch = player.plan(&state);
+        state.run_batch(&batch);
+        assert!(state.player_won(), "{batch:?}");
+    }
+
+    #[test]
+    fn evaluate_ranks_win_above_ongoing_above_loss() {
+        let ongoing = BattleState::new(1);
+        let mut won = ongoing.clone();
+        won.e_hp.current = 0;
+        let mut lost = ongoing.clone();
+        lost.p_hp.current = 0;
+        assert!(evaluate(&won) > evaluate(&ongoing));
+        assert!(evaluate(&ongoing) > evaluate(&lost));
+    }
+}